@@ -1,14 +1,33 @@
 use nom::IResult;
 use crate::resources::resources::Resources;
-use std::io::{self, Read};
-use std::fs::File;
+use std::io::{self, Read, Write};
 use crate::resources::resources::parse_resource_table;
 use std::io::Seek;
 use crate::zip::archive::ZipEntry;
 use crate::zip::archive::ZipArchive;
-use std::iter::Map;
+use crate::zip::archive::OpenOptions as ZipOpenOptions;
+use std::sync::Arc;
+use std::borrow::Cow;
 use crate::zip::archive::ZipIter;
-use crate::signature::get_key_fingerprint_sha256;
+use crate::zip::archive::Compression;
+use crate::zip::extra::ExtraFields;
+use crate::zip::time::DateTime;
+use crate::zip::audit::AuditFinding;
+use crate::zip::align::MisalignedEntry;
+use crate::signature::jar::{self, JarSigner};
+use crate::signature::certificate::Certificate;
+use crate::signature::block::SigningBlock;
+use crate::signature::error::SignatureError;
+use crate::signature::idsig::{self, V4Signature};
+use crate::signature::lineage::SigningCertificateLineage;
+use crate::signature::verify::{self, SchemeReport, SignatureReport};
+use crate::signature::sign::ApkSigner;
+use crate::signature::stamp::SourceStamp;
+use crate::signature::verity;
+use sha2::{Digest, Sha256};
+use crate::extract::ExtractOptions;
+use crate::axml;
+use std::path::Path;
 
 pub struct Apk {
     zip_archive: ZipArchive,
@@ -30,20 +49,129 @@ impl ApkFile {
         self.0.header.compressed_size as usize
     }
 
-    pub fn content(&self) -> io::Result<Box<Read>> {
+    pub fn compression(&self) -> Compression {
+        self.0.compression()
+    }
+
+    pub fn last_modified(&self) -> DateTime {
+        self.0.last_modified()
+    }
+
+    pub fn unix_mode(&self) -> Option<u32> {
+        self.0.unix_mode()
+    }
+
+    pub fn comment(&self) -> String {
+        self.0.comment()
+    }
+
+    pub fn extra_fields(&self) -> ExtraFields<'_> {
+        self.0.extra_fields()
+    }
+
+    pub fn content(&self) -> io::Result<Box<dyn Read + Send>> {
         self.0.content()
     }
+
+    /// The uncompressed content, borrowed without copying for stored entries of memory mapped
+    /// or in-memory APKs.
+    pub fn bytes(&self) -> io::Result<Cow<'_, [u8]>> {
+        self.0.bytes()
+    }
+
+    pub fn verify(&self) -> io::Result<()> {
+        self.0.verify()
+    }
+}
+
+/// Limits for opening untrusted APKs, see `zip::archive::OpenOptions` for their meaning. An
+/// APK whose `resources.arsc` exceeds them fails to open.
+///
+/// ```no_run
+/// use apk_rs::apk::OpenOptions;
+///
+/// let apk = OpenOptions::new()
+///     .max_total_uncompressed(512 * 1024 * 1024)
+///     .max_compression_ratio(100)
+///     .open("app.apk");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions(ZipOpenOptions);
+
+impl OpenOptions {
+    pub fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    pub fn max_entry_size(&mut self, max: u64) -> &mut OpenOptions {
+        self.0.max_entry_size(max);
+        self
+    }
+
+    pub fn max_total_uncompressed(&mut self, max: u64) -> &mut OpenOptions {
+        self.0.max_total_uncompressed(max);
+        self
+    }
+
+    pub fn max_compression_ratio(&mut self, max: u64) -> &mut OpenOptions {
+        self.0.max_compression_ratio(max);
+        self
+    }
+
+    pub fn max_entry_count(&mut self, max: usize) -> &mut OpenOptions {
+        self.0.max_entry_count(max);
+        self
+    }
+
+    pub fn max_central_directory_size(&mut self, max: u64) -> &mut OpenOptions {
+        self.0.max_central_directory_size(max);
+        self
+    }
+
+    pub fn open(&self, path: &str) -> io::Result<Apk> {
+        Apk::from_zip_archive(self.0.open(path)?)
+    }
+
+    pub fn open_mmap(&self, path: &str) -> io::Result<Apk> {
+        Apk::from_zip_archive(self.0.open_mmap(path)?)
+    }
+
+    pub fn from_bytes<D: Into<Arc<[u8]>>>(&self, data: D) -> io::Result<Apk> {
+        Apk::from_zip_archive(self.0.from_bytes(data)?)
+    }
+
+    pub fn from_reader<R: Read + Seek + Send + 'static>(&self, reader: R) -> io::Result<Apk> {
+        Apk::from_zip_archive(self.0.from_reader(reader)?)
+    }
 }
 
 impl Apk {
     pub fn open(path: &str) -> io::Result<Apk> {
-        let zip_archive = ZipArchive::open(path)?;
+        OpenOptions::new().open(path)
+    }
+
+    /// Memory maps the APK. `resources.arsc`, `AndroidManifest.xml` and other stored entries are
+    /// parsed directly from the mapping.
+    pub fn open_mmap(path: &str) -> io::Result<Apk> {
+        OpenOptions::new().open_mmap(path)
+    }
+
+    /// Opens an APK held in memory, e.g. `Apk::from_bytes(vec)` for a downloaded file.
+    pub fn from_bytes<D: Into<Arc<[u8]>>>(data: D) -> io::Result<Apk> {
+        OpenOptions::new().from_bytes(data)
+    }
+
+    /// Opens an APK from any seekable source. Entries are read lazily, so the source is kept
+    /// for the lifetime of the `Apk`.
+    pub fn from_reader<R: Read + Seek + Send + 'static>(reader: R) -> io::Result<Apk> {
+        OpenOptions::new().from_reader(reader)
+    }
+
+    fn from_zip_archive(zip_archive: ZipArchive) -> io::Result<Apk> {
         let mut resources = None;
         {
             if let Some(res_file) = zip_archive.by_name("resources.arsc")? {
-                let mut buf = Vec::with_capacity(res_file.len());
-                let mut reader = res_file.content()?;
-                reader.read_to_end(&mut buf)?;
+                let buf = res_file.bytes()?;
                 let r = parse_resource_table(&buf);
                 if let IResult::Done(_, r) = r {
                     resources = r;
@@ -57,6 +185,20 @@ impl Apk {
         })
     }
 
+    /// `AndroidManifest.xml` decoded from binary XML, references to resources of the package
+    /// are shown by name. A stored manifest is parsed directly from the mapping or buffer.
+    pub fn manifest_xml(&self) -> io::Result<Option<String>> {
+        let manifest = match self.zip_archive.by_name("AndroidManifest.xml")? {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
+        let data = manifest.bytes()?;
+        match axml::to_xml(&data, self.resources.as_ref()) {
+            Ok(xml) => Ok(Some(xml)),
+            Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "AndroidManifest.xml is not valid binary XML")),
+        }
+    }
+
     pub fn files(&self) -> ApkIter {
         ApkIter(self.zip_archive.files())
     }
@@ -68,23 +210,180 @@ impl Apk {
         Ok(None)
     }
 
+    /// Entries whose name starts with `prefix`, e.g. `files_under("lib/arm64-v8a/")`.
+    pub fn files_under<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = ApkFile> + 'a {
+        self.zip_archive.files_under(prefix).map(ApkFile)
+    }
+
+    /// Entries whose name matches a glob pattern like `res/**/*.xml`.
+    pub fn files_matching<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = ApkFile> + 'a {
+        self.zip_archive.files_matching(pattern).map(ApkFile)
+    }
+
+    /// Names that occur more than once. Android rejects such APKs, `file_by_name` returns the
+    /// first entry with the name.
+    pub fn duplicate_names(&self) -> &[String] {
+        self.zip_archive.duplicate_names()
+    }
+
+    /// Extracts all entries accepted by `filter` below `dir`, in parallel. Entry names are
+    /// sanitised so nothing is written outside of `dir`, see `ExtractOptions` for details and
+    /// for decoding binary XML.
+    pub fn extract_to<P, F>(&self, dir: P, filter: F) -> io::Result<usize>
+    where
+        P: AsRef<Path>,
+        F: Fn(&ApkFile) -> bool + Sync,
+    {
+        ExtractOptions::new().extract(self, dir, filter)
+    }
+
+    /// Extracts every entry and checks its CRC-32, the error of the first corrupted entry is
+    /// returned.
+    pub fn verify_all_crcs(&self) -> io::Result<()> {
+        for file in self.files() {
+            file.verify()?;
+        }
+        Ok(())
+    }
+
+    /// The comment of the ZIP archive.
+    pub fn comment(&self) -> String {
+        self.zip_archive.comment()
+    }
+
+    /// Starts the `max_total_uncompressed` budget of the APK over, see
+    /// `ZipArchive::reset_total_uncompressed`.
+    pub fn reset_total_uncompressed(&self) {
+        self.zip_archive.reset_total_uncompressed();
+    }
+
+    pub fn audit(&self) -> io::Result<Vec<AuditFinding>> {
+        self.zip_archive.audit()
+    }
+
+    /// Stored entries that are not aligned as Android requires, `page_size` applies to shared
+    /// libraries.
+    pub fn check_alignment(&self, page_size: u64) -> io::Result<Vec<MisalignedEntry>> {
+        self.zip_archive.check_alignment(page_size)
+    }
+
     pub fn get_resources(&self) -> Option<&Resources> {
         self.resources.as_ref()
     }
 
-    pub fn get_certificate_fingerprint_sha256(&self) -> io::Result<Vec<u8>> {
-        if let Some(cert_file) = self.file_by_name("META-INF/CERT.RSA")? {
-            let mut reader = cert_file.content()?;
-            let mut buf = Vec::with_capacity(cert_file.len());
-            reader.read_to_end(&mut buf)?;
-
-            let fingerprint = get_key_fingerprint_sha256(&buf);
-            if fingerprint.is_err() {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "unable to parse certificate"));
+    /// The APK signing block holding v2 and later signatures, `None` for APKs signed only with
+    /// JAR signing (v1) or not at all.
+    pub fn signing_block(&self) -> io::Result<Option<SigningBlock>> {
+        SigningBlock::read(&self.zip_archive)
+    }
+
+    /// The source stamp an app store added to the signing block, `None` for APKs without one,
+    /// like those repackaged after download. Its signatures are not verified.
+    pub fn source_stamp(&self) -> io::Result<Option<SourceStamp>> {
+        match self.signing_block()? {
+            Some(block) => block.source_stamp(),
+            None => Ok(None),
+        }
+    }
+
+    /// Verifies the JAR, v2, v3 and v3.1 signatures, the report lists the problems found per scheme
+    /// and signer. Schemes the APK is not signed with are reported as not signed.
+    pub fn verify_signatures(&self) -> io::Result<SignatureReport> {
+        verify::verify(&self.zip_archive)
+    }
+
+    /// Verifies the APK Signature Scheme v4 signature of an `.idsig` file against this APK:
+    /// the Merkle tree over the APK and that it was signed by the v3 or else v2 signer.
+    pub fn verify_v4_signature(&self, idsig: &[u8]) -> io::Result<SchemeReport> {
+        let signature = V4Signature::parse(idsig).ok_or(SignatureError::InvalidV4Signature)?;
+        idsig::verify(&self.zip_archive, &signature)
+    }
+
+    /// Signs the APK, replacing any existing signatures, and writes the signed APK to `out`.
+    pub fn sign<W: Write>(&self, signer: &ApkSigner, out: W) -> io::Result<()> {
+        signer.sign(&self.zip_archive, out)
+    }
+
+    /// The fs-verity style SHA-256 Merkle tree root over the whole APK with 4096 byte blocks and
+    /// no salt, as signed by v4 signatures.
+    pub fn verity_root_hash(&self) -> io::Result<Vec<u8>> {
+        Ok(verity::merkle_tree(self.zip_archive.raw_range(0, u64::MAX)?, b"")?.root_hash)
+    }
+
+    /// The JAR signers found in `META-INF/`, whatever their names and key algorithms. Their
+    /// certificates are not verified, see `verify_signatures`.
+    pub fn jar_signers(&self) -> io::Result<Vec<JarSigner>> {
+        jar::signers(&self.zip_archive)
+    }
+
+    /// The certificate chain of every signer, each starting with the certificate of the signer.
+    /// These are the JAR signers, or for APKs without JAR signature the v3 or else v2 signers.
+    /// Nothing is verified, see `verify_signatures`.
+    pub fn signer_certificates(&self) -> io::Result<Vec<Vec<Vec<u8>>>> {
+        let chains: Vec<_> = self.jar_signers()?.into_iter()
+            .map(|signer| signer.certificates)
+            .filter(|certificates| !certificates.is_empty())
+            .collect();
+        if !chains.is_empty() {
+            return Ok(chains);
+        }
+        if let Some(block) = self.signing_block()? {
+            let signers = match block.v3_signers()? {
+                Some(signers) => Some(signers),
+                None => block.v2_signers()?,
+            };
+            return Ok(signers.unwrap_or_default().into_iter()
+                .map(|signer| signer.signed_data.certificates)
+                .collect());
+        }
+        Ok(Vec::new())
+    }
+
+    /// The parsed certificate of every signer as ordered by `signer_certificates`, `None` for
+    /// signers without a certificate or with one that can not be parsed.
+    pub fn signing_certificates(&self) -> io::Result<Vec<Option<Certificate>>> {
+        Ok(self.signer_certificates()?.iter()
+            .map(|chain| chain.first().and_then(|certificate| Certificate::from_der(certificate).ok()))
+            .collect())
+    }
+
+    /// The proof-of-rotation of an APK whose signing key was rotated, taken from the v3.1 or
+    /// else v3 signer. Not verified, see `verify_signatures`.
+    pub fn signing_certificate_lineage(&self) -> io::Result<Option<SigningCertificateLineage>> {
+        let block = match self.signing_block()? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        for signers in [block.v31_signers()?, block.v3_signers()?].iter().flatten() {
+            if let Some(lineage) = signers.iter().find_map(|signer| signer.lineage()) {
+                return Ok(Some(lineage));
             }
-            return Ok(fingerprint.unwrap());
         }
-        Err(io::Error::new(io::ErrorKind::NotFound, "CERT.RSA not found"))
+        Ok(None)
+    }
+
+    /// The DER encoded certificate devices on the SDK version see as the signer: that of the
+    /// v3.1 or v3 signer covering the version, else of the v2 or JAR signer. Nothing is
+    /// verified, see `verify_signatures`.
+    pub fn signing_certificate_for_sdk(&self, sdk: u32) -> io::Result<Option<Vec<u8>>> {
+        if let Some(block) = self.signing_block()? {
+            if let Some(signer) = block.signer_for_sdk(sdk)? {
+                return Ok(signer.signed_data.certificates.into_iter().next());
+            }
+            if let Some(signers) = block.v2_signers()? {
+                return Ok(signers.into_iter().next().and_then(|signer| signer.signed_data.certificates.into_iter().next()));
+            }
+        }
+        Ok(self.jar_signers()?.into_iter().next().and_then(|signer| signer.certificates.into_iter().next()))
+    }
+
+    /// SHA-256 of the certificate of the first signer as ordered by `signer_certificates`.
+    pub fn get_certificate_fingerprint_sha256(&self) -> io::Result<Vec<u8>> {
+        let chains = self.signer_certificates()?;
+        match chains.first().and_then(|chain| chain.first()) {
+            Some(certificate) => Ok(Sha256::digest(certificate).to_vec()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no signing certificate found")),
+        }
     }
 }
 
@@ -99,4 +398,48 @@ impl Iterator for ApkIter {
         }
         None
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_apk_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Apk>();
+    assert_send_sync::<ApkFile>();
+    assert_send_sync::<ApkIter>();
+}
+
+#[test]
+fn test_manifest_xml() {
+    use crate::zip::writer::ZipWriter;
+
+    let mut writer = ZipWriter::new(Vec::new());
+    writer.add_stored("AndroidManifest.xml", &axml::build_test_xml()).unwrap();
+    let data = writer.finish().unwrap();
+    let path = std::env::temp_dir().join(format!("apk-rs-test-manifest-{}.apk", std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+    let apk = Apk::open_mmap(path.to_str().unwrap()).unwrap();
+    let manifest = apk.manifest_xml().unwrap().unwrap();
+    assert!(manifest.contains("<manifest xmlns:android=\"http://schemas.android.com/apk/res/android\" package=\"com.example\""));
+    std::fs::remove_file(&path).unwrap();
+
+    let mut writer = ZipWriter::new(Vec::new());
+    writer.add_deflated("classes.dex", b"dex").unwrap();
+    assert!(Apk::from_bytes(writer.finish().unwrap()).unwrap().manifest_xml().unwrap().is_none());
+}
+
+#[test]
+fn test_signing_certificates() {
+    let certificate = include_bytes!("../testdata/rsa.der");
+    let mut data = include_bytes!("../testdata/v2v3-rsa.apk").to_vec();
+    let certificates = Apk::from_bytes(data.clone()).unwrap().signing_certificates().unwrap();
+    assert_eq!(certificates.len(), 1);
+    assert!(certificates[0].as_ref().unwrap().is_debug_certificate());
+
+    // a certificate that can not be parsed does not hide the others
+    while let Some(position) = data.windows(certificate.len()).position(|w| w == certificate) {
+        // the certificate claims to be longer than it is
+        data[position + 2] = 0xff;
+    }
+    let certificates = Apk::from_bytes(data).unwrap().signing_certificates().unwrap();
+    assert_eq!(certificates.len(), 1);
+    assert!(certificates[0].is_none());
+}
@@ -1,10 +1,88 @@
 use crate::chunk::*;
 use crate::stringpool::{parse_string_pool_chunk, StringPool};
 use crate::typedvalue::TypedValue;
+use crate::resources::resources::{is_package_reference, Resources};
 use nom::IResult;
 
 pub fn is_binary_xml(data: &[u8]) -> bool {
-    data[0] == 0x03 && data[1] == 0x00
+    data.starts_with(&[0x03, 0x00])
+}
+
+/// Converts binary XML to its textual form. References to resources of the package are shown
+/// by name (e.g. `@string/app_name`) when `resources` is given.
+pub fn to_xml(data: &[u8], resources: Option<&Resources>) -> Result<String, ParseError> {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    // (prefix, uri) of all namespaces in scope, the ones not yet declared on an element last
+    let mut namespaces: Vec<(String, String)> = Vec::new();
+    let mut undeclared = 0;
+    let mut depth = 0;
+    let mut open_tag = false;
+
+    for event in XmlElementStream::new(data)? {
+        // the start tag is completed by the next event, elements without content are closed
+        // right away
+        let closed = open_tag && matches!(event, XmlEvent::ElementEnd(_));
+        if open_tag {
+            xml.push_str(if closed { " />\n" } else { ">\n" });
+        }
+        open_tag = matches!(event, XmlEvent::ElementStart(_));
+        match event {
+            XmlEvent::NamespaceStart(ns) => {
+                namespaces.push((ns.prefix, ns.uri));
+                undeclared += 1;
+            }
+            XmlEvent::NamespaceEnd(_) => {
+                namespaces.pop();
+                undeclared = undeclared.min(namespaces.len());
+            }
+            XmlEvent::ElementStart(e) => {
+                xml.push_str(&"  ".repeat(depth));
+                xml.push('<');
+                xml.push_str(&qualified_name(&namespaces, e.ns.as_deref(), &e.name));
+                for (prefix, uri) in &namespaces[namespaces.len() - undeclared..] {
+                    xml.push_str(&format!(" xmlns:{}=\"{}\"", prefix, escape(uri)));
+                }
+                undeclared = 0;
+                for a in e.attributes.unwrap_or_default() {
+                    let value = match (&a.value, resources) {
+                        (TypedValue::Reference(r), Some(resources)) if is_package_reference(*r) => {
+                            resources.get_human_reference(*r).unwrap_or_else(|| a.value.to_string())
+                        }
+                        _ => a.value.to_string(),
+                    };
+                    let name = qualified_name(&namespaces, a.ns.as_deref(), &a.name);
+                    xml.push_str(&format!(" {}=\"{}\"", name, escape(&value)));
+                }
+                depth += 1;
+            }
+            XmlEvent::ElementEnd(e) => {
+                depth = depth.saturating_sub(1);
+                if !closed {
+                    xml.push_str(&"  ".repeat(depth));
+                    xml.push_str(&format!("</{}>\n", qualified_name(&namespaces, e.ns.as_deref(), &e.name)));
+                }
+            }
+            XmlEvent::CData(c) => {
+                xml.push_str(&"  ".repeat(depth));
+                xml.push_str(&escape(&c.data));
+                xml.push('\n');
+            }
+        }
+    }
+
+    Ok(xml)
+}
+
+fn qualified_name(namespaces: &[(String, String)], ns: Option<&str>, name: &str) -> String {
+    let prefix = ns.and_then(|ns| namespaces.iter().rev().find(|(_, uri)| uri == ns));
+    match prefix {
+        Some((prefix, _)) => format!("{}:{}", prefix, name),
+        None => name.to_string(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
 }
 
 pub struct XmlElementStream<'a> {
@@ -17,6 +95,9 @@ impl<'a> XmlElementStream<'a> {
     pub fn new(data: &'a [u8]) -> Result<Self, ParseError> {
         if let IResult::Done(_, r) = parse_chunk(&data) {
             if let IResult::Done(_, s) = r.get_sub_chunks() {
+                if s.len() < 2 {
+                    return Err(ParseError::WrongChunkType);
+                }
                 if let Ok(string_pool) = parse_string_pool_chunk(&s[0]) {
                     //TODO: actually handle res chunks
                     let index = if s[1].typ == 0x180 { 2 } else { 1 };
@@ -99,6 +180,7 @@ impl<'a> Iterator for XmlElementStream<'a> {
     }
 }
 
+#[derive(Debug)]
 pub enum ParseError {
     WrongChunkType,
 }
@@ -224,3 +306,67 @@ impl CData {
         }
     }
 }
+
+/// `<manifest package="com.example" android:versionCode="1"><application /></manifest>` in
+/// binary XML, with the android namespace declared on the root element.
+#[cfg(test)]
+pub(crate) fn build_test_xml() -> Vec<u8> {
+    fn chunk(typ: u16, header: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut c = typ.to_le_bytes().to_vec();
+        c.extend_from_slice(&(8 + header.len() as u16).to_le_bytes());
+        c.extend_from_slice(&(8 + (header.len() + data.len()) as u32).to_le_bytes());
+        c.extend_from_slice(header);
+        c.extend_from_slice(data);
+        c
+    }
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+    }
+    let strings = ["android", "http://schemas.android.com/apk/res/android", "manifest", "package", "com.example", "versionCode", "application"];
+    let mut offsets = Vec::new();
+    let mut string_data = Vec::new();
+    for s in &strings {
+        offsets.push(string_data.len() as u32);
+        string_data.extend_from_slice(&[s.len() as u8, s.len() as u8]);
+        string_data.extend_from_slice(s.as_bytes());
+        string_data.push(0);
+    }
+    while string_data.len() % 4 != 0 {
+        string_data.push(0);
+    }
+    let mut pool = words(&offsets);
+    pool.extend_from_slice(&string_data);
+    let pool_header = words(&[strings.len() as u32, 0, 0x100, 28 + 4 * strings.len() as u32, 0]);
+
+    let node = words(&[1, 0xffff_ffff]);
+    let none = 0xffff_ffff;
+    // attributes: namespace, name, raw value and a typed value of size 8
+    let mut manifest = words(&[none, 2]);
+    manifest.extend_from_slice(&[20, 0, 20, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+    manifest.extend_from_slice(&words(&[none, 3, 4, 0x0300_0008, 4]));
+    manifest.extend_from_slice(&words(&[1, 5, none, 0x1000_0008, 1]));
+    let mut application = words(&[none, 6]);
+    application.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+    let mut body = chunk(0x0001, &pool_header, &pool);
+    body.extend_from_slice(&chunk(0x0100, &node, &words(&[0, 1])));
+    body.extend_from_slice(&chunk(0x0102, &node, &manifest));
+    body.extend_from_slice(&chunk(0x0102, &node, &application));
+    body.extend_from_slice(&chunk(0x0103, &node, &words(&[none, 6])));
+    body.extend_from_slice(&chunk(0x0103, &node, &words(&[none, 2])));
+    body.extend_from_slice(&chunk(0x0101, &node, &words(&[0, 1])));
+    chunk(0x0003, &[], &body)
+}
+
+#[test]
+fn test_to_xml() {
+    let data = build_test_xml();
+    assert!(is_binary_xml(&data));
+    assert_eq!(to_xml(&data, None).unwrap(), concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+        "<manifest xmlns:android=\"http://schemas.android.com/apk/res/android\" package=\"com.example\" android:versionCode=\"1\">\n",
+        "  <application />\n",
+        "</manifest>\n",
+    ));
+    assert!(to_xml(b"<manifest />", None).is_err());
+}
@@ -0,0 +1,192 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use crate::apk::{Apk, ApkFile};
+use crate::axml::{is_binary_xml, to_xml};
+
+/// Options for extracting an APK to a directory.
+///
+/// ```no_run
+/// use apk_rs::apk::Apk;
+/// use apk_rs::extract::ExtractOptions;
+///
+/// let apk = Apk::open("app.apk").unwrap();
+/// ExtractOptions::new()
+///     .decode_binary_xml(true)
+///     .extract(&apk, "out", |f| !f.name().starts_with("META-INF/"))
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    decode_binary_xml: bool,
+    threads: usize,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> ExtractOptions {
+        ExtractOptions {
+            decode_binary_xml: false,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+impl ExtractOptions {
+    pub fn new() -> ExtractOptions {
+        ExtractOptions::default()
+    }
+
+    /// Writes `.xml` entries in binary XML format as text, resource references are resolved
+    /// with `resources.arsc`. Entries that can not be decoded are written unchanged.
+    pub fn decode_binary_xml(&mut self, decode: bool) -> &mut ExtractOptions {
+        self.decode_binary_xml = decode;
+        self
+    }
+
+    /// Number of entries extracted in parallel, defaults to the available parallelism.
+    pub fn threads(&mut self, threads: usize) -> &mut ExtractOptions {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Extracts all entries accepted by `filter` below `dir` and returns how many were
+    /// written. Names are sanitised first: leading slashes are removed and names with `..`
+    /// components or drive letters are rejected with an `InvalidData` error before anything
+    /// is written. Of entries sharing a name only the first is extracted.
+    ///
+    /// Modification times are restored for files, Unix permissions (without setuid, setgid and
+    /// sticky bits) on Unix. Symbolic links are written as regular files containing the target.
+    pub fn extract<P, F>(&self, apk: &Apk, dir: P, filter: F) -> io::Result<usize>
+    where
+        P: AsRef<Path>,
+        F: Fn(&ApkFile) -> bool + Sync,
+    {
+        let dir = dir.as_ref();
+        let mut seen = HashSet::new();
+        let mut files = Vec::new();
+        for file in apk.files().filter(|f| filter(f)) {
+            let name = file.name();
+            let path = match sanitize_name(&name) {
+                Some(path) => dir.join(path),
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: unsafe entry name", name))),
+            };
+            // names like `a/b` and `/a/b` end up at the same path
+            if seen.insert(path.clone()) {
+                files.push((file, path, name.ends_with('/')));
+            }
+        }
+
+        fs::create_dir_all(dir)?;
+        let next = AtomicUsize::new(0);
+        let error = Mutex::new(None);
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(files.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let (file, path, is_dir) = match files.get(index) {
+                            Some(entry) => entry,
+                            None => break,
+                        };
+                        let result = if *is_dir {
+                            fs::create_dir_all(path)
+                        } else {
+                            self.extract_file(apk, file, path)
+                        };
+                        if let Err(e) = result {
+                            error.lock().unwrap().get_or_insert(e);
+                            // let the other threads stop after their current entry
+                            next.store(files.len(), Ordering::Relaxed);
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        match error.into_inner().unwrap() {
+            Some(e) => Err(e),
+            None => Ok(files.len()),
+        }
+    }
+
+    fn extract_file(&self, apk: &Apk, file: &ApkFile, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut out = File::create(path)?;
+        if self.decode_binary_xml && file.name().ends_with(".xml") {
+            // decoding needs the whole document, everything else is streamed
+            let data = file.bytes()?;
+            let xml = if is_binary_xml(&data) { to_xml(&data, apk.get_resources()).ok() } else { None };
+            match xml {
+                Some(xml) => out.write_all(xml.as_bytes())?,
+                None => out.write_all(&data)?,
+            }
+        } else {
+            io::copy(&mut file.content()?, &mut out)?;
+        }
+
+        out.set_modified(file.last_modified().to_system_time())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Some(mode) = file.unix_mode().map(|mode| mode & 0o777).filter(|&mode| mode != 0) {
+                out.set_permissions(fs::Permissions::from_mode(mode))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Turns an entry name into a relative path that can not leave the extraction directory.
+/// Backslashes are treated as separators, as Windows does.
+fn sanitize_name(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for part in name.split(['/', '\\']) {
+        match Path::new(part).components().next() {
+            None | Some(Component::CurDir) => continue,
+            Some(Component::Normal(_)) if !part.contains(':') && !part.contains('\0') => path.push(part),
+            _ => return None,
+        }
+    }
+    if path.as_os_str().is_empty() {
+        return None;
+    }
+    Some(path)
+}
+
+#[test]
+fn test_sanitize_name() {
+    assert_eq!(sanitize_name("res/layout/main.xml"), Some(PathBuf::from("res/layout/main.xml")));
+    assert_eq!(sanitize_name("/etc/passwd"), Some(PathBuf::from("etc/passwd")));
+    assert_eq!(sanitize_name("./lib//x86/"), Some(PathBuf::from("lib/x86")));
+    assert_eq!(sanitize_name("assets/../../evil"), None);
+    assert_eq!(sanitize_name("..\\evil"), None);
+    assert_eq!(sanitize_name("C:\\Windows\\evil"), None);
+    assert_eq!(sanitize_name("/"), None);
+}
+
+#[test]
+fn test_extract() {
+    use crate::zip::writer::ZipWriter;
+
+    let mut writer = ZipWriter::new(Vec::new());
+    writer.add_deflated("res/layout/main.xml", &crate::axml::build_test_xml()).unwrap();
+    writer.add_deflated("assets/a", b"first").unwrap();
+    writer.add_stored("/assets/a", b"second").unwrap();
+    let apk = Apk::from_bytes(writer.finish().unwrap()).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("apk-rs-test-extract-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let extracted = ExtractOptions::new().decode_binary_xml(true).extract(&apk, &dir, |_| true).unwrap();
+    assert_eq!(extracted, 2);
+    assert_eq!(fs::read(dir.join("assets/a")).unwrap(), b"first");
+    assert!(fs::read_to_string(dir.join("res/layout/main.xml")).unwrap().starts_with("<?xml"));
+    fs::remove_dir_all(&dir).unwrap();
+}
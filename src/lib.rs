@@ -2,11 +2,11 @@ extern crate nom;
 
 pub mod apk;
 pub mod axml;
+pub mod extract;
 pub mod resources;
 pub mod typedvalue;
 pub mod signature;
+pub mod zip;
 
 mod chunk;
 mod stringpool;
-mod zip;
-
@@ -15,8 +15,26 @@ use crate::resources::resources::is_package_reference;
 fn main() -> Result<(), Box<std::error::Error>> {
     let apk_path = env::args().last().unwrap();
     let apk = apk::Apk::open(&apk_path)?;
-    let fp = apk.get_certificate_fingerprint_sha256()?;
-    println!("{:x?}", fp);
+    let certificates = apk.signing_certificates()?;
+    if certificates.is_empty() {
+        println!("not signed");
+    }
+    for (i, certificate) in certificates.iter().enumerate() {
+        let certificate = match certificate {
+            Some(certificate) => certificate,
+            None => {
+                println!("Signer #{}: invalid certificate", i + 1);
+                continue;
+            }
+        };
+        let fingerprints = certificate.fingerprints();
+        println!("Signer #{}: {}", i + 1, certificate.subject);
+        println!("  MD5:      {}", fingerprints.md5);
+        println!("  SHA-1:    {}", fingerprints.sha1);
+        println!("  SHA-256:  {}", fingerprints.sha256);
+        println!("  SHA-512:  {}", fingerprints.sha512);
+        println!("  Key hash: {}", fingerprints.facebook_key_hash());
+    }
     /*
     for f in apk.files() {
         println!("{}: {}/{}", f.name(), f.len(), f.compressed_len());
@@ -80,6 +98,7 @@ fn indent_ouput(level: u32) {
 mod apk;
 mod chunk;
 mod axml;
+mod extract;
 mod stringpool;
 mod typedvalue;
 mod zip;
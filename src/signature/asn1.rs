@@ -40,31 +40,56 @@ named!(parse_sub_identifiers<&[u8], Vec<&[u8]>>, do_parse!(
     (sub_identifiers)
 ));
 
-fn decode_object_identifier(data: &[u8]) -> Vec<u32> {
-    let (_, sub_ids) = parse_sub_identifiers(data).unwrap();
-    let mut parts: Vec<u32> = Vec::with_capacity(sub_ids.len());
+/// Decodes the content octets of an object identifier, `None` if they are truncated or a
+/// sub-identifier does not fit in a `u32`.
+pub fn decode_object_identifier(data: &[u8]) -> Option<Vec<u32>> {
+    let sub_ids = match parse_sub_identifiers(data) {
+        IResult::Done([], sub_ids) if !sub_ids.is_empty() => sub_ids,
+        _ => return None,
+    };
+    let mut parts: Vec<u32> = Vec::with_capacity(sub_ids.len() + 1);
     for (i, sub_id) in sub_ids.iter().enumerate() {
+        // 5 octets hold 35 bits, of which only 32 fit
+        if sub_id.len() > 5 || (sub_id.len() == 5 && sub_id[0] & 0x7f > 0x0f) {
+            return None;
+        }
         let mut part: u32 = 0;
         for (j, d) in sub_id.iter().rev().enumerate() {
             part |= (*d as u32 & 0x7f) << (j * 7);
         }
         if i == 0 {
-            let y = part % 40;
-            let x = part / 40;
+            // the first arc is 0, 1 or 2, only below 2 the second one is less than 40
+            let x = (part / 40).min(2);
             parts.push(x);
-            parts.push(y);
+            parts.push(part - x * 40);
         } else {
             parts.push(part);
         }
     }
-    parts
+    Some(parts)
 }
 
 #[test]
 fn test_decode_object_identifier() {
     let d = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
-    let r = decode_object_identifier(&d);
+    let r = decode_object_identifier(&d).unwrap();
     assert_eq!(r, [1, 2, 840, 113549, 1, 7, 2]);
+    assert_eq!(encode_object_identifier(&r), d);
+
+    let d = [0x88, 0x37, 0x03];
+    let r = decode_object_identifier(&d).unwrap();
+    assert_eq!(r, [2, 999, 3]);
+    assert_eq!(encode_object_identifier(&r), d);
+    assert_eq!(decode_object_identifier(&[0x8f, 0xff, 0xff, 0xff, 0x7f]), Some(vec![2, u32::MAX - 80]));
+
+    // 2.25 followed by a UUID as a 128 bit integer
+    let mut d = vec![0x69, 0x83];
+    d.extend_from_slice(&[0xff; 17]);
+    d.push(0x7f);
+    assert_eq!(decode_object_identifier(&d), None);
+    assert_eq!(decode_object_identifier(&[0x2a, 0x90, 0x80, 0x80, 0x80, 0x00]), None);
+    assert_eq!(decode_object_identifier(&[0x2a, 0x86]), None);
+    assert_eq!(decode_object_identifier(&[]), None);
 }
 
 #[derive(Debug)]
@@ -83,6 +108,81 @@ impl DataElement {
     pub fn data(&self) -> &Vec<u8> {
         &self.data
     }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn is_context_specific(&self) -> bool {
+        self.tag_class == TagClass::Context
+    }
+
+    /// The DER encoding of the element, including identifier and length.
+    pub fn to_der(&self) -> Vec<u8> {
+        let class = match self.tag_class {
+            TagClass::Universal => 0x00,
+            TagClass::Application => 0x40,
+            TagClass::Context => 0x80,
+            TagClass::Private => 0xc0,
+        };
+        let constructed = if self.constructed { 0x20 } else { 0x00 };
+        let mut der = Vec::with_capacity(self.data.len() + 8);
+        if self.id < 0x1f {
+            der.push(class | constructed | self.id as u8);
+        } else {
+            der.push(class | constructed | 0x1f);
+            let mut shift = (31 - self.id.leading_zeros()) / 7 * 7;
+            loop {
+                let more = if shift > 0 { 0x80 } else { 0x00 };
+                der.push(more | ((self.id >> shift) & 0x7f) as u8);
+                if shift == 0 {
+                    break;
+                }
+                shift -= 7;
+            }
+        }
+        der.extend_from_slice(&encode_length(self.data.len()));
+        der.extend_from_slice(&self.data);
+        der
+    }
+}
+
+/// The DER encoding of a length, in short form below 128.
+pub fn encode_length(length: usize) -> Vec<u8> {
+    if length < 0x80 {
+        return vec![length as u8];
+    }
+    let bytes = length.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    let mut encoded = vec![0x80 | (bytes.len() - skip) as u8];
+    encoded.extend_from_slice(&bytes[skip..]);
+    encoded
+}
+
+/// The DER encoding of an element with a single byte identifier.
+pub fn encode(identifier: u8, content: &[u8]) -> Vec<u8> {
+    let mut der = vec![identifier];
+    der.extend_from_slice(&encode_length(content.len()));
+    der.extend_from_slice(content);
+    der
+}
+
+/// The content octets of an object identifier, the inverse of `decode_object_identifier`.
+pub fn encode_object_identifier(oid: &[u32]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let first = oid.first().copied().unwrap_or(0) * 40 + oid.get(1).copied().unwrap_or(0);
+    for &part in std::iter::once(&first).chain(oid.iter().skip(2)) {
+        let mut shift = (31 - (part | 1).leading_zeros()) / 7 * 7;
+        loop {
+            let more = if shift > 0 { 0x80 } else { 0x00 };
+            encoded.push(more | ((part >> shift) & 0x7f) as u8);
+            if shift == 0 {
+                break;
+            }
+            shift -= 7;
+        }
+    }
+    encoded
 }
 
 #[derive(Debug, PartialEq)]
@@ -94,6 +194,9 @@ enum TagClass {
 }
 
 fn parse_identifier(input: &[u8]) -> IResult<&[u8], (TagClass, bool, u32)> {
+    if input.is_empty() {
+        return IResult::Incomplete(Needed::Size(1));
+    }
     let tag_class = match (input[0] & 0xC0) >> 6 {
         0 => TagClass::Universal,
         1 => TagClass::Application,
@@ -108,14 +211,14 @@ fn parse_identifier(input: &[u8]) -> IResult<&[u8], (TagClass, bool, u32)> {
         let id = input[0] as u32 & 0x1f;
         return IResult::Done(&input[1..], (tag_class, constructed, id));
     } else {
-        for i in 1..5 {
+        for i in 1..input.len().min(5) {
             if input[i] & 0x80 != 0x80 {
                 let mut identifier: u32 = 0;
                 for j in 1..=i {
                     let shift = (i - j) * 7;
                     identifier |= (input[j] as u32 & 0x7f) << shift;
                 }
-                return IResult::Done(&input[i..], (tag_class, constructed, identifier));
+                return IResult::Done(&input[i + 1..], (tag_class, constructed, identifier));
             }
         }
         return IResult::Error(ErrorKind::Custom(0));
@@ -147,6 +250,9 @@ fn parse_length(input: &[u8]) -> IResult<&[u8], usize> {
     let mut length: usize = 0;
     let rest: &[u8];
 
+    if input.is_empty() {
+        return IResult::Incomplete(Needed::Size(1));
+    }
     if input[0] & 0x80 != 0x80 {
         length = input[0] as usize & 0x7f;
         rest = &input[1..];
@@ -155,6 +261,9 @@ fn parse_length(input: &[u8]) -> IResult<&[u8], usize> {
         if length_length > size_of::<usize>() {
             return IResult::Error(ErrorKind::Custom(0));
         }
+        if input.len() <= length_length {
+            return IResult::Incomplete(Needed::Size(length_length + 1));
+        }
 
         for i in 1..=length_length {
             length |= (input[i] as usize) << ((length_length - i) * 8);
@@ -188,6 +297,19 @@ named!(pub parse_data_element<&[u8], DataElement>, do_parse!(
     })
 ));
 
+#[test]
+fn test_to_der() {
+    let d = b"\x30\x03\x02\x01\x05";
+    let (_, r) = parse_data_element(d).unwrap();
+    assert_eq!(r.to_der(), d);
+
+    let mut d = vec![0xbf, 0x8a, 0x2a, 0x82, 0x01, 0x00];
+    d.extend_from_slice(&[0; 256]);
+    let (_, r) = parse_data_element(&d).unwrap();
+    assert_eq!(r.id(), 0x52a);
+    assert_eq!(r.to_der(), d);
+}
+
 named!(pub parse_data_elements<&[u8], Vec<DataElement>>, do_parse!(
     elements: many0!(parse_data_element) >>
     (elements)
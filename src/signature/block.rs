@@ -0,0 +1,250 @@
+use std::io::{self, Read};
+use nom::*;
+use crate::zip::archive::ZipArchive;
+use crate::signature::error::SignatureError;
+use crate::signature::signer::{self, Signer};
+use crate::signature::stamp::SourceStamp;
+
+pub const APK_SIGNING_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+
+pub const SIGNATURE_SCHEME_V2_BLOCK_ID: u32 = 0x7109_871a;
+pub const SIGNATURE_SCHEME_V3_BLOCK_ID: u32 = 0xf053_68c0;
+/// APK Signature Scheme v3.1, the v3 format for the rotated key on newer SDKs while the v3
+/// block keeps the original key for older ones.
+pub const SIGNATURE_SCHEME_V31_BLOCK_ID: u32 = 0x1b93_ad61;
+/// Android 13, the first version reading the v3.1 block.
+pub const SIGNATURE_SCHEME_V31_MIN_SDK_VERSION: u32 = 33;
+
+/// Source stamps of app stores, see `SourceStamp`.
+pub const SOURCE_STAMP_V1_BLOCK_ID: u32 = 0x2b09_189e;
+pub const SOURCE_STAMP_V2_BLOCK_ID: u32 = 0x6dff_800d;
+/// Zeros padding the signing block to a multiple of 4096 bytes for fs-verity.
+pub const VERITY_PADDING_BLOCK_ID: u32 = 0x4272_6577;
+/// Encrypted information on the dependencies of the app, added by the Android Gradle plugin.
+pub const DEPENDENCY_INFO_BLOCK_ID: u32 = 0x504b_4453;
+/// Google Play metadata, the frosting.
+pub const FROSTING_BLOCK_ID: u32 = 0x2146_444e;
+
+/// Additional attribute of v3 signers giving the SDK version from which the v3.1 block applies.
+/// It keeps the v3.1 block from being stripped to hide the rotation.
+pub const ROTATION_MIN_SDK_VERSION_ATTRIBUTE_ID: u32 = 0x559f_8b02;
+
+/// The APK signing block sits between the last entry and the central directory. It holds the
+/// blocks of the v2 and later signature schemes as ID-value pairs.
+#[derive(Debug, Clone)]
+pub struct SigningBlock {
+    /// Offset of the signing block in the APK, the end of the data covered by v2+ digests.
+    pub offset: u64,
+    pub pairs: Vec<IdValuePair>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IdValuePair {
+    pub id: u32,
+    pub value: Vec<u8>,
+}
+
+/// The known kinds of ID-value pairs in the signing block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockKind {
+    SignatureSchemeV2,
+    SignatureSchemeV3,
+    SignatureSchemeV31,
+    SourceStampV1,
+    SourceStampV2,
+    VerityPadding,
+    DependencyInfo,
+    Frosting,
+    Unknown(u32),
+}
+
+impl From<u32> for BlockKind {
+    fn from(id: u32) -> BlockKind {
+        match id {
+            SIGNATURE_SCHEME_V2_BLOCK_ID => BlockKind::SignatureSchemeV2,
+            SIGNATURE_SCHEME_V3_BLOCK_ID => BlockKind::SignatureSchemeV3,
+            SIGNATURE_SCHEME_V31_BLOCK_ID => BlockKind::SignatureSchemeV31,
+            SOURCE_STAMP_V1_BLOCK_ID => BlockKind::SourceStampV1,
+            SOURCE_STAMP_V2_BLOCK_ID => BlockKind::SourceStampV2,
+            VERITY_PADDING_BLOCK_ID => BlockKind::VerityPadding,
+            DEPENDENCY_INFO_BLOCK_ID => BlockKind::DependencyInfo,
+            FROSTING_BLOCK_ID => BlockKind::Frosting,
+            id => BlockKind::Unknown(id),
+        }
+    }
+}
+
+impl IdValuePair {
+    pub fn kind(&self) -> BlockKind {
+        self.id.into()
+    }
+}
+
+impl SigningBlock {
+    /// Reads the signing block of an archive, `None` if the APK has none.
+    pub fn read(archive: &ZipArchive) -> io::Result<Option<SigningBlock>> {
+        let cd_offset = archive.central_directory_offset();
+        if cd_offset < 32 {
+            return Ok(None);
+        }
+        let mut footer = [0; 24];
+        archive.raw_range(cd_offset - 24, 24)?.read_exact(&mut footer)?;
+        if &footer[8..] != APK_SIGNING_BLOCK_MAGIC {
+            return Ok(None);
+        }
+
+        // the size excludes the leading size field itself
+        let size = u64::from_le_bytes([footer[0], footer[1], footer[2], footer[3], footer[4], footer[5], footer[6], footer[7]]);
+        let offset = match cd_offset.checked_sub(size).and_then(|o| o.checked_sub(8)) {
+            Some(offset) if size >= 24 => offset,
+            _ => return Err(SignatureError::InvalidSigningBlock.into()),
+        };
+        let mut block = Vec::new();
+        archive.raw_range(offset, size + 8)?.read_to_end(&mut block)?;
+        match parse_signing_block(&block) {
+            IResult::Done([], pairs) => Ok(Some(SigningBlock { offset, pairs })),
+            _ => Err(SignatureError::InvalidSigningBlock.into()),
+        }
+    }
+
+    /// Encodes the signing block with its pairs, to be put in front of the central directory.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let size = self.pairs.iter().map(|pair| 12 + pair.value.len() as u64).sum::<u64>() + 24;
+        let mut block = size.to_le_bytes().to_vec();
+        for pair in &self.pairs {
+            block.extend_from_slice(&(pair.value.len() as u64 + 4).to_le_bytes());
+            block.extend_from_slice(&pair.id.to_le_bytes());
+            block.extend_from_slice(&pair.value);
+        }
+        block.extend_from_slice(&size.to_le_bytes());
+        block.extend_from_slice(APK_SIGNING_BLOCK_MAGIC);
+        block
+    }
+
+    /// The value of the first pair with the given ID.
+    pub fn get(&self, id: u32) -> Option<&[u8]> {
+        self.pairs.iter().find(|pair| pair.id == id).map(|pair| &pair.value[..])
+    }
+
+    /// Signers of APK Signature Scheme v2, `None` if the APK is not signed with it.
+    pub fn v2_signers(&self) -> io::Result<Option<Vec<Signer>>> {
+        self.signers(SIGNATURE_SCHEME_V2_BLOCK_ID, false)
+    }
+
+    /// Signers of APK Signature Scheme v3, `None` if the APK is not signed with it.
+    pub fn v3_signers(&self) -> io::Result<Option<Vec<Signer>>> {
+        self.signers(SIGNATURE_SCHEME_V3_BLOCK_ID, true)
+    }
+
+    /// Signers of APK Signature Scheme v3.1, `None` if the APK is not signed with it.
+    pub fn v31_signers(&self) -> io::Result<Option<Vec<Signer>>> {
+        self.signers(SIGNATURE_SCHEME_V31_BLOCK_ID, true)
+    }
+
+    /// The v3.1 or else v3 signer devices on the SDK version verify the APK with, `None` if
+    /// neither covers it and devices fall back to v2 or JAR signing. Devices before Android 13
+    /// ignore the v3.1 block whatever SDK versions its signers declare.
+    pub fn signer_for_sdk(&self, sdk: u32) -> io::Result<Option<Signer>> {
+        let v31_signers = if sdk >= SIGNATURE_SCHEME_V31_MIN_SDK_VERSION {
+            self.v31_signers()?
+        } else {
+            None
+        };
+        for signers in [v31_signers, self.v3_signers()?].iter().flatten() {
+            if let Some(signer) = signers.iter().find(|signer| signer.supports_sdk(sdk)) {
+                return Ok(Some(signer.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The source stamp, version 2 if present or else 1, `None` if the APK has none.
+    pub fn source_stamp(&self) -> io::Result<Option<SourceStamp>> {
+        for &(id, version) in &[(SOURCE_STAMP_V2_BLOCK_ID, 2), (SOURCE_STAMP_V1_BLOCK_ID, 1)] {
+            if let Some(value) = self.get(id) {
+                return match SourceStamp::parse(value, version) {
+                    Some(stamp) => Ok(Some(stamp)),
+                    None => Err(SignatureError::InvalidSchemeBlock { id }.into()),
+                };
+            }
+        }
+        Ok(None)
+    }
+
+    /// The raw ID and value of every pair whose ID is not known.
+    pub fn unknown_pairs(&self) -> Vec<(u32, &[u8])> {
+        self.pairs.iter()
+            .filter(|pair| matches!(pair.kind(), BlockKind::Unknown(_)))
+            .map(|pair| (pair.id, &pair.value[..]))
+            .collect()
+    }
+
+    fn signers(&self, id: u32, v3: bool) -> io::Result<Option<Vec<Signer>>> {
+        match self.get(id) {
+            Some(value) => match signer::parse_signers(value, v3) {
+                Some(signers) => Ok(Some(signers)),
+                None => Err(SignatureError::InvalidSchemeBlock { id }.into()),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+named!(parse_id_value_pair<&[u8], IdValuePair>, do_parse!(
+    pair: length_bytes!(le_u64) >>
+    pair: expr_opt!(if pair.len() >= 4 { Some(pair) } else { None }) >>
+    (IdValuePair {
+        id: u32::from_le_bytes([pair[0], pair[1], pair[2], pair[3]]),
+        value: pair[4..].to_vec(),
+    })
+));
+
+named!(parse_signing_block<&[u8], Vec<IdValuePair>>, do_parse!(
+    size: verify!(le_u64, |s| s >= 24) >>
+    pairs: flat_map!(take!(size - 24), many0!(complete!(parse_id_value_pair))) >>
+    verify!(le_u64, |s| s == size) >>
+    tag!(APK_SIGNING_BLOCK_MAGIC) >>
+    (pairs)
+));
+
+#[test]
+fn test_parse_signing_block() {
+    let mut d = Vec::new();
+    d.extend_from_slice(&(8 + 10 + 8 + 4 + 24u64).to_le_bytes());
+    d.extend_from_slice(&10u64.to_le_bytes());
+    d.extend_from_slice(&[0x1a, 0x87, 0x09, 0x71, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+    d.extend_from_slice(&4u64.to_le_bytes());
+    d.extend_from_slice(&[0x77, 0x65, 0x72, 0x42]);
+    d.extend_from_slice(&(8 + 10 + 8 + 4 + 24u64).to_le_bytes());
+    d.extend_from_slice(APK_SIGNING_BLOCK_MAGIC);
+    let (rest, pairs) = parse_signing_block(&d).unwrap();
+    assert_eq!(rest.len(), 0);
+    assert_eq!(pairs.len(), 2);
+    assert_eq!(pairs[0].id, SIGNATURE_SCHEME_V2_BLOCK_ID);
+    assert_eq!(pairs[0].value, [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+    assert_eq!(pairs[1].id, 0x4272_6577);
+    assert!(pairs[1].value.is_empty());
+    assert_eq!(pairs[1].kind(), BlockKind::VerityPadding);
+    assert_eq!(SigningBlock { offset: 0, pairs }.to_bytes(), d);
+}
+
+#[test]
+fn test_signer_for_sdk() {
+    let archive = ZipArchive::from_bytes(&include_bytes!("../../testdata/v31-rotated.apk")[..]).unwrap();
+    let mut block = SigningBlock::read(&archive).unwrap().unwrap();
+    let rsa = include_bytes!("../../testdata/rsa.der").to_vec();
+    let ec = include_bytes!("../../testdata/ec.der").to_vec();
+    let certificate = |block: &SigningBlock, sdk| block.signer_for_sdk(sdk).unwrap().map(|signer| signer.signed_data.certificates[0].clone());
+    assert_eq!(certificate(&block, 27), None);
+    assert_eq!(certificate(&block, 28), Some(rsa.clone()));
+    assert_eq!(certificate(&block, 33), Some(ec.clone()));
+
+    // a v3.1 signer claiming older versions only applies from Android 13 on
+    let mut v31_signers = block.v31_signers().unwrap().unwrap();
+    v31_signers[0].min_sdk_version = Some(24);
+    let pair = block.pairs.iter_mut().find(|pair| pair.id == SIGNATURE_SCHEME_V31_BLOCK_ID).unwrap();
+    pair.value = signer::encode_signers(&v31_signers);
+    assert_eq!(certificate(&block, 24), None);
+    assert_eq!(certificate(&block, 30), Some(rsa));
+    assert_eq!(certificate(&block, 33), Some(ec));
+}
@@ -0,0 +1,489 @@
+use std::fmt;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use crate::signature::asn1::{self, DataElement};
+use crate::signature::error::SignatureError;
+use crate::signature::fingerprint::CertificateFingerprints;
+use crate::signature::key;
+use crate::zip::time::DateTime;
+
+/// Subject of the certificates in debug keystores generated by the Android SDK.
+pub const ANDROID_DEBUG_COMMON_NAME: &str = "Android Debug";
+
+const COMMON_NAME: &[u32] = &[2, 5, 4, 3];
+
+/// An X.509 certificate.
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    der: Vec<u8>,
+    /// 1 to 3, certificates without version field are version 1.
+    pub version: u32,
+    /// Big-endian two's complement, as encoded.
+    pub serial_number: Vec<u8>,
+    pub signature_algorithm: SignatureAlgorithm,
+    pub issuer: DistinguishedName,
+    pub subject: DistinguishedName,
+    pub not_before: DateTime,
+    pub not_after: DateTime,
+    pub public_key: PublicKey,
+    pub extensions: Vec<Extension>,
+}
+
+/// The algorithm the issuer signed the certificate with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureAlgorithm {
+    pub oid: Vec<u32>,
+}
+
+impl SignatureAlgorithm {
+    /// The name as used by Java, like `SHA256withRSA`, `None` for unknown algorithms.
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match &self.oid[..] {
+            [1, 2, 840, 113549, 1, 1, 2] => "MD2withRSA",
+            [1, 2, 840, 113549, 1, 1, 4] => "MD5withRSA",
+            [1, 2, 840, 113549, 1, 1, 5] => "SHA1withRSA",
+            [1, 2, 840, 113549, 1, 1, 10] => "RSASSA-PSS",
+            [1, 2, 840, 113549, 1, 1, 11] => "SHA256withRSA",
+            [1, 2, 840, 113549, 1, 1, 12] => "SHA384withRSA",
+            [1, 2, 840, 113549, 1, 1, 13] => "SHA512withRSA",
+            [1, 2, 840, 10045, 4, 1] => "SHA1withECDSA",
+            [1, 2, 840, 10045, 4, 3, 2] => "SHA256withECDSA",
+            [1, 2, 840, 10045, 4, 3, 3] => "SHA384withECDSA",
+            [1, 2, 840, 10045, 4, 3, 4] => "SHA512withECDSA",
+            [1, 2, 840, 10040, 4, 3] => "SHA1withDSA",
+            [2, 16, 840, 1, 101, 3, 4, 3, 2] => "SHA256withDSA",
+            _ => return None,
+        };
+        Some(name)
+    }
+
+    /// Whether the algorithm relies on MD2, MD5 or SHA-1.
+    pub fn is_weak(&self) -> bool {
+        matches!(self.name(), Some(name) if name.starts_with("MD") || name.starts_with("SHA1"))
+    }
+}
+
+impl fmt::Display for SignatureAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{}", format_object_identifier(&self.oid)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PublicKeyAlgorithm {
+    Rsa,
+    Ec,
+    Dsa,
+    Other(Vec<u32>),
+}
+
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    pub algorithm: PublicKeyAlgorithm,
+    /// Size of the RSA modulus, the EC field or the DSA prime in bits, 0 if unknown.
+    pub size: u32,
+    /// DER encoded SubjectPublicKeyInfo.
+    pub der: Vec<u8>,
+}
+
+impl PublicKey {
+    /// Whether the key is too small to be trusted: RSA and DSA below 2048 bits, EC below 224.
+    pub fn is_weak(&self) -> bool {
+        match self.algorithm {
+            PublicKeyAlgorithm::Rsa | PublicKeyAlgorithm::Dsa => self.size < 2048,
+            PublicKeyAlgorithm::Ec => self.size < 224,
+            PublicKeyAlgorithm::Other(_) => false,
+        }
+    }
+}
+
+/// An issuer or subject name as a sequence of attributes, in the order of the encoding.
+#[derive(Debug, Clone)]
+pub struct DistinguishedName {
+    /// Content octets of the Name SEQUENCE, to compare names as encoded.
+    raw: Vec<u8>,
+    pub attributes: Vec<NameAttribute>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameAttribute {
+    pub oid: Vec<u32>,
+    pub value: String,
+}
+
+impl DistinguishedName {
+    /// The value of the first attribute with the given type.
+    pub fn get(&self, oid: &[u32]) -> Option<&str> {
+        self.attributes.iter().find(|a| a.oid == oid).map(|a| &a.value[..])
+    }
+
+    pub fn common_name(&self) -> Option<&str> {
+        self.get(COMMON_NAME)
+    }
+}
+
+impl PartialEq for DistinguishedName {
+    fn eq(&self, other: &DistinguishedName) -> bool {
+        self.raw == other.raw
+    }
+}
+
+fn attribute_short_name(oid: &[u32]) -> Option<&'static str> {
+    let name = match oid {
+        [2, 5, 4, 3] => "CN",
+        [2, 5, 4, 5] => "SERIALNUMBER",
+        [2, 5, 4, 6] => "C",
+        [2, 5, 4, 7] => "L",
+        [2, 5, 4, 8] => "ST",
+        [2, 5, 4, 9] => "STREET",
+        [2, 5, 4, 10] => "O",
+        [2, 5, 4, 11] => "OU",
+        [2, 5, 4, 12] => "T",
+        [1, 2, 840, 113549, 1, 9, 1] => "EMAILADDRESS",
+        [0, 9, 2342, 19_200_300, 100, 1, 1] => "UID",
+        [0, 9, 2342, 19_200_300, 100, 1, 25] => "DC",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn format_object_identifier(oid: &[u32]) -> String {
+    oid.iter().map(u32::to_string).collect::<Vec<_>>().join(".")
+}
+
+/// Formats like RFC 4514 with the most specific attribute first, e.g.
+/// `CN=Android Debug, O=Android, C=US`.
+impl fmt::Display for DistinguishedName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, attribute) in self.attributes.iter().rev().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match attribute_short_name(&attribute.oid) {
+                Some(name) => write!(f, "{}=", name)?,
+                None => write!(f, "{}=", format_object_identifier(&attribute.oid))?,
+            }
+            for c in attribute.value.chars() {
+                if matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';') {
+                    write!(f, "\\")?;
+                }
+                write!(f, "{}", c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Extension {
+    pub oid: Vec<u32>,
+    pub critical: bool,
+    /// The DER encoded extension value.
+    pub value: Vec<u8>,
+}
+
+fn elements(element: &DataElement) -> Option<Vec<DataElement>> {
+    element.parse_data().to_full_result().ok()
+}
+
+/// Decodes the string types used in names, others are taken as Latin-1.
+fn decode_string(element: &DataElement) -> String {
+    let data = element.data();
+    match element.id() {
+        // UTF8String, PrintableString, IA5String
+        12 | 19 | 22 => String::from_utf8_lossy(data).into_owned(),
+        // BMPString
+        30 => {
+            let units: Vec<u16> = data.chunks(2).map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])).collect();
+            String::from_utf16_lossy(&units)
+        }
+        // UniversalString
+        28 => data.chunks(4)
+            .map(|c| {
+                let mut b = [0; 4];
+                b[..c.len()].copy_from_slice(c);
+                std::char::from_u32(u32::from_be_bytes(b)).unwrap_or('\u{fffd}')
+            })
+            .collect(),
+        _ => data.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+fn parse_name(name: &DataElement) -> Option<DistinguishedName> {
+    let mut attributes = Vec::new();
+    for rdn in elements(name)? {
+        for attribute in elements(&rdn)? {
+            let attribute = elements(&attribute)?;
+            attributes.push(NameAttribute {
+                oid: asn1::decode_object_identifier(attribute.first()?.data())?,
+                value: decode_string(attribute.get(1)?),
+            });
+        }
+    }
+    Some(DistinguishedName { raw: name.data().clone(), attributes })
+}
+
+fn digits(data: &[u8]) -> Option<u32> {
+    let text = std::str::from_utf8(data).ok()?;
+    if text.bytes().all(|b| b.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Decodes UTCTime and GeneralizedTime in the `Z` form required for certificates.
+fn parse_time(time: &DataElement) -> Option<DateTime> {
+    let data = time.data();
+    let (year, rest) = match time.id() {
+        23 => {
+            let year = digits(data.get(..2)?)?;
+            // two digit years mean 1950 to 2049
+            (if year < 50 { 2000 + year } else { 1900 + year }, &data[2..])
+        }
+        24 => (digits(data.get(..4)?)?, &data[4..]),
+        _ => return None,
+    };
+    if rest.len() < 10 || rest[10..] != *b"Z" {
+        return None;
+    }
+    Some(DateTime {
+        year: year as u16,
+        month: digits(&rest[0..2])? as u8,
+        day: digits(&rest[2..4])? as u8,
+        hour: digits(&rest[4..6])? as u8,
+        minute: digits(&rest[6..8])? as u8,
+        second: digits(&rest[8..10])? as u8,
+    })
+}
+
+/// Bits of a big-endian unsigned integer.
+fn bit_length(integer: &[u8]) -> u32 {
+    let integer: Vec<_> = integer.iter().skip_while(|&&b| b == 0).collect();
+    match integer.first() {
+        Some(first) => (integer.len() as u32 - 1) * 8 + (8 - first.leading_zeros()),
+        None => 0,
+    }
+}
+
+fn parse_public_key(der: Vec<u8>) -> Option<PublicKey> {
+    let info = key::parse_public_key_info(&der)?;
+    let first_integer = |data: &[u8]| -> Option<u32> {
+        let sequence = asn1::parse_data_element(data).to_full_result().ok()?;
+        Some(bit_length(elements(&sequence)?.first()?.data()))
+    };
+    let first_parameter = |data: &[u8]| -> Option<u32> {
+        Some(bit_length(asn1::parse_data_elements(data).to_full_result().ok()?.first()?.data()))
+    };
+    let (algorithm, size) = match &info.algorithm[..] {
+        key::RSA_ENCRYPTION => (PublicKeyAlgorithm::Rsa, first_integer(&info.key).unwrap_or(0)),
+        key::EC_PUBLIC_KEY => {
+            let size = match info.curve.as_deref() {
+                Some(key::SECP256R1) => 256,
+                Some(key::SECP384R1) => 384,
+                Some(key::SECP521R1) => 521,
+                _ => 0,
+            };
+            (PublicKeyAlgorithm::Ec, size)
+        }
+        key::DSA => (PublicKeyAlgorithm::Dsa, first_parameter(&info.parameters).unwrap_or(0)),
+        _ => (PublicKeyAlgorithm::Other(info.algorithm.clone()), 0),
+    };
+    Some(PublicKey { algorithm, size, der })
+}
+
+fn parse_extensions(extensions: &DataElement) -> Option<Vec<Extension>> {
+    let extensions = elements(extensions)?;
+    let mut parsed = Vec::new();
+    for extension in elements(extensions.first()?)? {
+        let fields = elements(&extension)?;
+        // critical is a BOOLEAN defaulting to false
+        let critical = fields.get(1).filter(|f| f.id() == 1).is_some_and(|f| f.data().first() != Some(&0));
+        parsed.push(Extension {
+            oid: asn1::decode_object_identifier(fields.first()?.data())?,
+            critical,
+            value: fields.last()?.data().clone(),
+        });
+    }
+    Some(parsed)
+}
+
+/// The fields of a DER encoded X.509 certificate needed to verify signatures made with it,
+/// without parsing the rest of the certificate.
+pub struct CertificateInfo {
+    /// Content octets of the serial number INTEGER.
+    pub serial_number: Vec<u8>,
+    /// Content octets of the issuer and subject Name SEQUENCEs.
+    pub issuer: Vec<u8>,
+    pub subject: Vec<u8>,
+    /// DER encoded SubjectPublicKeyInfo.
+    pub public_key: Vec<u8>,
+}
+
+pub fn parse_certificate_info(certificate: &[u8]) -> Option<CertificateInfo> {
+    let certificate = asn1::parse_data_element(certificate).to_full_result().ok()?;
+    let certificate = elements(&certificate)?;
+    let tbs_certificate = elements(certificate.first()?)?;
+    // the version is optional and tagged [0]
+    let fields = if tbs_certificate.first()?.is_context_specific() {
+        &tbs_certificate[1..]
+    } else {
+        &tbs_certificate[..]
+    };
+    Some(CertificateInfo {
+        serial_number: fields.first()?.data().clone(),
+        issuer: fields.get(2)?.data().clone(),
+        subject: fields.get(4)?.data().clone(),
+        public_key: fields.get(5)?.to_der(),
+    })
+}
+
+fn parse_certificate(der: &[u8]) -> Option<Certificate> {
+    let certificate = asn1::parse_data_element(der).to_full_result().ok()?;
+    let certificate = elements(&certificate)?;
+    let tbs_certificate = elements(certificate.first()?)?;
+    let signature_algorithm = SignatureAlgorithm {
+        oid: asn1::decode_object_identifier(elements(certificate.get(1)?)?.first()?.data())?,
+    };
+
+    // the version is optional and tagged [0]
+    let (version, fields) = match tbs_certificate.first()? {
+        version if version.is_context_specific() && version.id() == 0 => {
+            let version = asn1::parse_data_element(version.data()).to_full_result().ok()?;
+            (u32::from(*version.data().last()?) + 1, &tbs_certificate[1..])
+        }
+        _ => (1, &tbs_certificate[..]),
+    };
+    let validity = elements(fields.get(3)?)?;
+    let extensions = fields.iter()
+        .skip(6)
+        .find(|f| f.is_context_specific() && f.id() == 3)
+        .map(parse_extensions);
+
+    Some(Certificate {
+        der: der.to_vec(),
+        version,
+        serial_number: fields.first()?.data().clone(),
+        signature_algorithm,
+        issuer: parse_name(fields.get(2)?)?,
+        subject: parse_name(fields.get(4)?)?,
+        not_before: parse_time(validity.first()?)?,
+        not_after: parse_time(validity.get(1)?)?,
+        public_key: parse_public_key(fields.get(5)?.to_der())?,
+        extensions: extensions.unwrap_or(Some(Vec::new()))?,
+    })
+}
+
+impl Certificate {
+    /// Parses a DER encoded certificate.
+    pub fn from_der(der: &[u8]) -> io::Result<Certificate> {
+        parse_certificate(der).ok_or_else(|| SignatureError::InvalidCertificate.into())
+    }
+
+    /// The DER encoding the certificate was parsed from.
+    pub fn der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// The certificate PEM encoded, base64 in lines of 64 characters between
+    /// `-----BEGIN CERTIFICATE-----` and `-----END CERTIFICATE-----`.
+    pub fn to_pem(&self) -> String {
+        let encoded = BASE64.encode(&self.der);
+        let mut pem = String::with_capacity(encoded.len() + encoded.len() / 64 + 64);
+        pem.push_str("-----BEGIN CERTIFICATE-----\n");
+        for line in encoded.as_bytes().chunks(64) {
+            pem.push_str(std::str::from_utf8(line).unwrap());
+            pem.push('\n');
+        }
+        pem.push_str("-----END CERTIFICATE-----\n");
+        pem
+    }
+
+    pub fn fingerprints(&self) -> CertificateFingerprints {
+        CertificateFingerprints::new(&self.der)
+    }
+
+    pub fn extension(&self, oid: &[u32]) -> Option<&Extension> {
+        self.extensions.iter().find(|e| e.oid == oid)
+    }
+
+    /// Whether the certificate comes from a debug keystore of the Android SDK.
+    pub fn is_debug_certificate(&self) -> bool {
+        self.subject.common_name() == Some(ANDROID_DEBUG_COMMON_NAME)
+    }
+
+    pub fn is_self_signed(&self) -> bool {
+        self.issuer == self.subject
+    }
+
+    pub fn is_valid_at(&self, time: DateTime) -> bool {
+        self.not_before <= time && time <= self.not_after
+    }
+
+    /// Whether the certificate is outside of its validity period now. Android itself does not
+    /// check the validity of signing certificates.
+    pub fn is_expired(&self) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+        !self.is_valid_at(DateTime::from_unix_timestamp(now))
+    }
+}
+
+#[test]
+fn test_parse_time() {
+    let (_, utc) = asn1::parse_data_element(b"\x17\x0d491231235959Z").unwrap();
+    assert_eq!(parse_time(&utc), Some(DateTime { year: 2049, month: 12, day: 31, hour: 23, minute: 59, second: 59 }));
+    let (_, utc) = asn1::parse_data_element(b"\x17\x0d500101000000Z").unwrap();
+    assert_eq!(parse_time(&utc).unwrap().year, 1950);
+    let (_, generalized) = asn1::parse_data_element(b"\x18\x0f20500101000000Z").unwrap();
+    assert_eq!(parse_time(&generalized).unwrap().year, 2050);
+    let (_, local) = asn1::parse_data_element(b"\x17\x0b4912312359Z").unwrap();
+    assert_eq!(parse_time(&local), None);
+}
+
+#[test]
+fn test_certificate() {
+    let certificate = Certificate::from_der(include_bytes!("../../testdata/rsa.der")).unwrap();
+    assert_eq!(certificate.version, 3);
+    assert_eq!(certificate.serial_number, [0x01, 0x23, 0x45, 0x67]);
+    assert_eq!(certificate.signature_algorithm.to_string(), "SHA256withRSA");
+    assert!(!certificate.signature_algorithm.is_weak());
+    assert_eq!(certificate.subject.to_string(), "CN=Android Debug, O=Android, C=US");
+    assert_eq!(certificate.subject.get(&[2, 5, 4, 6]), Some("US"));
+    assert!(certificate.is_self_signed());
+    assert!(certificate.is_debug_certificate());
+    assert_eq!(certificate.not_before, DateTime { year: 2020, month: 1, day: 1, hour: 0, minute: 0, second: 0 });
+    assert_eq!(certificate.not_after, DateTime { year: 2049, month: 12, day: 24, hour: 0, minute: 0, second: 0 });
+    assert!(!certificate.is_valid_at(DateTime { year: 2019, month: 12, day: 31, hour: 23, minute: 59, second: 59 }));
+    assert_eq!(certificate.public_key.algorithm, PublicKeyAlgorithm::Rsa);
+    assert_eq!(certificate.public_key.size, 2048);
+    assert!(!certificate.public_key.is_weak());
+    // basic constraints are critical, the subject key identifier is not
+    assert!(certificate.extension(&[2, 5, 29, 19]).unwrap().critical);
+    assert!(!certificate.extension(&[2, 5, 29, 14]).unwrap().critical);
+    assert!(certificate.extension(&[2, 5, 29, 15]).is_none());
+
+    let info = parse_certificate_info(certificate.der()).unwrap();
+    assert_eq!(info.public_key, certificate.public_key.der);
+    assert_eq!(info.serial_number, certificate.serial_number);
+    assert_eq!(info.subject, info.issuer);
+
+    let certificate = Certificate::from_der(include_bytes!("../../testdata/ec.der")).unwrap();
+    assert_eq!(certificate.subject.to_string(), "CN=Example EC, O=Example");
+    assert!(!certificate.is_debug_certificate());
+    assert_eq!((certificate.public_key.algorithm, certificate.public_key.size), (PublicKeyAlgorithm::Ec, 256));
+    assert_eq!(certificate.signature_algorithm.name(), Some("SHA256withECDSA"));
+
+    let certificate = Certificate::from_der(include_bytes!("../../testdata/dsa.der")).unwrap();
+    assert_eq!((certificate.public_key.algorithm, certificate.public_key.size), (PublicKeyAlgorithm::Dsa, 2048));
+    assert_eq!(certificate.signature_algorithm.name(), Some("SHA256withDSA"));
+
+    assert!(Certificate::from_der(b"\x30\x00").is_err());
+    let truncated = &include_bytes!("../../testdata/rsa.der")[..100];
+    assert!(Certificate::from_der(truncated).is_err());
+    assert!(parse_certificate_info(truncated).is_none());
+}
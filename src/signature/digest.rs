@@ -0,0 +1,192 @@
+use std::io::{self, Read};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use crate::zip::archive::ZipArchive;
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+const SHA1: &[u32] = &[1, 3, 14, 3, 2, 26];
+const SHA256: &[u32] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+const SHA384: &[u32] = &[2, 16, 840, 1, 101, 3, 4, 2, 2];
+const SHA512: &[u32] = &[2, 16, 840, 1, 101, 3, 4, 2, 3];
+
+/// Message digests used by JAR signing and PKCS#7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DigestAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    /// The algorithm for an object identifier, `None` for unsupported ones like MD5.
+    pub fn from_oid(oid: &[u32]) -> Option<DigestAlgorithm> {
+        match oid {
+            SHA1 => Some(DigestAlgorithm::Sha1),
+            SHA256 => Some(DigestAlgorithm::Sha256),
+            SHA384 => Some(DigestAlgorithm::Sha384),
+            SHA512 => Some(DigestAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    /// The algorithm for the prefix of a JAR digest attribute like `SHA-256-Digest`.
+    pub fn from_jar_name(name: &str) -> Option<DigestAlgorithm> {
+        match name.to_ascii_uppercase().as_str() {
+            "SHA1" | "SHA-1" => Some(DigestAlgorithm::Sha1),
+            "SHA-256" => Some(DigestAlgorithm::Sha256),
+            "SHA-384" => Some(DigestAlgorithm::Sha384),
+            "SHA-512" => Some(DigestAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn oid(self) -> &'static [u32] {
+        match self {
+            DigestAlgorithm::Sha1 => SHA1,
+            DigestAlgorithm::Sha256 => SHA256,
+            DigestAlgorithm::Sha384 => SHA384,
+            DigestAlgorithm::Sha512 => SHA512,
+        }
+    }
+
+    /// The prefix of JAR digest attributes, as written by apksigner.
+    pub fn jar_name(self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha1 => "SHA1",
+            DigestAlgorithm::Sha256 => "SHA-256",
+            DigestAlgorithm::Sha384 => "SHA-384",
+            DigestAlgorithm::Sha512 => "SHA-512",
+        }
+    }
+
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            DigestAlgorithm::Sha1 => Sha1::digest(data).to_vec(),
+            DigestAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            DigestAlgorithm::Sha384 => Sha384::digest(data).to_vec(),
+            DigestAlgorithm::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    pub fn digest_reader<R: Read>(self, r: R) -> io::Result<Vec<u8>> {
+        match self {
+            DigestAlgorithm::Sha1 => digest_reader::<Sha1, R>(r),
+            DigestAlgorithm::Sha256 => digest_reader::<Sha256, R>(r),
+            DigestAlgorithm::Sha384 => digest_reader::<Sha384, R>(r),
+            DigestAlgorithm::Sha512 => digest_reader::<Sha512, R>(r),
+        }
+    }
+}
+
+fn digest_reader<D: Digest, R: Read>(mut r: R) -> io::Result<Vec<u8>> {
+    let mut digest = D::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match r.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => digest.update(&buf[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(digest.finalize().to_vec())
+}
+
+/// The ways the v2 and later schemes digest the contents of an APK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentDigestAlgorithm {
+    ChunkedSha256,
+    ChunkedSha512,
+}
+
+/// Computes the digest v2 and v3 signers sign over: the ZIP entries up to the signing block,
+/// the central directory and the end of central directory record with the central directory
+/// offset pointing to the signing block. Each section is split into 1 MiB chunks, the
+/// digest is computed over the digests of all chunks.
+pub fn content_digest(archive: &ZipArchive, signing_block_offset: u64, algorithm: ContentDigestAlgorithm) -> io::Result<Vec<u8>> {
+    let mut eocd = Vec::new();
+    archive.raw_range(archive.end_of_central_directory_offset(), u64::MAX)?.read_to_end(&mut eocd)?;
+    if eocd.len() < 22 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    // the signing block is not covered, as if it did not exist
+    let signing_block_offset = signing_block_offset.min(u64::from(u32::MAX)) as u32;
+    eocd[16..20].copy_from_slice(&signing_block_offset.to_le_bytes());
+
+    let sections: Vec<Box<dyn Read>> = vec![
+        Box::new(archive.raw_range(0, u64::from(signing_block_offset))?),
+        Box::new(archive.raw_range(archive.central_directory_offset(), archive.central_directory_size())?),
+        Box::new(&eocd[..]),
+    ];
+    match algorithm {
+        ContentDigestAlgorithm::ChunkedSha256 => chunked_digest::<Sha256>(sections),
+        ContentDigestAlgorithm::ChunkedSha512 => chunked_digest::<Sha512>(sections),
+    }
+}
+
+fn chunked_digest<D: Digest>(sections: Vec<Box<dyn Read + '_>>) -> io::Result<Vec<u8>> {
+    let mut chunk_digests = Vec::new();
+    let mut chunk_count: u32 = 0;
+    let mut buf = vec![0; CHUNK_SIZE];
+    for mut section in sections {
+        loop {
+            let len = read_chunk(&mut section, &mut buf)?;
+            if len == 0 {
+                break;
+            }
+            let mut digest = D::new();
+            digest.update([0xa5]);
+            digest.update((len as u32).to_le_bytes());
+            digest.update(&buf[..len]);
+            chunk_digests.extend_from_slice(&digest.finalize());
+            chunk_count += 1;
+            if len < buf.len() {
+                break;
+            }
+        }
+    }
+
+    let mut digest = D::new();
+    digest.update([0x5a]);
+    digest.update(chunk_count.to_le_bytes());
+    digest.update(&chunk_digests);
+    Ok(digest.finalize().to_vec())
+}
+
+/// Fills `buf` as far as possible, returns less than its length only at the end of `r`.
+pub(crate) fn read_chunk<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match r.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+#[test]
+fn test_chunked_digest() {
+    // two chunks in the first section, an empty section and a short one
+    let first = vec![0x42; CHUNK_SIZE + 1];
+    let sections: Vec<Box<dyn Read>> = vec![Box::new(&first[..]), Box::new(&[][..]), Box::new(&b"abc"[..])];
+    let digest = chunked_digest::<Sha256>(sections).unwrap();
+
+    let chunk = |data: &[u8]| {
+        let mut d = Sha256::new();
+        d.update([0xa5]);
+        d.update((data.len() as u32).to_le_bytes());
+        d.update(data);
+        d.finalize()
+    };
+    let mut expected = Sha256::new();
+    expected.update([0x5a, 3, 0, 0, 0]);
+    expected.update(chunk(&first[..CHUNK_SIZE]));
+    expected.update(chunk(&first[CHUNK_SIZE..]));
+    expected.update(chunk(b"abc"));
+    assert_eq!(digest, expected.finalize().to_vec());
+}
@@ -0,0 +1,51 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Reasons for rejecting the signatures of an APK. Like `ZipError` it is returned wrapped in an
+/// `io::Error` and can be recovered with `get_ref` and `downcast_ref`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureError {
+    /// The APK signing block in front of the central directory is malformed.
+    InvalidSigningBlock,
+    /// The block of a signature scheme inside the APK signing block can not be parsed.
+    InvalidSchemeBlock { id: u32 },
+    /// An X.509 certificate can not be parsed.
+    InvalidCertificate,
+    /// An APK Signature Scheme v4 signature file can not be parsed.
+    InvalidV4Signature,
+    /// A private key is not an unencrypted PKCS#8 RSA, EC or DSA key or can not sign.
+    InvalidPrivateKey,
+    /// The private key does not belong to the signing certificate.
+    PrivateKeyMismatch,
+    /// The key can not sign with this v2 or later signature algorithm.
+    UnsupportedSignatureAlgorithm(u32),
+    /// APKs can not use ZIP64 records.
+    Zip64NotSupported,
+    /// The signing certificate lineage does not end with the signing certificate.
+    LineageMismatch,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::InvalidSigningBlock => write!(f, "invalid APK signing block"),
+            SignatureError::InvalidSchemeBlock { id } => write!(f, "invalid signature scheme block 0x{:08x}", id),
+            SignatureError::InvalidCertificate => write!(f, "invalid certificate"),
+            SignatureError::InvalidV4Signature => write!(f, "invalid v4 signature file"),
+            SignatureError::InvalidPrivateKey => write!(f, "invalid or unsupported private key"),
+            SignatureError::PrivateKeyMismatch => write!(f, "private key does not match the certificate"),
+            SignatureError::UnsupportedSignatureAlgorithm(id) => write!(f, "key can not sign with signature algorithm 0x{:04x}", id),
+            SignatureError::Zip64NotSupported => write!(f, "ZIP64 archives can not be signed"),
+            SignatureError::LineageMismatch => write!(f, "signing certificate lineage does not end with the signing certificate"),
+        }
+    }
+}
+
+impl Error for SignatureError {}
+
+impl From<SignatureError> for io::Error {
+    fn from(e: SignatureError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+/// A digest of a DER encoded certificate.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Fingerprint(Vec<u8>);
+
+impl Fingerprint {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Lowercase hex without separators, e.g. `a1b2c3`.
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Uppercase hex separated by colons as shown by keytool and developer consoles, e.g.
+    /// `A1:B2:C3`.
+    pub fn to_colon_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(&self.0)
+    }
+}
+
+/// Formats as colon separated hex.
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_colon_hex())
+    }
+}
+
+/// The fingerprints of a certificate asked for by app stores and API consoles.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CertificateFingerprints {
+    /// Only for legacy systems, MD5 is broken.
+    pub md5: Fingerprint,
+    pub sha1: Fingerprint,
+    pub sha256: Fingerprint,
+    pub sha512: Fingerprint,
+}
+
+impl CertificateFingerprints {
+    /// Computes the fingerprints of a DER encoded certificate.
+    pub fn new(der: &[u8]) -> CertificateFingerprints {
+        CertificateFingerprints {
+            md5: Fingerprint(Md5::digest(der).to_vec()),
+            sha1: Fingerprint(Sha1::digest(der).to_vec()),
+            sha256: Fingerprint(Sha256::digest(der).to_vec()),
+            sha512: Fingerprint(Sha512::digest(der).to_vec()),
+        }
+    }
+
+    /// The key hash Facebook login asks for, the base64 encoded SHA-1 fingerprint.
+    pub fn facebook_key_hash(&self) -> String {
+        self.sha1.to_base64()
+    }
+}
+
+#[test]
+fn test_fingerprint_formats() {
+    let fingerprints = CertificateFingerprints::new(b"abc");
+    assert_eq!(fingerprints.md5.to_hex(), "900150983cd24fb0d6963f7d28e17f72");
+    assert_eq!(fingerprints.sha1.to_colon_hex(), "A9:99:3E:36:47:06:81:6A:BA:3E:25:71:78:50:C2:6C:9C:D0:D8:9D");
+    assert_eq!(fingerprints.facebook_key_hash(), "qZk+NkcGgWq6PiVxeFDCbJzQ2J0=");
+    assert_eq!(fingerprints.sha256.to_hex(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_eq!(fingerprints.sha512.as_bytes().len(), 64);
+}
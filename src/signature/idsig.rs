@@ -0,0 +1,306 @@
+use std::io;
+use nom::*;
+use crate::zip::archive::ZipArchive;
+use crate::signature::block::SigningBlock;
+use crate::signature::certificate;
+use crate::signature::key;
+use crate::signature::signer::{write_bytes, SignatureAlgorithm, Signer};
+use crate::signature::verify::{SchemeReport, SignerReport, VerificationError};
+use crate::signature::verity;
+
+/// The only hash algorithm of the Merkle tree, SHA-256.
+pub const HASH_ALGORITHM_SHA256: u32 = 1;
+/// The only block size of the Merkle tree, 4096 bytes.
+pub const LOG2_BLOCK_SIZE_4096: u8 = 12;
+
+/// The APK Signature Scheme v4 signature of an `.apk.idsig` file. It signs the root hash of
+/// a Merkle tree over the whole APK together with the digest of the v2 or v3 signer, so that
+/// blocks streamed during an incremental install can be verified one by one.
+#[derive(Debug, Clone)]
+pub struct V4Signature {
+    pub version: u32,
+    pub hashing_info: HashingInfo,
+    pub signing_info: SigningInfo,
+    /// The Merkle tree as laid out by `verity::merkle_tree`, if the file contains it.
+    pub merkle_tree: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HashingInfo {
+    pub hash_algorithm: u32,
+    pub log2_block_size: u8,
+    pub salt: Vec<u8>,
+    pub root_hash: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SigningInfo {
+    /// The strongest content digest of the v3 or else v2 signer.
+    pub apk_digest: Vec<u8>,
+    /// DER encoded X.509 certificate of the signer.
+    pub certificate: Vec<u8>,
+    pub additional_data: Vec<u8>,
+    /// DER encoded SubjectPublicKeyInfo.
+    pub public_key: Vec<u8>,
+    pub signature_algorithm: SignatureAlgorithm,
+    pub signature: Vec<u8>,
+}
+
+named!(parse_hashing_info<&[u8], HashingInfo>, do_parse!(
+    hash_algorithm: le_u32 >>
+    log2_block_size: le_u8 >>
+    salt: length_bytes!(le_u32) >>
+    root_hash: length_bytes!(le_u32) >>
+    (HashingInfo {
+        hash_algorithm,
+        log2_block_size,
+        salt: salt.to_vec(),
+        root_hash: root_hash.to_vec(),
+    })
+));
+
+named!(parse_signing_info<&[u8], SigningInfo>, do_parse!(
+    apk_digest: length_bytes!(le_u32) >>
+    certificate: length_bytes!(le_u32) >>
+    additional_data: length_bytes!(le_u32) >>
+    public_key: length_bytes!(le_u32) >>
+    signature_algorithm: le_u32 >>
+    signature: length_bytes!(le_u32) >>
+    (SigningInfo {
+        apk_digest: apk_digest.to_vec(),
+        certificate: certificate.to_vec(),
+        additional_data: additional_data.to_vec(),
+        public_key: public_key.to_vec(),
+        signature_algorithm: signature_algorithm.into(),
+        signature: signature.to_vec(),
+    })
+));
+
+// newer versions append further signing infos for v3.1 signers, only the first one is read
+named!(parse_v4_signature<&[u8], V4Signature>, do_parse!(
+    version: verify!(le_u32, |v| v >= 2) >>
+    hashing_info: flat_map!(length_bytes!(le_u32), complete!(parse_hashing_info)) >>
+    signing_info: flat_map!(length_bytes!(le_u32), complete!(parse_signing_info)) >>
+    merkle_tree: opt!(complete!(length_bytes!(le_u32))) >>
+    (V4Signature {
+        version,
+        hashing_info,
+        signing_info,
+        merkle_tree: merkle_tree.map(|tree| tree.to_vec()),
+    })
+));
+
+impl V4Signature {
+    /// Parses the contents of an `.idsig` file.
+    pub fn parse(data: &[u8]) -> Option<V4Signature> {
+        match parse_v4_signature(data) {
+            IResult::Done([], signature) => Some(signature),
+            _ => None,
+        }
+    }
+
+    /// The data the signature is computed over for an APK of `file_size` bytes.
+    pub fn signed_data(&self, file_size: u64) -> Vec<u8> {
+        let hashing_info = &self.hashing_info;
+        let signing_info = &self.signing_info;
+        let mut data = Vec::new();
+        data.extend_from_slice(&file_size.to_le_bytes());
+        data.extend_from_slice(&hashing_info.hash_algorithm.to_le_bytes());
+        data.push(hashing_info.log2_block_size);
+        write_bytes(&mut data, &hashing_info.salt);
+        write_bytes(&mut data, &hashing_info.root_hash);
+        write_bytes(&mut data, &signing_info.apk_digest);
+        write_bytes(&mut data, &signing_info.certificate);
+        write_bytes(&mut data, &signing_info.additional_data);
+        // prefixed with its own size including the prefix
+        let mut signed_data = ((data.len() + 4) as u32).to_le_bytes().to_vec();
+        signed_data.extend_from_slice(&data);
+        signed_data
+    }
+}
+
+/// Preference of the content digests when picking the one a v4 signature refers to.
+fn digest_rank(algorithm: SignatureAlgorithm) -> u32 {
+    match algorithm {
+        SignatureAlgorithm::RsaPssSha512 | SignatureAlgorithm::RsaPkcs1Sha512 | SignatureAlgorithm::EcdsaSha512 => 3,
+        SignatureAlgorithm::VerityRsaPkcs1Sha256 | SignatureAlgorithm::VerityEcdsaSha256 | SignatureAlgorithm::VerityDsaSha256 => 2,
+        SignatureAlgorithm::Other(_) => 0,
+        _ => 1,
+    }
+}
+
+/// The strongest content digest of a v2 or v3 signer.
+fn best_digest(signer: &Signer) -> Option<&[u8]> {
+    signer.signed_data.digests.iter()
+        .filter(|digest| digest_rank(digest.algorithm) > 0)
+        .max_by_key(|digest| digest_rank(digest.algorithm))
+        .map(|digest| &digest.digest[..])
+}
+
+/// Verifies a v4 signature against an archive: the Merkle tree root over the APK, the digest
+/// and certificate of its v3 or else v2 signer and the signature itself. The v2 and v3
+/// signatures are not verified here, see `verify::verify`.
+pub fn verify(archive: &ZipArchive, signature: &V4Signature) -> io::Result<SchemeReport> {
+    let mut report = SchemeReport { signed: true, ..SchemeReport::default() };
+    let hashing_info = &signature.hashing_info;
+    let signing_info = &signature.signing_info;
+
+    let mut data_size = None;
+    if hashing_info.hash_algorithm != HASH_ALGORITHM_SHA256 || hashing_info.log2_block_size != LOG2_BLOCK_SIZE_4096 {
+        report.errors.push(VerificationError::UnsupportedHashingInfo);
+    } else {
+        let tree = verity::merkle_tree(archive.raw_range(0, u64::MAX)?, &hashing_info.salt)?;
+        if tree.root_hash != hashing_info.root_hash {
+            report.errors.push(VerificationError::RootHashMismatch);
+        } else if signature.merkle_tree.as_ref().is_some_and(|merkle_tree| *merkle_tree != tree.tree) {
+            report.errors.push(VerificationError::MerkleTreeMismatch);
+        }
+        data_size = Some(tree.data_size);
+    }
+
+    // a v3 block that can not be parsed is reported, the v2 signer may still match
+    let mut errors = Vec::new();
+    let signer = match SigningBlock::read(archive)? {
+        Some(block) => match block.v3_signers() {
+            Ok(Some(signers)) => signers.into_iter().next(),
+            v3_signers => {
+                if v3_signers.is_err() {
+                    errors.push(VerificationError::InvalidBlock);
+                }
+                match block.v2_signers() {
+                    Ok(signers) => signers.and_then(|signers| signers.into_iter().next()),
+                    Err(_) => {
+                        errors.push(VerificationError::InvalidBlock);
+                        None
+                    }
+                }
+            }
+        },
+        None => None,
+    };
+    match &signer {
+        Some(signer) => {
+            if signer.signed_data.certificates.first() != Some(&signing_info.certificate) {
+                errors.push(VerificationError::SignerMismatch);
+            }
+            if best_digest(signer) != Some(&signing_info.apk_digest[..]) {
+                errors.push(VerificationError::ApkDigestMismatch);
+            }
+        }
+        None => report.errors.push(VerificationError::NoSigners),
+    }
+
+    let info = certificate::parse_certificate_info(&signing_info.certificate);
+    if info.is_none_or(|info| info.public_key != signing_info.public_key) {
+        errors.push(VerificationError::PublicKeyMismatch);
+    }
+    if let Some(data_size) = data_size {
+        let signed_data = signature.signed_data(data_size);
+        if let Err(e) = key::verify_signature(&signing_info.public_key, signing_info.signature_algorithm, &signed_data, &signing_info.signature) {
+            errors.push(e);
+        }
+    }
+    report.signers.push(SignerReport {
+        certificates: vec![signing_info.certificate.clone()],
+        min_sdk_version: None,
+        max_sdk_version: None,
+        lineage: None,
+        errors,
+    });
+    Ok(report)
+}
+
+#[test]
+fn test_parse_v4_signature() {
+    let mut hashing_info = Vec::new();
+    hashing_info.extend_from_slice(&HASH_ALGORITHM_SHA256.to_le_bytes());
+    hashing_info.push(LOG2_BLOCK_SIZE_4096);
+    write_bytes(&mut hashing_info, b"");
+    write_bytes(&mut hashing_info, &[0x11; 32]);
+    let mut signing_info = Vec::new();
+    for field in [&b"digest"[..], b"cert", b"", b"key"] {
+        write_bytes(&mut signing_info, field);
+    }
+    signing_info.extend_from_slice(&0x0103u32.to_le_bytes());
+    write_bytes(&mut signing_info, b"sig");
+    let mut d = 2u32.to_le_bytes().to_vec();
+    write_bytes(&mut d, &hashing_info);
+    write_bytes(&mut d, &signing_info);
+
+    let signature = V4Signature::parse(&d).unwrap();
+    assert_eq!(signature.hashing_info.root_hash, [0x11; 32]);
+    assert_eq!(signature.signing_info.certificate, b"cert");
+    assert_eq!(signature.signing_info.signature_algorithm, SignatureAlgorithm::RsaPkcs1Sha256);
+    assert!(signature.merkle_tree.is_none());
+    let signed_data = signature.signed_data(5);
+    assert_eq!(signed_data.len(), 4 + 8 + 4 + 1 + 4 + 36 + 10 + 8 + 4);
+    assert_eq!(signed_data[..4], (signed_data.len() as u32).to_le_bytes());
+
+    write_bytes(&mut d, &[0; 4096]);
+    assert_eq!(V4Signature::parse(&d).unwrap().merkle_tree.map(|tree| tree.len()), Some(4096));
+}
+
+/// A v4 signature over an archive for the signer with the RSA fixture key.
+#[cfg(test)]
+fn sign_v4(data: &[u8], signer: &Signer) -> V4Signature {
+    let key = key::PrivateKey::from_pkcs8_der(include_bytes!("../../testdata/rsa.pk8")).unwrap();
+    let tree = verity::merkle_tree(data, b"").unwrap();
+    let mut signature = V4Signature {
+        version: 2,
+        hashing_info: HashingInfo {
+            hash_algorithm: HASH_ALGORITHM_SHA256,
+            log2_block_size: LOG2_BLOCK_SIZE_4096,
+            salt: Vec::new(),
+            root_hash: tree.root_hash,
+        },
+        signing_info: SigningInfo {
+            apk_digest: best_digest(signer).unwrap().to_vec(),
+            certificate: include_bytes!("../../testdata/rsa.der").to_vec(),
+            additional_data: Vec::new(),
+            public_key: key.public_key(),
+            signature_algorithm: SignatureAlgorithm::RsaPkcs1Sha256,
+            signature: Vec::new(),
+        },
+        merkle_tree: Some(tree.tree),
+    };
+    signature.signing_info.signature = key.sign(SignatureAlgorithm::RsaPkcs1Sha256, &signature.signed_data(tree.data_size)).unwrap();
+    signature
+}
+
+#[test]
+fn test_verify() {
+    let data = include_bytes!("../../testdata/v2v3-rsa.apk").to_vec();
+    let archive = ZipArchive::from_bytes(data.clone()).unwrap();
+    let block = SigningBlock::read(&archive).unwrap().unwrap();
+    let v2_signer = block.v2_signers().unwrap().unwrap().remove(0);
+    let v3_signer = block.v3_signers().unwrap().unwrap().remove(0);
+
+    let signature = sign_v4(&data, &v3_signer);
+    let report = verify(&archive, &signature).unwrap();
+    assert!(report.is_verified(), "{:?}", report);
+
+    let mut other_digest = signature.clone();
+    other_digest.signing_info.apk_digest[0] ^= 1;
+    let report = verify(&archive, &other_digest).unwrap();
+    assert_eq!(report.signers[0].errors, vec![VerificationError::ApkDigestMismatch, VerificationError::InvalidSignature(SignatureAlgorithm::RsaPkcs1Sha256)]);
+
+    let mut tampered = signature.clone();
+    tampered.signing_info.additional_data = b"tampered".to_vec();
+    let report = verify(&archive, &tampered).unwrap();
+    assert_eq!(report.signers[0].errors, vec![VerificationError::InvalidSignature(SignatureAlgorithm::RsaPkcs1Sha256)]);
+
+    // the last byte of the entries changed
+    let mut modified = data.clone();
+    modified[block.offset as usize - 1] ^= 1;
+    let report = verify(&ZipArchive::from_bytes(modified).unwrap(), &signature).unwrap();
+    assert_eq!(report.errors, vec![VerificationError::RootHashMismatch]);
+
+    // a v3 block that can not be parsed falls back to the v2 signer
+    let mut broken = data;
+    let id = crate::signature::block::SIGNATURE_SCHEME_V3_BLOCK_ID.to_le_bytes();
+    let position = broken.windows(4).position(|window| window == id).unwrap() + 4;
+    broken[position..position + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    let report = verify(&ZipArchive::from_bytes(broken.clone()).unwrap(), &sign_v4(&broken, &v2_signer)).unwrap();
+    assert!(report.errors.is_empty());
+    assert_eq!(report.signers[0].errors, vec![VerificationError::InvalidBlock]);
+}
@@ -0,0 +1,466 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::io::{self, Read};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use crate::zip::archive::ZipArchive;
+use crate::zip::limits::MAX_PREALLOCATION;
+use crate::signature::digest::DigestAlgorithm;
+use crate::signature::key::PrivateKey;
+use crate::signature::manifest::{self, Manifest, Section};
+use crate::signature::pkcs7;
+use crate::signature::verify::VerificationError;
+
+pub const MANIFEST_NAME: &str = "META-INF/MANIFEST.MF";
+
+/// Attribute of signature files listing the newer schemes the APK is also signed with.
+pub const APK_SIGNED_ATTRIBUTE: &str = "X-Android-APK-Signed";
+
+const SIGNATURE_BLOCK_EXTENSIONS: [&str; 3] = [".RSA", ".DSA", ".EC"];
+
+/// Outcome of verifying the JAR signature (v1) of an APK.
+#[derive(Debug, Clone, Default)]
+pub struct JarReport {
+    /// Whether the APK has a manifest or signature files.
+    pub signed: bool,
+    pub signers: Vec<JarSignerReport>,
+    /// Errors concerning the manifest and the entries rather than a single signer.
+    pub errors: Vec<VerificationError>,
+    /// Entries not protected by every signer, they could have been added or modified.
+    pub unsigned_entries: Vec<String>,
+}
+
+impl JarReport {
+    pub fn is_verified(&self) -> bool {
+        self.signed
+            && !self.signers.is_empty()
+            && self.errors.is_empty()
+            && self.unsigned_entries.is_empty()
+            && self.signers.iter().all(JarSignerReport::is_verified)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JarSignerReport {
+    /// The name of the signature file without `META-INF/` and extension, e.g. `CERT`.
+    pub name: String,
+    /// DER encoded X.509 certificates, starting with the one of the signer.
+    pub certificates: Vec<Vec<u8>>,
+    pub errors: Vec<VerificationError>,
+}
+
+impl JarSignerReport {
+    pub fn is_verified(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// A JAR signer, found as a signature block file in `META-INF/` named after the signer with
+/// `.RSA`, `.DSA` or `.EC` extension.
+#[derive(Debug, Clone)]
+pub struct JarSigner {
+    /// The name of the signature file without `META-INF/` and extension, e.g. `CERT`.
+    pub name: String,
+    /// Entry names of the signature file and the PKCS#7 signature block.
+    pub signature_file: String,
+    pub signature_block: String,
+    /// DER encoded X.509 certificates, starting with the one of the signer. Empty if the
+    /// signature block can not be parsed.
+    pub certificates: Vec<Vec<u8>>,
+}
+
+/// Whether an entry belongs to the JAR signature itself and is not covered by it.
+pub(crate) fn is_signature_entry(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    match name.strip_prefix("META-INF/") {
+        Some(file) if !file.contains('/') => {
+            name == MANIFEST_NAME
+                || file.starts_with("SIG-")
+                || file.ends_with(".SF")
+                || SIGNATURE_BLOCK_EXTENSIONS.iter().any(|extension| file.ends_with(extension))
+        }
+        _ => false,
+    }
+}
+
+fn read_entry(archive: &ZipArchive, name: &str) -> io::Result<Option<Vec<u8>>> {
+    match archive.by_name(name)? {
+        Some(entry) => {
+            let mut data = Vec::with_capacity(entry.len().min(MAX_PREALLOCATION));
+            entry.content()?.read_to_end(&mut data)?;
+            Ok(Some(data))
+        }
+        None => Ok(None),
+    }
+}
+
+/// The digests of a section for the attributes named `<algorithm><suffix>`, `None` if there
+/// are none with a supported algorithm.
+fn digest_attributes(section: &Section, suffix: &str) -> Option<Vec<(DigestAlgorithm, Vec<u8>)>> {
+    let digests: Vec<_> = section.attributes.iter()
+        .filter_map(|(name, value)| {
+            let split = name.len().checked_sub(suffix.len())?;
+            if !name.is_char_boundary(split) || !name[split..].eq_ignore_ascii_case(suffix) {
+                return None;
+            }
+            let algorithm = DigestAlgorithm::from_jar_name(&name[..split])?;
+            // undecodable digests can never match
+            Some((algorithm, BASE64.decode(value.trim()).unwrap_or_default()))
+        })
+        .collect();
+    if digests.is_empty() {
+        None
+    } else {
+        Some(digests)
+    }
+}
+
+fn matches(digests: &[(DigestAlgorithm, Vec<u8>)], data: &[u8]) -> bool {
+    digests.iter().all(|(algorithm, digest)| algorithm.digest(data) == *digest)
+}
+
+/// The signers of an archive ordered by name, their certificates are not loaded.
+fn find_signers(archive: &ZipArchive) -> Vec<JarSigner> {
+    let mut signers: Vec<_> = archive.files_under("META-INF/")
+        .map(|entry| entry.file_name())
+        .filter_map(|signature_block| {
+            let upper = signature_block.to_ascii_uppercase();
+            let extension = SIGNATURE_BLOCK_EXTENSIONS.iter().find(|extension| upper.ends_with(*extension))?;
+            let name = &signature_block["META-INF/".len()..signature_block.len() - extension.len()];
+            if name.contains('/') {
+                return None;
+            }
+            Some(JarSigner {
+                name: name.to_string(),
+                signature_file: format!("META-INF/{}.SF", name),
+                signature_block: signature_block.clone(),
+                certificates: Vec::new(),
+            })
+        })
+        .collect();
+    signers.sort_by(|a, b| a.name.cmp(&b.name));
+    signers
+}
+
+/// The JAR signers of an archive with their certificates, which are not verified.
+pub fn signers(archive: &ZipArchive) -> io::Result<Vec<JarSigner>> {
+    let mut signers = find_signers(archive);
+    for signer in &mut signers {
+        if let Some(block) = read_entry(archive, &signer.signature_block)? {
+            signer.certificates = pkcs7::parse_signed_data(&block)
+                .map(|signed_data| signed_data.signer_certificates())
+                .unwrap_or_default();
+        }
+    }
+    Ok(signers)
+}
+
+/// Verifies the JAR signature of an archive. `v2_signed` and `v3_signed` tell whether the
+/// newer schemes are present, for the stripping protection of signature files.
+pub fn verify(archive: &ZipArchive, v2_signed: bool, v3_signed: bool) -> io::Result<JarReport> {
+    let mut report = JarReport::default();
+    let manifest_data = read_entry(archive, MANIFEST_NAME)?;
+    let signers = find_signers(archive);
+    if manifest_data.is_none() && signers.is_empty() {
+        return Ok(report);
+    }
+    report.signed = true;
+
+    let manifest = match manifest_data.as_ref().map(|data| manifest::parse_manifest(data)) {
+        Some(Some(manifest)) => manifest,
+        Some(None) => {
+            report.errors.push(VerificationError::InvalidManifest);
+            return Ok(report);
+        }
+        None => {
+            report.errors.push(VerificationError::MissingManifest);
+            return Ok(report);
+        }
+    };
+    let manifest_data = manifest_data.unwrap_or_default();
+    if signers.is_empty() {
+        report.errors.push(VerificationError::NoSigners);
+    }
+
+    // the first section of each entry, later ones are reported
+    let mut sections = HashMap::new();
+    let mut unique_sections = Vec::new();
+    for section in &manifest.sections {
+        let name = match section.name() {
+            Some(name) => name,
+            None => continue,
+        };
+        match sections.entry(name) {
+            Entry::Occupied(_) => report.errors.push(VerificationError::DuplicateManifestSection(name.to_string())),
+            Entry::Vacant(vacant) => {
+                vacant.insert(section);
+                unique_sections.push((name, section));
+            }
+        }
+    }
+
+    let signed_schemes: Vec<u32> = [(2, v2_signed), (3, v3_signed)].iter()
+        .filter(|(_, signed)| *signed)
+        .map(|(scheme, _)| *scheme)
+        .collect();
+    // entries protected by each signer
+    let mut signed_entries = Vec::new();
+    for jar_signer in &signers {
+        let mut signer = JarSignerReport { name: jar_signer.name.clone(), certificates: Vec::new(), errors: Vec::new() };
+        let entries = verify_signer(archive, &manifest, &sections, &manifest_data, jar_signer, &signed_schemes, &mut signer)?;
+        signed_entries.push(entries);
+        report.signers.push(signer);
+    }
+
+    for (name, section) in unique_sections {
+        let digests = match digest_attributes(section, "-Digest") {
+            Some(digests) => digests,
+            None => continue,
+        };
+        let entry = match archive.by_name(name)? {
+            Some(entry) => entry,
+            None => {
+                report.errors.push(VerificationError::MissingEntry(name.to_string()));
+                continue;
+            }
+        };
+        for (algorithm, digest) in digests {
+            if algorithm.digest_reader(entry.content()?)? != digest {
+                report.errors.push(VerificationError::EntryDigestMismatch(name.to_string()));
+                break;
+            }
+        }
+    }
+
+    for entry in archive.files() {
+        let name = entry.file_name();
+        if name.ends_with('/') || is_signature_entry(&name) {
+            continue;
+        }
+        let covered = sections.get(&name[..]).is_some_and(|section| digest_attributes(section, "-Digest").is_some())
+            && !signed_entries.is_empty()
+            && signed_entries.iter().all(|entries| entries.contains(&name));
+        if !covered {
+            report.unsigned_entries.push(name);
+        }
+    }
+    Ok(report)
+}
+
+/// Generates the files of a JAR signature named `name` over the entries of an archive: the
+/// manifest with the digests of all entries but directories and signature files, the signature
+/// file with the digests of the manifest and its sections, and the PKCS#7 signature block.
+/// `signed_schemes` are announced for stripping protection. Returns entry names and contents.
+pub(crate) fn sign(
+    archive: &ZipArchive,
+    name: &str,
+    key: &PrivateKey,
+    certificates: &[Vec<u8>],
+    algorithm: DigestAlgorithm,
+    signed_schemes: &[u32],
+) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let digest_name = format!("{}-Digest", algorithm.jar_name());
+    let created_by = concat!(env!("CARGO_PKG_VERSION"), " (apk-rs)");
+
+    let mut entries: Vec<_> = archive.files()
+        .filter(|entry| {
+            let name = entry.file_name();
+            !name.ends_with('/') && !is_signature_entry(&name)
+        })
+        .collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    entries.dedup_by_key(|entry| entry.file_name());
+
+    let mut manifest = Vec::new();
+    manifest::write_attribute(&mut manifest, "Manifest-Version", "1.0");
+    manifest::write_attribute(&mut manifest, "Created-By", created_by);
+    manifest.extend_from_slice(b"\r\n");
+    let main_attributes_len = manifest.len();
+    let mut sections = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        let digest = algorithm.digest_reader(entry.content()?)?;
+        let mut section = Vec::new();
+        manifest::write_attribute(&mut section, "Name", &entry.file_name());
+        manifest::write_attribute(&mut section, &digest_name, &BASE64.encode(digest));
+        section.extend_from_slice(b"\r\n");
+        manifest.extend_from_slice(&section);
+        sections.push((entry.file_name(), section));
+    }
+
+    let mut signature_file = Vec::new();
+    manifest::write_attribute(&mut signature_file, "Signature-Version", "1.0");
+    manifest::write_attribute(&mut signature_file, "Created-By", created_by);
+    manifest::write_attribute(&mut signature_file, &format!("{}-Manifest", digest_name), &BASE64.encode(algorithm.digest(&manifest)));
+    manifest::write_attribute(
+        &mut signature_file,
+        &format!("{}-Manifest-Main-Attributes", digest_name),
+        &BASE64.encode(algorithm.digest(&manifest[..main_attributes_len])),
+    );
+    if !signed_schemes.is_empty() {
+        let schemes: Vec<_> = signed_schemes.iter().map(u32::to_string).collect();
+        manifest::write_attribute(&mut signature_file, APK_SIGNED_ATTRIBUTE, &schemes.join(", "));
+    }
+    signature_file.extend_from_slice(b"\r\n");
+    for (entry_name, section) in &sections {
+        manifest::write_attribute(&mut signature_file, "Name", entry_name);
+        manifest::write_attribute(&mut signature_file, &digest_name, &BASE64.encode(algorithm.digest(section)));
+        signature_file.extend_from_slice(b"\r\n");
+    }
+
+    let signature_block = pkcs7::encode_signed_data(key, certificates, algorithm, &signature_file)?;
+    Ok(vec![
+        (MANIFEST_NAME.to_string(), manifest),
+        (format!("META-INF/{}.SF", name), signature_file),
+        (format!("META-INF/{}{}", name, key.signature_block_extension()), signature_block),
+    ])
+}
+
+/// Verifies a signature file and its block against the manifest, whose sections are looked up
+/// by entry name in `sections`. Returns the names of the entries the signer protects.
+fn verify_signer(
+    archive: &ZipArchive,
+    manifest: &Manifest,
+    sections: &HashMap<&str, &Section>,
+    manifest_data: &[u8],
+    jar_signer: &JarSigner,
+    signed_schemes: &[u32],
+    signer: &mut JarSignerReport,
+) -> io::Result<HashSet<String>> {
+    let mut entries = HashSet::new();
+    let sf_data = match read_entry(archive, &jar_signer.signature_file)? {
+        Some(data) => data,
+        None => {
+            signer.errors.push(VerificationError::MissingSignatureFile);
+            return Ok(entries);
+        }
+    };
+    let block = read_entry(archive, &jar_signer.signature_block)?.unwrap_or_default();
+    match pkcs7::parse_signed_data(&block) {
+        Some(signed_data) => match signed_data.verify(&sf_data) {
+            Ok(certificates) => signer.certificates = certificates,
+            Err(e) => {
+                signer.certificates = signed_data.certificates;
+                signer.errors.push(e);
+                return Ok(entries);
+            }
+        },
+        None => {
+            signer.errors.push(VerificationError::InvalidSignatureBlock);
+            return Ok(entries);
+        }
+    }
+
+    // only a verified signature file is worth looking into
+    let sf = match manifest::parse_manifest(&sf_data) {
+        Some(sf) => sf,
+        None => {
+            signer.errors.push(VerificationError::InvalidSignatureFile);
+            return Ok(entries);
+        }
+    };
+    if let Some(schemes) = sf.main.get(APK_SIGNED_ATTRIBUTE) {
+        for scheme in schemes.split(',').filter_map(|scheme| scheme.trim().parse::<u32>().ok()) {
+            if (scheme == 2 || scheme == 3) && !signed_schemes.contains(&scheme) {
+                signer.errors.push(VerificationError::SchemeStripped(scheme));
+            }
+        }
+    }
+
+    // a digest of the whole manifest covers all its entries
+    if let Some(digests) = digest_attributes(&sf.main, "-Digest-Manifest") {
+        if matches(&digests, manifest_data) {
+            entries.extend(sections.keys().map(|name| name.to_string()));
+            return Ok(entries);
+        }
+    }
+    if let Some(digests) = digest_attributes(&sf.main, "-Digest-Manifest-Main-Attributes") {
+        if !matches(&digests, &manifest.main.raw) {
+            signer.errors.push(VerificationError::ManifestDigestMismatch);
+            return Ok(entries);
+        }
+    }
+    for section in &sf.sections {
+        let name = match section.name() {
+            Some(name) => name,
+            None => continue,
+        };
+        match (sections.get(name), digest_attributes(section, "-Digest")) {
+            (Some(manifest_section), Some(digests)) if matches(&digests, &manifest_section.raw) => {
+                entries.insert(name.to_string());
+            }
+            (None, _) => signer.errors.push(VerificationError::MissingManifestSection(name.to_string())),
+            _ => signer.errors.push(VerificationError::SectionDigestMismatch(name.to_string())),
+        }
+    }
+    Ok(entries)
+}
+
+#[test]
+fn test_is_signature_entry() {
+    assert!(is_signature_entry("META-INF/MANIFEST.MF"));
+    assert!(is_signature_entry("META-INF/ANDROIDD.RSA"));
+    assert!(is_signature_entry("META-INF/key0.ec"));
+    assert!(is_signature_entry("META-INF/CERT.SF"));
+    assert!(is_signature_entry("META-INF/SIG-FOO"));
+    assert!(!is_signature_entry("META-INF/services/CERT.RSA"));
+    assert!(!is_signature_entry("META-INF/kotlin.kotlin_module"));
+    assert!(!is_signature_entry("res/CERT.RSA"));
+}
+
+/// A copy of an archive with the content of one entry replaced.
+#[cfg(test)]
+fn replace_entry(archive: &ZipArchive, name: &str, data: &[u8]) -> ZipArchive {
+    use crate::zip::writer::ZipWriter;
+
+    let mut writer = ZipWriter::new(Vec::new());
+    for entry in archive.files() {
+        if entry.file_name() == name {
+            writer.add_stored(name, data).unwrap();
+        } else {
+            writer.copy_raw(&entry).unwrap();
+        }
+    }
+    ZipArchive::from_bytes(writer.finish().unwrap()).unwrap()
+}
+
+#[test]
+fn test_verify() {
+    let archive = ZipArchive::from_bytes(&include_bytes!("../../testdata/v1-rsa.apk")[..]).unwrap();
+    let report = verify(&archive, false, false).unwrap();
+    assert!(report.is_verified(), "{:?}", report);
+    assert_eq!(report.signers[0].name, "CERT");
+    assert_eq!(report.signers[0].certificates, vec![include_bytes!("../../testdata/rsa.der").to_vec()]);
+
+    // the signature still covers the manifest, but the entry no longer matches it
+    let report = verify(&replace_entry(&archive, "res/raw/hello.txt", b"Jello, world!\n"), false, false).unwrap();
+    assert!(!report.is_verified());
+    assert!(report.signers[0].is_verified());
+    assert_eq!(report.errors, vec![VerificationError::EntryDigestMismatch("res/raw/hello.txt".into())]);
+
+    // a changed digest in the manifest no longer matches the signature file
+    let mut manifest = read_entry(&archive, MANIFEST_NAME).unwrap().unwrap();
+    let digest_line = b"Name: classes.dex\r\nSHA-256-Digest: ";
+    let position = manifest.windows(digest_line.len()).position(|w| w == digest_line).unwrap() + digest_line.len();
+    manifest[position] = if manifest[position] == b'A' { b'B' } else { b'A' };
+    let report = verify(&replace_entry(&archive, MANIFEST_NAME, &manifest), false, false).unwrap();
+    assert_eq!(report.signers[0].errors, vec![VerificationError::SectionDigestMismatch("classes.dex".into())]);
+    assert_eq!(report.errors, vec![VerificationError::EntryDigestMismatch("classes.dex".into())]);
+    assert_eq!(report.unsigned_entries, vec!["classes.dex".to_string()]);
+
+    // a signature file announcing v2 while the APK has no v2 signature
+    let unsigned = ZipArchive::from_bytes(&include_bytes!("../../testdata/unsigned.apk")[..]).unwrap();
+    let key = PrivateKey::from_pkcs8_der(include_bytes!("../../testdata/rsa.pk8")).unwrap();
+    let certificates = vec![include_bytes!("../../testdata/rsa.der").to_vec()];
+    let files = sign(&unsigned, "CERT", &key, &certificates, DigestAlgorithm::Sha256, &[2]).unwrap();
+    let mut writer = crate::zip::writer::ZipWriter::new(Vec::new());
+    for (name, data) in files {
+        writer.add_deflated(&name, &data).unwrap();
+    }
+    for entry in unsigned.files() {
+        writer.copy_raw(&entry).unwrap();
+    }
+    let signed = ZipArchive::from_bytes(writer.finish().unwrap()).unwrap();
+    assert!(verify(&signed, true, false).unwrap().is_verified());
+    let report = verify(&signed, false, false).unwrap();
+    assert_eq!(report.signers[0].errors, vec![VerificationError::SchemeStripped(2)]);
+}
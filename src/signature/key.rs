@@ -0,0 +1,332 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use nom::IResult;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use rsa::{Pkcs1v15Sign, Pss, RsaPrivateKey, RsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePublicKey};
+use rsa::traits::PublicKeyParts;
+use p256::ecdsa::signature::SignatureEncoding;
+use p256::ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier};
+use crate::signature::error::SignatureError;
+use crate::signature::asn1;
+use crate::signature::digest::DigestAlgorithm;
+use crate::signature::signer::SignatureAlgorithm;
+use crate::signature::verify::VerificationError;
+
+pub const RSA_ENCRYPTION: &[u32] = &[1, 2, 840, 113549, 1, 1, 1];
+pub const EC_PUBLIC_KEY: &[u32] = &[1, 2, 840, 10045, 2, 1];
+pub const DSA: &[u32] = &[1, 2, 840, 10040, 4, 1];
+pub const SECP256R1: &[u32] = &[1, 2, 840, 10045, 3, 1, 7];
+pub const SECP384R1: &[u32] = &[1, 3, 132, 0, 34];
+pub const SECP521R1: &[u32] = &[1, 3, 132, 0, 35];
+pub const ECDSA_WITH_SHA1: &[u32] = &[1, 2, 840, 10045, 4, 1];
+pub const ECDSA_WITH_SHA256: &[u32] = &[1, 2, 840, 10045, 4, 3, 2];
+pub const ECDSA_WITH_SHA384: &[u32] = &[1, 2, 840, 10045, 4, 3, 3];
+pub const ECDSA_WITH_SHA512: &[u32] = &[1, 2, 840, 10045, 4, 3, 4];
+pub const DSA_WITH_SHA1: &[u32] = &[1, 2, 840, 10040, 4, 3];
+pub const DSA_WITH_SHA256: &[u32] = &[2, 16, 840, 1, 101, 3, 4, 3, 2];
+
+/// The algorithm and parameters of a DER encoded SubjectPublicKeyInfo, along with the key.
+pub struct PublicKeyInfo {
+    pub algorithm: Vec<u32>,
+    /// The named curve of EC keys.
+    pub curve: Option<Vec<u32>>,
+    /// Content octets of the algorithm parameters, like p, q and g of DSA keys.
+    pub parameters: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+pub fn parse_public_key_info(public_key: &[u8]) -> Option<PublicKeyInfo> {
+    let spki = match asn1::parse_data_element(public_key) {
+        IResult::Done(_, spki) => spki,
+        _ => return None,
+    };
+    let elements = spki.parse_data().to_full_result().ok()?;
+    let algorithm_identifier = elements.first()?.parse_data().to_full_result().ok()?;
+    let algorithm = asn1::decode_object_identifier(algorithm_identifier.first()?.data())?;
+    let curve = match algorithm_identifier.get(1).filter(|parameters| parameters.id() == 6) {
+        Some(parameters) => Some(asn1::decode_object_identifier(parameters.data())?),
+        None => None,
+    };
+    let parameters = algorithm_identifier.get(1).map(|parameters| parameters.data().clone()).unwrap_or_default();
+    // the bit string starts with the number of unused bits
+    let key = elements.get(1)?.data().get(1..)?.to_vec();
+    Some(PublicKeyInfo { algorithm, curve, parameters, key })
+}
+
+/// Verifies a v2 or later signature over `data` with a DER encoded SubjectPublicKeyInfo.
+pub fn verify_signature(public_key: &[u8], algorithm: SignatureAlgorithm, data: &[u8], signature: &[u8]) -> Result<(), VerificationError> {
+    let info = parse_public_key_info(public_key).ok_or(VerificationError::InvalidPublicKey)?;
+    let valid = match algorithm {
+        SignatureAlgorithm::RsaPkcs1Sha256 | SignatureAlgorithm::VerityRsaPkcs1Sha256 if info.algorithm == RSA_ENCRYPTION => {
+            let key = RsaPublicKey::from_public_key_der(public_key).map_err(|_| VerificationError::InvalidPublicKey)?;
+            key.verify(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(data), signature).is_ok()
+        }
+        SignatureAlgorithm::RsaPkcs1Sha512 if info.algorithm == RSA_ENCRYPTION => {
+            let key = RsaPublicKey::from_public_key_der(public_key).map_err(|_| VerificationError::InvalidPublicKey)?;
+            key.verify(Pkcs1v15Sign::new::<Sha512>(), &Sha512::digest(data), signature).is_ok()
+        }
+        SignatureAlgorithm::RsaPssSha256 if info.algorithm == RSA_ENCRYPTION => {
+            let key = RsaPublicKey::from_public_key_der(public_key).map_err(|_| VerificationError::InvalidPublicKey)?;
+            key.verify(Pss::new::<Sha256>(), &Sha256::digest(data), signature).is_ok()
+        }
+        SignatureAlgorithm::RsaPssSha512 if info.algorithm == RSA_ENCRYPTION => {
+            let key = RsaPublicKey::from_public_key_der(public_key).map_err(|_| VerificationError::InvalidPublicKey)?;
+            key.verify(Pss::new::<Sha512>(), &Sha512::digest(data), signature).is_ok()
+        }
+        SignatureAlgorithm::EcdsaSha256 | SignatureAlgorithm::VerityEcdsaSha256 if info.algorithm == EC_PUBLIC_KEY => {
+            verify_ecdsa(&info, &Sha256::digest(data), signature)?
+        }
+        SignatureAlgorithm::EcdsaSha512 if info.algorithm == EC_PUBLIC_KEY => {
+            verify_ecdsa(&info, &Sha512::digest(data), signature)?
+        }
+        SignatureAlgorithm::DsaSha256 | SignatureAlgorithm::VerityDsaSha256 if info.algorithm == DSA => {
+            let key = dsa::VerifyingKey::from_public_key_der(public_key).map_err(|_| VerificationError::InvalidPublicKey)?;
+            dsa::Signature::try_from(signature)
+                .and_then(|signature| key.verify_prehash(&Sha256::digest(data), &signature))
+                .is_ok()
+        }
+        SignatureAlgorithm::Other(_) => return Err(VerificationError::UnsupportedAlgorithm(algorithm)),
+        _ => return Err(VerificationError::InvalidPublicKey),
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(VerificationError::InvalidSignature(algorithm))
+    }
+}
+
+/// Verifies a signature over a message digest as found in PKCS#7 signer infos, RSA keys use
+/// PKCS#1 v1.5 padding. The kind of key decides the signature algorithm.
+pub fn verify_digest(public_key: &[u8], algorithm: DigestAlgorithm, digest: &[u8], signature: &[u8]) -> Result<bool, VerificationError> {
+    let info = parse_public_key_info(public_key).ok_or(VerificationError::InvalidPublicKey)?;
+    match &info.algorithm[..] {
+        RSA_ENCRYPTION => {
+            let key = RsaPublicKey::from_public_key_der(public_key).map_err(|_| VerificationError::InvalidPublicKey)?;
+            let padding = match algorithm {
+                DigestAlgorithm::Sha1 => Pkcs1v15Sign::new::<Sha1>(),
+                DigestAlgorithm::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+                DigestAlgorithm::Sha384 => Pkcs1v15Sign::new::<Sha384>(),
+                DigestAlgorithm::Sha512 => Pkcs1v15Sign::new::<Sha512>(),
+            };
+            Ok(key.verify(padding, digest, signature).is_ok())
+        }
+        EC_PUBLIC_KEY => verify_ecdsa(&info, digest, signature),
+        DSA => {
+            let key = dsa::VerifyingKey::from_public_key_der(public_key).map_err(|_| VerificationError::InvalidPublicKey)?;
+            Ok(dsa::Signature::try_from(signature).and_then(|signature| key.verify_prehash(digest, &signature)).is_ok())
+        }
+        _ => Err(VerificationError::InvalidPublicKey),
+    }
+}
+
+/// A private key to sign APKs with. RSA, EC keys on the NIST P-256, P-384 and P-521 curves
+/// and DSA keys are supported.
+pub struct PrivateKey(PrivateKeyKind);
+
+enum PrivateKeyKind {
+    Rsa(RsaPrivateKey),
+    P256(p256::ecdsa::SigningKey),
+    P384(p384::ecdsa::SigningKey),
+    P521(p521::SecretKey),
+    Dsa(dsa::SigningKey),
+}
+
+impl fmt::Debug for PrivateKey {
+    // never prints the key material
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.0 {
+            PrivateKeyKind::Rsa(_) => "RSA",
+            PrivateKeyKind::P256(_) => "EC P-256",
+            PrivateKeyKind::P384(_) => "EC P-384",
+            PrivateKeyKind::P521(_) => "EC P-521",
+            PrivateKeyKind::Dsa(_) => "DSA",
+        };
+        write!(f, "PrivateKey({})", kind)
+    }
+}
+
+impl PrivateKey {
+    /// Parses an unencrypted DER encoded PKCS#8 PrivateKeyInfo.
+    pub fn from_pkcs8_der(der: &[u8]) -> io::Result<PrivateKey> {
+        let kind = if let Ok(key) = RsaPrivateKey::from_pkcs8_der(der) {
+            PrivateKeyKind::Rsa(key)
+        } else if let Ok(key) = p256::SecretKey::from_pkcs8_der(der) {
+            PrivateKeyKind::P256(key.into())
+        } else if let Ok(key) = p384::SecretKey::from_pkcs8_der(der) {
+            PrivateKeyKind::P384(key.into())
+        } else if let Ok(key) = p521::SecretKey::from_pkcs8_der(der) {
+            PrivateKeyKind::P521(key)
+        } else if let Ok(key) = dsa::SigningKey::from_pkcs8_der(der) {
+            PrivateKeyKind::Dsa(key)
+        } else {
+            return Err(SignatureError::InvalidPrivateKey.into());
+        };
+        Ok(PrivateKey(kind))
+    }
+
+    /// The DER encoded SubjectPublicKeyInfo of the key.
+    pub fn public_key(&self) -> Vec<u8> {
+        let der = match &self.0 {
+            PrivateKeyKind::Rsa(key) => RsaPublicKey::from(key).to_public_key_der(),
+            PrivateKeyKind::P256(key) => key.verifying_key().to_public_key_der(),
+            PrivateKeyKind::P384(key) => key.verifying_key().to_public_key_der(),
+            PrivateKeyKind::P521(key) => key.public_key().to_public_key_der(),
+            PrivateKeyKind::Dsa(key) => key.verifying_key().to_public_key_der(),
+        };
+        // encoding a valid key does not fail
+        der.map(|der| der.as_bytes().to_vec()).unwrap_or_default()
+    }
+
+    /// The v2 and later signature algorithm apksigner picks for the key: SHA-512 for RSA keys
+    /// over 3072 bits and EC keys over 256 bits, SHA-256 otherwise.
+    pub fn signature_algorithm(&self) -> SignatureAlgorithm {
+        match &self.0 {
+            PrivateKeyKind::Rsa(key) if key.size() > 384 => SignatureAlgorithm::RsaPkcs1Sha512,
+            PrivateKeyKind::Rsa(_) => SignatureAlgorithm::RsaPkcs1Sha256,
+            PrivateKeyKind::P256(_) => SignatureAlgorithm::EcdsaSha256,
+            PrivateKeyKind::P384(_) | PrivateKeyKind::P521(_) => SignatureAlgorithm::EcdsaSha512,
+            PrivateKeyKind::Dsa(_) => SignatureAlgorithm::DsaSha256,
+        }
+    }
+
+    /// The object identifier of the signature algorithm PKCS#7 signer infos name for the key
+    /// and digest, and whether it takes NULL parameters.
+    pub(crate) fn digest_encryption_algorithm(&self, algorithm: DigestAlgorithm) -> (&'static [u32], bool) {
+        match (&self.0, algorithm) {
+            (PrivateKeyKind::Rsa(_), _) => (RSA_ENCRYPTION, true),
+            (PrivateKeyKind::Dsa(_), DigestAlgorithm::Sha1) => (DSA_WITH_SHA1, false),
+            (PrivateKeyKind::Dsa(_), _) => (DSA_WITH_SHA256, false),
+            (_, DigestAlgorithm::Sha1) => (ECDSA_WITH_SHA1, false),
+            (_, DigestAlgorithm::Sha256) => (ECDSA_WITH_SHA256, false),
+            (_, DigestAlgorithm::Sha384) => (ECDSA_WITH_SHA384, false),
+            (_, DigestAlgorithm::Sha512) => (ECDSA_WITH_SHA512, false),
+        }
+    }
+
+    /// The extension of JAR signature block files for the key.
+    pub(crate) fn signature_block_extension(&self) -> &'static str {
+        match self.0 {
+            PrivateKeyKind::Rsa(_) => ".RSA",
+            PrivateKeyKind::Dsa(_) => ".DSA",
+            _ => ".EC",
+        }
+    }
+
+    /// The digest of JAR signatures for APKs supporting `min_sdk_version`, like apksigner: SHA-1
+    /// where older Android versions lack SHA-256 with the key type. With RSA that is up to
+    /// Android 4.2, with EC and DSA up to Android 4.4.
+    pub(crate) fn jar_digest_algorithm(&self, min_sdk_version: u32) -> DigestAlgorithm {
+        let sha256_min_sdk_version = match self.0 {
+            PrivateKeyKind::Rsa(_) => 18,
+            _ => 21,
+        };
+        if min_sdk_version < sha256_min_sdk_version {
+            DigestAlgorithm::Sha1
+        } else {
+            DigestAlgorithm::Sha256
+        }
+    }
+
+    /// Signs `data` with a v2 or later signature algorithm matching the key. RSA-PSS is not
+    /// supported as it needs randomness, the other algorithms are deterministic.
+    pub fn sign(&self, algorithm: SignatureAlgorithm, data: &[u8]) -> io::Result<Vec<u8>> {
+        let (digest_algorithm, rsa) = match algorithm {
+            SignatureAlgorithm::RsaPkcs1Sha256 | SignatureAlgorithm::VerityRsaPkcs1Sha256 => (DigestAlgorithm::Sha256, true),
+            SignatureAlgorithm::RsaPkcs1Sha512 => (DigestAlgorithm::Sha512, true),
+            SignatureAlgorithm::EcdsaSha256
+            | SignatureAlgorithm::VerityEcdsaSha256
+            | SignatureAlgorithm::DsaSha256
+            | SignatureAlgorithm::VerityDsaSha256 => (DigestAlgorithm::Sha256, false),
+            SignatureAlgorithm::EcdsaSha512 => (DigestAlgorithm::Sha512, false),
+            _ => return Err(SignatureError::UnsupportedSignatureAlgorithm(algorithm.into()).into()),
+        };
+        let dsa = matches!(algorithm, SignatureAlgorithm::DsaSha256 | SignatureAlgorithm::VerityDsaSha256);
+        let matches_key = match self.0 {
+            PrivateKeyKind::Rsa(_) => rsa,
+            PrivateKeyKind::Dsa(_) => dsa,
+            _ => !rsa && !dsa,
+        };
+        if !matches_key {
+            return Err(SignatureError::UnsupportedSignatureAlgorithm(algorithm.into()).into());
+        }
+        self.sign_digest(digest_algorithm, &digest_algorithm.digest(data))
+    }
+
+    /// Signs a message digest as PKCS#7 signer infos do, RSA keys use PKCS#1 v1.5 padding.
+    /// ECDSA and DSA signatures are DER encoded.
+    pub(crate) fn sign_digest(&self, algorithm: DigestAlgorithm, digest: &[u8]) -> io::Result<Vec<u8>> {
+        let signature = match &self.0 {
+            PrivateKeyKind::Rsa(key) => {
+                let padding = match algorithm {
+                    DigestAlgorithm::Sha1 => Pkcs1v15Sign::new::<Sha1>(),
+                    DigestAlgorithm::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+                    DigestAlgorithm::Sha384 => Pkcs1v15Sign::new::<Sha384>(),
+                    DigestAlgorithm::Sha512 => Pkcs1v15Sign::new::<Sha512>(),
+                };
+                key.sign(padding, digest).ok()
+            }
+            PrivateKeyKind::P256(key) => PrehashSigner::<p256::ecdsa::Signature>::sign_prehash(key, digest)
+                .ok().map(|signature| signature.to_der().to_vec()),
+            PrivateKeyKind::P384(key) => PrehashSigner::<p384::ecdsa::Signature>::sign_prehash(key, &ecdsa_prehash(digest, 48))
+                .ok().map(|signature| signature.to_der().to_vec()),
+            PrivateKeyKind::P521(key) => p521::ecdsa::SigningKey::from_bytes(&key.to_bytes()).ok()
+                .and_then(|key| PrehashSigner::<p521::ecdsa::Signature>::sign_prehash(&key, &ecdsa_prehash(digest, 66)).ok())
+                .map(|signature| signature.to_der().to_vec()),
+            PrivateKeyKind::Dsa(key) => key.sign_prehash(digest).ok().map(|signature| signature.to_vec()),
+        };
+        signature.ok_or_else(|| SignatureError::InvalidPrivateKey.into())
+    }
+}
+
+/// Left pads a digest shorter than the field, which ECDSA takes as the same integer, as the
+/// ecdsa crate refuses digests below half the field size, e.g. SHA-256 with P-521.
+fn ecdsa_prehash(digest: &[u8], field_size: usize) -> Cow<'_, [u8]> {
+    if digest.len() < field_size {
+        let mut padded = vec![0; field_size - digest.len()];
+        padded.extend_from_slice(digest);
+        Cow::Owned(padded)
+    } else {
+        Cow::Borrowed(digest)
+    }
+}
+
+/// Verifies a DER encoded ECDSA signature over a message digest, the curve is taken from the
+/// key.
+fn verify_ecdsa(info: &PublicKeyInfo, digest: &[u8], signature: &[u8]) -> Result<bool, VerificationError> {
+    let valid = match info.curve.as_deref() {
+        Some(SECP256R1) => {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&info.key).map_err(|_| VerificationError::InvalidPublicKey)?;
+            p256::ecdsa::Signature::from_der(signature).and_then(|signature| key.verify_prehash(digest, &signature)).is_ok()
+        }
+        Some(SECP384R1) => {
+            let key = p384::ecdsa::VerifyingKey::from_sec1_bytes(&info.key).map_err(|_| VerificationError::InvalidPublicKey)?;
+            p384::ecdsa::Signature::from_der(signature).and_then(|signature| key.verify_prehash(&ecdsa_prehash(digest, 48), &signature)).is_ok()
+        }
+        Some(SECP521R1) => {
+            let key = p521::ecdsa::VerifyingKey::from_sec1_bytes(&info.key).map_err(|_| VerificationError::InvalidPublicKey)?;
+            p521::ecdsa::Signature::from_der(signature).and_then(|signature| key.verify_prehash(&ecdsa_prehash(digest, 66), &signature)).is_ok()
+        }
+        _ => return Err(VerificationError::InvalidPublicKey),
+    };
+    Ok(valid)
+}
+
+#[test]
+fn test_parse_public_key_info() {
+    let key = PrivateKey::from_pkcs8_der(include_bytes!("../../testdata/ec.pk8")).unwrap();
+    let mut public_key = key.public_key();
+    let info = parse_public_key_info(&public_key).unwrap();
+    assert_eq!(info.algorithm, [1, 2, 840, 10045, 2, 1]);
+    assert_eq!(info.curve.as_deref(), Some(&[1, 2, 840, 10045, 3, 1, 7][..]));
+
+    // the same length curve identifier with an arc overflowing u32
+    let curve = asn1::encode_object_identifier(&[1, 2, 840, 10045, 3, 1, 7]);
+    let position = public_key.windows(curve.len()).position(|window| window == &curve[..]).unwrap();
+    public_key[position + 1..position + curve.len()].copy_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+    assert!(parse_public_key_info(&public_key).is_none());
+}
@@ -0,0 +1,286 @@
+use std::io;
+use nom::*;
+use crate::signature::certificate;
+use crate::signature::error::SignatureError;
+use crate::signature::key::{self, PrivateKey};
+use crate::signature::signer::{write_bytes, SignatureAlgorithm};
+use crate::signature::verify::VerificationError;
+
+/// Additional attribute of v3 signers holding the proof-of-rotation structure.
+pub const PROOF_OF_ROTATION_ATTRIBUTE_ID: u32 = 0x3ba0_6f8c;
+
+const LINEAGE_VERSION: u32 = 1;
+
+/// What an app signed with a later certificate of the lineage grants to a past certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities(pub u32);
+
+impl Default for Capabilities {
+    /// Everything but rollback, like apksigner grants.
+    fn default() -> Capabilities {
+        Capabilities(Capabilities::INSTALLED_DATA | Capabilities::SHARED_USER_ID | Capabilities::PERMISSION | Capabilities::AUTH)
+    }
+}
+
+impl Capabilities {
+    pub const INSTALLED_DATA: u32 = 1;
+    pub const SHARED_USER_ID: u32 = 2;
+    pub const PERMISSION: u32 = 4;
+    pub const ROLLBACK: u32 = 8;
+    pub const AUTH: u32 = 16;
+
+    /// Updates signed with the past certificate may keep the installed data.
+    pub fn installed_data(self) -> bool {
+        self.0 & Capabilities::INSTALLED_DATA != 0
+    }
+
+    /// Apps signed with the past certificate may share the user ID.
+    pub fn shared_user_id(self) -> bool {
+        self.0 & Capabilities::SHARED_USER_ID != 0
+    }
+
+    /// Apps signed with the past certificate are granted signature permissions.
+    pub fn permission(self) -> bool {
+        self.0 & Capabilities::PERMISSION != 0
+    }
+
+    /// Updates may go back to the past certificate.
+    pub fn rollback(self) -> bool {
+        self.0 & Capabilities::ROLLBACK != 0
+    }
+
+    /// The past certificate is accepted for authenticating the app, e.g. by account services.
+    pub fn auth(self) -> bool {
+        self.0 & Capabilities::AUTH != 0
+    }
+}
+
+/// A certificate in the lineage, signed by the key of the previous one.
+#[derive(Debug, Clone)]
+pub struct LineageNode {
+    /// DER encoded X.509 certificate.
+    pub certificate: Vec<u8>,
+    /// The algorithm the previous certificate signed this node with, `None` for the first.
+    pub parent_signature_algorithm: Option<SignatureAlgorithm>,
+    /// The algorithm this certificate signs the next node with.
+    pub signature_algorithm: SignatureAlgorithm,
+    pub capabilities: Capabilities,
+    signed_data: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+/// The certificates an app was signed with over time, oldest first, as proven by key rotation.
+#[derive(Debug, Clone)]
+pub struct SigningCertificateLineage {
+    pub nodes: Vec<LineageNode>,
+}
+
+struct RawNode<'a> {
+    signed_data: &'a [u8],
+    certificate: &'a [u8],
+    signed_algorithm: u32,
+    flags: u32,
+    algorithm: u32,
+    signature: &'a [u8],
+}
+
+named!(parse_node<&[u8], RawNode<'_>>, do_parse!(
+    signed_data: length_bytes!(le_u32) >>
+    flags: le_u32 >>
+    algorithm: le_u32 >>
+    signature: length_bytes!(le_u32) >>
+    content: expr_res!(parse_node_signed_data(signed_data).to_full_result()) >>
+    (RawNode {
+        signed_data,
+        certificate: content.0,
+        signed_algorithm: content.1,
+        flags,
+        algorithm,
+        signature,
+    })
+));
+
+named!(parse_node_signed_data<&[u8], (&[u8], u32)>, do_parse!(
+    certificate: length_bytes!(le_u32) >>
+    algorithm: le_u32 >>
+    ((certificate, algorithm))
+));
+
+/// Fails unless `key` belongs to `certificate`.
+fn check_key(key: &PrivateKey, certificate: &[u8]) -> io::Result<()> {
+    let info = certificate::parse_certificate_info(certificate).ok_or(SignatureError::InvalidCertificate)?;
+    if info.public_key != key.public_key() {
+        return Err(SignatureError::PrivateKeyMismatch.into());
+    }
+    Ok(())
+}
+
+impl LineageNode {
+    fn new(certificate: Vec<u8>, parent_signature_algorithm: Option<SignatureAlgorithm>, signature_algorithm: SignatureAlgorithm) -> LineageNode {
+        let mut signed_data = Vec::new();
+        write_bytes(&mut signed_data, &certificate);
+        signed_data.extend_from_slice(&parent_signature_algorithm.map_or(0, u32::from).to_le_bytes());
+        LineageNode {
+            certificate,
+            parent_signature_algorithm,
+            signature_algorithm,
+            capabilities: Capabilities::default(),
+            signed_data,
+            signature: Vec::new(),
+        }
+    }
+}
+
+impl SigningCertificateLineage {
+    /// Starts a lineage with the original signing certificate and its key.
+    pub fn new(key: &PrivateKey, certificate: Vec<u8>) -> io::Result<SigningCertificateLineage> {
+        check_key(key, &certificate)?;
+        let node = LineageNode::new(certificate, None, key.signature_algorithm());
+        Ok(SigningCertificateLineage { nodes: vec![node] })
+    }
+
+    /// Rotates from the current certificate, whose key is `key`, to `certificate` with
+    /// `new_key`. The new certificate is signed with the current key.
+    pub fn rotate(&mut self, key: &PrivateKey, new_key: &PrivateKey, certificate: Vec<u8>) -> io::Result<()> {
+        let parent = self.nodes.last().ok_or(SignatureError::LineageMismatch)?;
+        check_key(key, &parent.certificate)?;
+        check_key(new_key, &certificate)?;
+        let mut node = LineageNode::new(certificate, Some(parent.signature_algorithm), new_key.signature_algorithm());
+        node.signature = key.sign(parent.signature_algorithm, &node.signed_data)?;
+        self.nodes.push(node);
+        Ok(())
+    }
+
+    /// Encodes the lineage as the value of the proof-of-rotation attribute.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = LINEAGE_VERSION.to_le_bytes().to_vec();
+        for node in &self.nodes {
+            let mut encoded = Vec::new();
+            write_bytes(&mut encoded, &node.signed_data);
+            encoded.extend_from_slice(&node.capabilities.0.to_le_bytes());
+            encoded.extend_from_slice(&u32::from(node.signature_algorithm).to_le_bytes());
+            write_bytes(&mut encoded, &node.signature);
+            write_bytes(&mut data, &encoded);
+        }
+        data
+    }
+
+    /// Parses the value of the proof-of-rotation attribute.
+    pub fn parse(data: &[u8]) -> Option<SigningCertificateLineage> {
+        let (mut data, version) = match le_u32(data) {
+            IResult::Done(rest, version) => (rest, version),
+            _ => return None,
+        };
+        if version != LINEAGE_VERSION {
+            return None;
+        }
+        let mut nodes = Vec::new();
+        let mut previous_algorithm = None;
+        while !data.is_empty() {
+            let (remaining, node) = match length_bytes!(data, le_u32) {
+                IResult::Done(remaining, node) => (remaining, node),
+                _ => return None,
+            };
+            let node = parse_node(node).to_full_result().ok()?;
+            nodes.push(LineageNode {
+                certificate: node.certificate.to_vec(),
+                parent_signature_algorithm: previous_algorithm.map(|_| node.signed_algorithm.into()),
+                signature_algorithm: node.algorithm.into(),
+                capabilities: Capabilities(node.flags),
+                signed_data: node.signed_data.to_vec(),
+                signature: node.signature.to_vec(),
+            });
+            previous_algorithm = Some(node.algorithm);
+            data = remaining;
+        }
+        Some(SigningCertificateLineage { nodes })
+    }
+
+    /// Verifies that every certificate was signed by the key of the previous one.
+    pub fn verify(&self) -> Result<(), VerificationError> {
+        if self.nodes.is_empty() {
+            return Err(VerificationError::InvalidLineage);
+        }
+        for pair in self.nodes.windows(2) {
+            let (parent, node) = (&pair[0], &pair[1]);
+            if node.parent_signature_algorithm != Some(parent.signature_algorithm) {
+                return Err(VerificationError::InvalidLineage);
+            }
+            let parent_certificate = certificate::parse_certificate_info(&parent.certificate).ok_or(VerificationError::InvalidLineage)?;
+            key::verify_signature(&parent_certificate.public_key, parent.signature_algorithm, &node.signed_data, &node.signature)
+                .map_err(|_| VerificationError::InvalidLineage)?;
+        }
+        Ok(())
+    }
+
+    /// The current certificate, the last one of the lineage.
+    pub fn current(&self) -> Option<&[u8]> {
+        self.nodes.last().map(|node| &node.certificate[..])
+    }
+
+    /// The node of a certificate, to look up what it is still trusted with.
+    pub fn find(&self, certificate: &[u8]) -> Option<&LineageNode> {
+        self.nodes.iter().find(|node| node.certificate == certificate)
+    }
+}
+
+#[test]
+fn test_parse_lineage() {
+    use crate::signature::signer::length_prefixed as lp;
+
+    let node = |certificate: &[u8], signed_algorithm: u32, flags: u32, algorithm: u32, signature: &[u8]| {
+        let mut signed_data = lp(certificate);
+        signed_data.extend_from_slice(&signed_algorithm.to_le_bytes());
+        let mut node = lp(&signed_data);
+        node.extend_from_slice(&flags.to_le_bytes());
+        node.extend_from_slice(&algorithm.to_le_bytes());
+        node.extend_from_slice(&lp(signature));
+        lp(&node)
+    };
+    let mut d = 1u32.to_le_bytes().to_vec();
+    d.extend_from_slice(&node(b"old", 0, 0x1f, 0x0103, b""));
+    d.extend_from_slice(&node(b"new", 0x0103, 0x17, 0x0201, b"sig"));
+    let lineage = SigningCertificateLineage::parse(&d).unwrap();
+    assert_eq!(lineage.nodes.len(), 2);
+    assert_eq!(lineage.nodes[0].parent_signature_algorithm, None);
+    assert_eq!(lineage.nodes[1].parent_signature_algorithm, Some(SignatureAlgorithm::RsaPkcs1Sha256));
+    assert_eq!(lineage.nodes[1].signature_algorithm, SignatureAlgorithm::EcdsaSha256);
+    assert!(lineage.nodes[0].capabilities.rollback());
+    assert!(!lineage.nodes[1].capabilities.rollback());
+    assert_eq!(lineage.current(), Some(&b"new"[..]));
+
+    assert_eq!(lineage.encode(), d);
+
+    d[0] = 2;
+    assert!(SigningCertificateLineage::parse(&d).is_none());
+}
+
+#[test]
+fn test_rotate() {
+    let rsa_key = PrivateKey::from_pkcs8_der(include_bytes!("../../testdata/rsa.pk8")).unwrap();
+    let rsa = include_bytes!("../../testdata/rsa.der").to_vec();
+    let ec_key = PrivateKey::from_pkcs8_der(include_bytes!("../../testdata/ec.pk8")).unwrap();
+    let ec = include_bytes!("../../testdata/ec.der").to_vec();
+
+    let mut lineage = SigningCertificateLineage::new(&rsa_key, rsa.clone()).unwrap();
+    assert!(lineage.rotate(&ec_key, &ec_key, ec.clone()).is_err());
+    assert!(lineage.rotate(&rsa_key, &rsa_key, ec.clone()).is_err());
+    lineage.rotate(&rsa_key, &ec_key, ec.clone()).unwrap();
+    assert_eq!(lineage.verify(), Ok(()));
+    assert_eq!(lineage.current(), Some(&ec[..]));
+    assert_eq!(lineage.nodes[1].parent_signature_algorithm, Some(SignatureAlgorithm::RsaPkcs1Sha256));
+    assert_eq!(lineage.nodes[1].signature_algorithm, SignatureAlgorithm::EcdsaSha256);
+    assert_eq!(lineage.find(&rsa).unwrap().capabilities, Capabilities::default());
+
+    let encoded = lineage.encode();
+    let parsed = SigningCertificateLineage::parse(&encoded).unwrap();
+    assert_eq!(parsed.verify(), Ok(()));
+    assert_eq!(parsed.encode(), encoded);
+    assert!(SigningCertificateLineage::parse(&encoded[..encoded.len() - 1]).is_none());
+
+    // the signature over the EC certificate is the last field of the encoding
+    let mut tampered = encoded;
+    *tampered.last_mut().unwrap() ^= 1;
+    let tampered = SigningCertificateLineage::parse(&tampered).unwrap();
+    assert_eq!(tampered.verify(), Err(VerificationError::InvalidLineage));
+}
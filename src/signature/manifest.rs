@@ -0,0 +1,116 @@
+/// A JAR manifest or signature file: a main section followed by per-entry sections separated
+/// by empty lines.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub main: Section,
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    /// The bytes of the section including the empty line ending it, what signature files
+    /// digest.
+    pub raw: Vec<u8>,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Section {
+    /// The value of an attribute, names are case-insensitive.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// The entry the section is about, `None` for the main section.
+    pub fn name(&self) -> Option<&str> {
+        self.get("Name")
+    }
+}
+
+/// Splits the next line off `data`, returns the line without and the length with its
+/// terminator.
+fn next_line(data: &[u8]) -> (&[u8], usize) {
+    match data.iter().position(|&b| b == b'\r' || b == b'\n') {
+        Some(end) if data[end] == b'\r' && data.get(end + 1) == Some(&b'\n') => (&data[..end], end + 2),
+        Some(end) => (&data[..end], end + 1),
+        None => (data, data.len()),
+    }
+}
+
+/// Parses a manifest, `None` if a line is neither an attribute nor a continuation.
+pub fn parse_manifest(data: &[u8]) -> Option<Manifest> {
+    let mut sections = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let start = offset;
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        let mut value = Vec::new();
+        while offset < data.len() {
+            let (line, len) = next_line(&data[offset..]);
+            offset += len;
+            if line.is_empty() {
+                break;
+            }
+            if line[0] == b' ' {
+                // continuation lines extend the value of the previous attribute
+                attributes.last()?;
+                value.extend_from_slice(&line[1..]);
+            } else {
+                if let Some(last) = attributes.last_mut() {
+                    last.1 = String::from_utf8_lossy(&value).into_owned();
+                }
+                let separator = line.windows(2).position(|w| w == b": ")?;
+                attributes.push((String::from_utf8_lossy(&line[..separator]).into_owned(), String::new()));
+                value = line[separator + 2..].to_vec();
+            }
+        }
+        if let Some(last) = attributes.last_mut() {
+            last.1 = String::from_utf8_lossy(&value).into_owned();
+        }
+        // the main section is kept even when empty, extra empty lines are not sections
+        if sections.is_empty() || !attributes.is_empty() {
+            sections.push(Section { raw: data[start..offset].to_vec(), attributes });
+        }
+    }
+
+    let mut sections = sections.into_iter();
+    let main = sections.next().unwrap_or(Section { raw: Vec::new(), attributes: Vec::new() });
+    Some(Manifest { main, sections: sections.collect() })
+}
+
+/// Appends an attribute line, wrapped into continuation lines so that no line exceeds 72
+/// bytes as the JAR specification demands.
+pub fn write_attribute(out: &mut Vec<u8>, name: &str, value: &str) {
+    let line = format!("{}: {}", name, value).into_bytes();
+    let (first, mut rest) = line.split_at(line.len().min(72));
+    out.extend_from_slice(first);
+    out.extend_from_slice(b"\r\n");
+    while !rest.is_empty() {
+        let (continuation, remaining) = rest.split_at(rest.len().min(71));
+        out.push(b' ');
+        out.extend_from_slice(continuation);
+        out.extend_from_slice(b"\r\n");
+        rest = remaining;
+    }
+}
+
+#[test]
+fn test_parse_manifest() {
+    let d = b"Manifest-Version: 1.0\r\nCreated-By: test\r\n\r\nName: res/a-very-long-name\r\n .xml\r\nSHA-256-Digest: abc=\r\n\r\n\r\nName: b\nSHA1-Digest: def=\n";
+    let manifest = parse_manifest(d).unwrap();
+    assert_eq!(manifest.main.get("manifest-version"), Some("1.0"));
+    assert_eq!(manifest.main.raw, b"Manifest-Version: 1.0\r\nCreated-By: test\r\n\r\n");
+    assert_eq!(manifest.sections.len(), 2);
+    assert_eq!(manifest.sections[0].name(), Some("res/a-very-long-name.xml"));
+    assert_eq!(manifest.sections[0].get("SHA-256-Digest"), Some("abc="));
+    assert_eq!(manifest.sections[1].name(), Some("b"));
+    assert_eq!(manifest.sections[1].raw, b"Name: b\nSHA1-Digest: def=\n");
+    assert!(parse_manifest(b"Manifest-Version 1.0\r\n").is_none());
+
+    let name = "x".repeat(150);
+    let mut d = Vec::new();
+    write_attribute(&mut d, "Name", &name);
+    assert!(d.split(|&b| b == b'\n').all(|line| line.len() <= 73));
+    assert_eq!(parse_manifest(&d).unwrap().main.name(), Some(&name[..]));
+}
@@ -1,22 +1,30 @@
 use sha2::{Sha256, Digest};
-use nom::*;
 
 mod asn1;
+pub mod block;
+pub mod certificate;
+pub mod digest;
+pub mod error;
+pub mod fingerprint;
+pub mod idsig;
+pub mod jar;
+mod key;
+pub mod lineage;
+pub mod manifest;
+pub mod pkcs7;
+pub mod sign;
+pub mod signer;
+pub mod stamp;
+pub mod verify;
+pub mod verity;
 
+pub use self::key::PrivateKey;
+
+/// SHA-256 of the certificate of the first signer of a PKCS#7 signature block.
 pub fn get_key_fingerprint_sha256(pkcs7: &[u8]) -> Result<Vec<u8>, u32> {
-    if let IResult::Done(_, cert) = get_cert(pkcs7) {
-        let mut hasher = Sha256::new();
-        hasher.input(cert);
-        return Ok(hasher.result().to_vec());
+    let signed_data = pkcs7::parse_signed_data(pkcs7).ok_or(0u32)?;
+    match signed_data.signer_certificates().first() {
+        Some(certificate) => Ok(Sha256::digest(certificate).to_vec()),
+        None => Err(0),
     }
-    Err(0)
 }
-
-fn get_cert(pkcs7: &[u8]) -> IResult<&[u8], Vec<u8>> {
-    let (_, r) = try_parse!(pkcs7, asn1::parse_data_element);
-    let (_, r) = r.parse_data().unwrap();
-    let (_, r) = r[1].parse_data().unwrap();
-    let (_, r) = r[0].parse_data().unwrap();
-
-    IResult::Done(&[], r[3].data().to_vec())
-}
\ No newline at end of file
@@ -0,0 +1,243 @@
+use std::io;
+use crate::signature::asn1::{self, DataElement};
+use crate::signature::certificate::{self, CertificateInfo};
+use crate::signature::digest::DigestAlgorithm;
+use crate::signature::error::SignatureError;
+use crate::signature::key::{self, PrivateKey};
+use crate::signature::verify::VerificationError;
+
+const SIGNED_DATA: &[u32] = &[1, 2, 840, 113549, 1, 7, 2];
+const DATA: &[u32] = &[1, 2, 840, 113549, 1, 7, 1];
+const CONTENT_TYPE: &[u32] = &[1, 2, 840, 113549, 1, 9, 3];
+const MESSAGE_DIGEST: &[u32] = &[1, 2, 840, 113549, 1, 9, 4];
+
+/// PKCS#7 SignedData with detached content, as found in JAR signature block files.
+#[derive(Debug, Clone)]
+pub struct SignedData {
+    /// DER encoded X.509 certificates in the order of the block.
+    pub certificates: Vec<Vec<u8>>,
+    pub signer_infos: Vec<SignerInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SignerInfo {
+    /// Content octets of the issuer Name and the serial number of the signer certificate.
+    pub issuer: Vec<u8>,
+    pub serial_number: Vec<u8>,
+    pub digest_algorithm: Vec<u32>,
+    /// Content octets of the `[0]` authenticated attributes.
+    pub authenticated_attributes: Option<Vec<u8>>,
+    pub signature: Vec<u8>,
+}
+
+fn elements(element: &DataElement) -> Option<Vec<DataElement>> {
+    element.parse_data().to_full_result().ok()
+}
+
+fn object_identifier(element: &DataElement) -> Option<Vec<u32>> {
+    if element.id() == 6 && !element.is_context_specific() {
+        asn1::decode_object_identifier(element.data())
+    } else {
+        None
+    }
+}
+
+fn parse_signer_info(signer_info: &DataElement) -> Option<SignerInfo> {
+    let fields = elements(signer_info)?;
+    let issuer_and_serial_number = elements(fields.get(1)?)?;
+    let digest_algorithm = object_identifier(elements(fields.get(2)?)?.first()?)?;
+    let (authenticated_attributes, rest) = match fields.get(3) {
+        Some(attributes) if attributes.is_context_specific() && attributes.id() == 0 => (Some(attributes.data().clone()), &fields[4..]),
+        _ => (None, &fields[3..]),
+    };
+    // the digest encryption algorithm is implied by the key of the certificate
+    Some(SignerInfo {
+        issuer: issuer_and_serial_number.first()?.data().clone(),
+        serial_number: issuer_and_serial_number.get(1)?.data().clone(),
+        digest_algorithm,
+        authenticated_attributes,
+        signature: rest.get(1)?.data().clone(),
+    })
+}
+
+/// Parses a DER encoded ContentInfo holding SignedData.
+pub fn parse_signed_data(der: &[u8]) -> Option<SignedData> {
+    let content_info = asn1::parse_data_element(der).to_full_result().ok()?;
+    let content_info = elements(&content_info)?;
+    if object_identifier(content_info.first()?)? != SIGNED_DATA {
+        return None;
+    }
+    let signed_data = elements(content_info.get(1)?)?;
+    let signed_data = elements(signed_data.first()?)?;
+
+    let mut certificates = Vec::new();
+    let mut signer_infos = None;
+    // version, digest algorithms and content info are followed by optional certificates and
+    // CRLs, the signer infos come last
+    for element in signed_data.iter().skip(3) {
+        if element.is_context_specific() {
+            if element.id() == 0 {
+                certificates = elements(element)?.iter().map(DataElement::to_der).collect();
+            }
+        } else {
+            signer_infos = Some(elements(element)?.iter().map(parse_signer_info).collect::<Option<Vec<_>>>()?);
+        }
+    }
+    Some(SignedData { certificates, signer_infos: signer_infos? })
+}
+
+impl SignedData {
+    fn certificate_infos(&self) -> Vec<Option<CertificateInfo>> {
+        self.certificates.iter()
+            .map(|certificate| certificate::parse_certificate_info(certificate))
+            .collect()
+    }
+
+    /// Index of the certificate of a signer.
+    fn find_certificate(infos: &[Option<CertificateInfo>], signer_info: &SignerInfo) -> Option<usize> {
+        infos.iter().position(|info| {
+            matches!(info, Some(info) if info.issuer == signer_info.issuer && info.serial_number == signer_info.serial_number)
+        })
+    }
+
+    /// The certificate chain of the first signer starting with its own certificate, without
+    /// verifying anything. All certificates if the one of the signer is missing.
+    pub fn signer_certificates(&self) -> Vec<Vec<u8>> {
+        let infos = self.certificate_infos();
+        let signer = self.signer_infos.first().and_then(|signer_info| SignedData::find_certificate(&infos, signer_info));
+        match signer {
+            Some(signer) => chain(&self.certificates, &infos, signer),
+            None => self.certificates.clone(),
+        }
+    }
+
+    /// Verifies the first signer over detached `content`. Returns the certificate chain of the
+    /// signer, starting with its own certificate.
+    pub fn verify(&self, content: &[u8]) -> Result<Vec<Vec<u8>>, VerificationError> {
+        let signer_info = self.signer_infos.first().ok_or(VerificationError::NoSigners)?;
+        let digest_algorithm = DigestAlgorithm::from_oid(&signer_info.digest_algorithm)
+            .ok_or(VerificationError::InvalidSignatureBlock)?;
+        let infos = self.certificate_infos();
+        let signer = SignedData::find_certificate(&infos, signer_info).ok_or(VerificationError::NoCertificates)?;
+        let signer_certificate = infos[signer].as_ref().unwrap();
+
+        let digest = digest_algorithm.digest(content);
+        let signed = match &signer_info.authenticated_attributes {
+            Some(attributes) => {
+                verify_authenticated_attributes(attributes, &digest)?;
+                // the signature covers the attributes with their universal SET tag
+                let mut signed = vec![0x31];
+                signed.extend_from_slice(&asn1::encode_length(attributes.len()));
+                signed.extend_from_slice(attributes);
+                digest_algorithm.digest(&signed)
+            }
+            None => digest,
+        };
+        if !key::verify_digest(&signer_certificate.public_key, digest_algorithm, &signed, &signer_info.signature)? {
+            return Err(VerificationError::InvalidJarSignature);
+        }
+        Ok(chain(&self.certificates, &infos, signer))
+    }
+}
+
+fn algorithm_identifier(oid: &[u32], null_parameters: bool) -> Vec<u8> {
+    let mut content = asn1::encode(0x06, &asn1::encode_object_identifier(oid));
+    if null_parameters {
+        content.extend_from_slice(&[0x05, 0x00]);
+    }
+    asn1::encode(0x30, &content)
+}
+
+/// Signs detached `content` and encodes a ContentInfo holding the SignedData, without
+/// authenticated attributes like apksigner. `certificates` is the chain starting with the
+/// certificate of the key.
+pub(crate) fn encode_signed_data(key: &PrivateKey, certificates: &[Vec<u8>], digest_algorithm: DigestAlgorithm, content: &[u8]) -> io::Result<Vec<u8>> {
+    let certificate = certificates.first()
+        .and_then(|certificate| certificate::parse_certificate_info(certificate))
+        .ok_or(SignatureError::InvalidCertificate)?;
+    let signature = key.sign_digest(digest_algorithm, &digest_algorithm.digest(content))?;
+    let (encryption_algorithm, null_parameters) = key.digest_encryption_algorithm(digest_algorithm);
+    let digest_algorithm = algorithm_identifier(digest_algorithm.oid(), true);
+
+    let mut issuer_and_serial_number = asn1::encode(0x30, &certificate.issuer);
+    issuer_and_serial_number.extend_from_slice(&asn1::encode(0x02, &certificate.serial_number));
+    let mut signer_info = vec![0x02, 0x01, 0x01];
+    signer_info.extend_from_slice(&asn1::encode(0x30, &issuer_and_serial_number));
+    signer_info.extend_from_slice(&digest_algorithm);
+    signer_info.extend_from_slice(&algorithm_identifier(encryption_algorithm, null_parameters));
+    signer_info.extend_from_slice(&asn1::encode(0x04, &signature));
+
+    let mut signed_data = vec![0x02, 0x01, 0x01];
+    signed_data.extend_from_slice(&asn1::encode(0x31, &digest_algorithm));
+    signed_data.extend_from_slice(&asn1::encode(0x30, &asn1::encode(0x06, &asn1::encode_object_identifier(DATA))));
+    signed_data.extend_from_slice(&asn1::encode(0xa0, &certificates.concat()));
+    signed_data.extend_from_slice(&asn1::encode(0x31, &asn1::encode(0x30, &signer_info)));
+
+    let mut content_info = asn1::encode(0x06, &asn1::encode_object_identifier(SIGNED_DATA));
+    content_info.extend_from_slice(&asn1::encode(0xa0, &asn1::encode(0x30, &signed_data)));
+    Ok(asn1::encode(0x30, &content_info))
+}
+
+fn verify_authenticated_attributes(attributes: &[u8], digest: &[u8]) -> Result<(), VerificationError> {
+    let attributes = asn1::parse_data_elements(attributes).to_full_result().map_err(|_| VerificationError::InvalidSignatureBlock)?;
+    let mut message_digest = None;
+    for attribute in &attributes {
+        let attribute = elements(attribute).ok_or(VerificationError::InvalidSignatureBlock)?;
+        let (attribute_type, values) = match (attribute.first().and_then(object_identifier), attribute.get(1).and_then(elements)) {
+            (Some(attribute_type), Some(values)) => (attribute_type, values),
+            _ => return Err(VerificationError::InvalidSignatureBlock),
+        };
+        let value = values.first().ok_or(VerificationError::InvalidSignatureBlock)?;
+        if attribute_type == CONTENT_TYPE && object_identifier(value).as_deref() != Some(DATA) {
+            return Err(VerificationError::InvalidSignatureBlock);
+        } else if attribute_type == MESSAGE_DIGEST {
+            message_digest = Some(value.data().clone());
+        }
+    }
+    match message_digest {
+        Some(message_digest) if message_digest[..] == digest[..] => Ok(()),
+        _ => Err(VerificationError::InvalidJarSignature),
+    }
+}
+
+/// Orders the certificates from the signer up to the root as far as issuers are present.
+fn chain(certificates: &[Vec<u8>], infos: &[Option<CertificateInfo>], signer: usize) -> Vec<Vec<u8>> {
+    let mut chain = vec![signer];
+    while let Some(Some(current)) = infos.get(*chain.last().unwrap()) {
+        let issuer = infos.iter().position(|info| matches!(info, Some(info) if info.subject == current.issuer));
+        match issuer {
+            Some(issuer) if !chain.contains(&issuer) => chain.push(issuer),
+            _ => break,
+        }
+    }
+    chain.into_iter().map(|i| certificates[i].clone()).collect()
+}
+
+#[test]
+fn test_verify() {
+    use crate::zip::archive::ZipArchive;
+
+    let archive = ZipArchive::from_bytes(&include_bytes!("../../testdata/v1-rsa.apk")[..]).unwrap();
+    let read = |name: &str| archive.by_name(name).unwrap().unwrap().bytes().unwrap().into_owned();
+    let mut signature_file = read("META-INF/CERT.SF");
+    let signed_data = parse_signed_data(&read("META-INF/CERT.RSA")).unwrap();
+    let certificate = include_bytes!("../../testdata/rsa.der").to_vec();
+    assert_eq!(signed_data.signer_certificates(), vec![certificate.clone()]);
+    // jarsigner signs authenticated attributes holding the digest of the signature file
+    assert!(signed_data.signer_infos[0].authenticated_attributes.is_some());
+    assert_eq!(signed_data.verify(&signature_file), Ok(vec![certificate]));
+
+    signature_file[0] ^= 1;
+    assert_eq!(signed_data.verify(&signature_file), Err(VerificationError::InvalidJarSignature));
+
+    // the content type attribute holds an object identifier whose last arc overflows u32
+    let mut block = read("META-INF/CERT.RSA");
+    let mut content_type = asn1::encode(0x06, &asn1::encode_object_identifier(CONTENT_TYPE));
+    content_type.extend_from_slice(&[0x31, 0x0b]);
+    content_type.extend_from_slice(&asn1::encode(0x06, &asn1::encode_object_identifier(DATA)));
+    let position = block.windows(content_type.len()).position(|window| window == &content_type[..]).unwrap() + content_type.len() - 9;
+    block[position..position + 9].copy_from_slice(&[0x2a, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+    let signed_data = parse_signed_data(&block).unwrap();
+    signature_file[0] ^= 1;
+    assert_eq!(signed_data.verify(&signature_file), Err(VerificationError::InvalidSignatureBlock));
+}
@@ -0,0 +1,260 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use crate::zip::archive::ZipArchive;
+use crate::zip::writer::ZipWriter;
+use crate::signature::block::{IdValuePair, SigningBlock, SIGNATURE_SCHEME_V2_BLOCK_ID, SIGNATURE_SCHEME_V3_BLOCK_ID};
+use crate::signature::certificate;
+use crate::signature::digest;
+use crate::signature::error::SignatureError;
+use crate::signature::jar;
+use crate::signature::key::PrivateKey;
+use crate::signature::lineage::{SigningCertificateLineage, PROOF_OF_ROTATION_ATTRIBUTE_ID};
+use crate::signature::signer::{self, AdditionalAttribute, ContentDigest, Signature, SignedData, Signer};
+use crate::signature::verify::STRIPPING_PROTECTION_ATTRIBUTE_ID;
+
+/// Name of the JAR signature files, `META-INF/CERT.SF` and its block.
+const JAR_SIGNER_NAME: &str = "CERT";
+
+/// Android 9, the first version verifying v3 signatures.
+const V3_MIN_SDK_VERSION: u32 = 28;
+const V3_MAX_SDK_VERSION: u32 = i32::MAX as u32;
+
+/// Page size stored shared libraries are aligned to, as by `zipalign -p`.
+const PAGE_SIZE: u64 = 4096;
+
+/// A private key with its certificate chain, starting with the certificate of the key.
+struct SignerConfig {
+    key: PrivateKey,
+    certificates: Vec<Vec<u8>>,
+}
+
+impl SignerConfig {
+    fn new(private_key: &[u8], certificates: Vec<Vec<u8>>) -> io::Result<SignerConfig> {
+        let key = PrivateKey::from_pkcs8_der(private_key)?;
+        let info = certificates.first()
+            .and_then(|certificate| certificate::parse_certificate_info(certificate))
+            .ok_or(SignatureError::InvalidCertificate)?;
+        if info.public_key != key.public_key() {
+            return Err(SignatureError::PrivateKeyMismatch.into());
+        }
+        Ok(SignerConfig { key, certificates })
+    }
+
+    fn certificate(&self) -> &[u8] {
+        &self.certificates[0]
+    }
+}
+
+/// Signs APKs with JAR signing (v1) and APK Signature Scheme v2 and v3, like apksigner does.
+/// Existing signatures are replaced, entries are copied without recompression and stored
+/// entries are aligned like `zipalign -p`.
+pub struct ApkSigner {
+    signer: SignerConfig,
+    original_signer: Option<SignerConfig>,
+    lineage: Option<SigningCertificateLineage>,
+    v1: bool,
+    v2: bool,
+    v3: bool,
+    min_sdk_version: u32,
+}
+
+impl ApkSigner {
+    /// A signer with a DER encoded PKCS#8 private key and the DER encoded certificate chain
+    /// starting with the certificate of the key. All schemes are enabled.
+    pub fn new(private_key: &[u8], certificates: Vec<Vec<u8>>) -> io::Result<ApkSigner> {
+        Ok(ApkSigner {
+            signer: SignerConfig::new(private_key, certificates)?,
+            original_signer: None,
+            lineage: None,
+            v1: true,
+            v2: true,
+            v3: true,
+            min_sdk_version: 24,
+        })
+    }
+
+    pub fn v1(&mut self, enabled: bool) -> &mut ApkSigner {
+        self.v1 = enabled;
+        self
+    }
+
+    pub fn v2(&mut self, enabled: bool) -> &mut ApkSigner {
+        self.v2 = enabled;
+        self
+    }
+
+    pub fn v3(&mut self, enabled: bool) -> &mut ApkSigner {
+        self.v3 = enabled;
+        self
+    }
+
+    /// The lowest Android version the APK supports, 24 by default. The JAR signature falls back
+    /// to SHA-1 for versions lacking SHA-256 with the key type.
+    pub fn min_sdk_version(&mut self, min_sdk_version: u32) -> &mut ApkSigner {
+        self.min_sdk_version = min_sdk_version;
+        self
+    }
+
+    /// Adds the proof-of-rotation to the v3 signature. It has to end with the certificate of
+    /// the signer.
+    pub fn lineage(&mut self, lineage: SigningCertificateLineage) -> io::Result<&mut ApkSigner> {
+        if lineage.current() != Some(self.signer.certificate()) {
+            return Err(SignatureError::LineageMismatch.into());
+        }
+        self.lineage = Some(lineage);
+        Ok(self)
+    }
+
+    /// After a key rotation, signs v1 and v2 with the original key older Android versions know
+    /// the app by. The key given to `new` then only signs v3, the original certificate has to
+    /// be part of the lineage.
+    pub fn original_signer(&mut self, private_key: &[u8], certificates: Vec<Vec<u8>>) -> io::Result<&mut ApkSigner> {
+        self.original_signer = Some(SignerConfig::new(private_key, certificates)?);
+        Ok(self)
+    }
+
+    /// Signs the entries of an archive and writes the signed APK to `out`. With v2 or v3 the
+    /// unsigned APK is built in memory first, as the signing block covers all of it.
+    pub fn sign<W: Write>(&self, archive: &ZipArchive, mut out: W) -> io::Result<()> {
+        let legacy_signer = self.original_signer.as_ref().unwrap_or(&self.signer);
+        if let Some(original_signer) = &self.original_signer {
+            if self.lineage.as_ref().and_then(|lineage| lineage.find(original_signer.certificate())).is_none() {
+                return Err(SignatureError::LineageMismatch.into());
+            }
+        }
+        if !self.v2 && !self.v3 {
+            self.write_entries(archive, legacy_signer, out)?;
+            return Ok(());
+        }
+
+        let unsigned_archive = ZipArchive::from_bytes(self.write_entries(archive, legacy_signer, Vec::new())?)?;
+        let central_directory_offset = unsigned_archive.central_directory_offset();
+        let end_of_central_directory_offset = unsigned_archive.end_of_central_directory_offset();
+        if central_directory_offset + unsigned_archive.central_directory_size() != end_of_central_directory_offset {
+            return Err(SignatureError::Zip64NotSupported.into());
+        }
+
+        let mut pairs = Vec::new();
+        if self.v2 {
+            let attributes = if self.v3 {
+                vec![AdditionalAttribute { id: STRIPPING_PROTECTION_ATTRIBUTE_ID, value: 3u32.to_le_bytes().to_vec() }]
+            } else {
+                Vec::new()
+            };
+            let signer = scheme_signer(&unsigned_archive, legacy_signer, attributes, None)?;
+            pairs.push(IdValuePair { id: SIGNATURE_SCHEME_V2_BLOCK_ID, value: signer::encode_signers(&[signer]) });
+        }
+        if self.v3 {
+            let attributes = match &self.lineage {
+                Some(lineage) => vec![AdditionalAttribute { id: PROOF_OF_ROTATION_ATTRIBUTE_ID, value: lineage.encode() }],
+                None => Vec::new(),
+            };
+            let sdk_versions = Some((V3_MIN_SDK_VERSION, V3_MAX_SDK_VERSION));
+            let signer = scheme_signer(&unsigned_archive, &self.signer, attributes, sdk_versions)?;
+            pairs.push(IdValuePair { id: SIGNATURE_SCHEME_V3_BLOCK_ID, value: signer::encode_signers(&[signer]) });
+        }
+        let block = SigningBlock { offset: central_directory_offset, pairs }.to_bytes();
+
+        // the signing block goes in front of the central directory, which moves accordingly
+        let mut eocd = Vec::new();
+        unsigned_archive.raw_range(end_of_central_directory_offset, u64::MAX)?.read_to_end(&mut eocd)?;
+        let moved = u32::try_from(central_directory_offset + block.len() as u64).map_err(|_| SignatureError::Zip64NotSupported)?;
+        eocd[16..20].copy_from_slice(&moved.to_le_bytes());
+        io::copy(&mut unsigned_archive.raw_range(0, central_directory_offset)?, &mut out)?;
+        out.write_all(&block)?;
+        io::copy(&mut unsigned_archive.raw_range(central_directory_offset, end_of_central_directory_offset - central_directory_offset)?, &mut out)?;
+        out.write_all(&eocd)
+    }
+
+    /// Writes the entries of an archive without its old signatures, preceded by the JAR
+    /// signature files if v1 is enabled.
+    fn write_entries<W: Write>(&self, archive: &ZipArchive, legacy_signer: &SignerConfig, out: W) -> io::Result<W> {
+        let mut writer = ZipWriter::new(out);
+        writer.align_stored_entries(PAGE_SIZE)?;
+        if self.v1 {
+            let algorithm = legacy_signer.key.jar_digest_algorithm(self.min_sdk_version);
+            let signed_schemes: Vec<u32> = [(2, self.v2), (3, self.v3)].iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(scheme, _)| *scheme)
+                .collect();
+            let files = jar::sign(archive, JAR_SIGNER_NAME, &legacy_signer.key, &legacy_signer.certificates, algorithm, &signed_schemes)?;
+            for (name, data) in files {
+                writer.add_deflated(&name, &data)?;
+            }
+        }
+        // old signatures would no longer verify
+        for entry in archive.files().filter(|entry| !jar::is_signature_entry(&entry.file_name())) {
+            writer.copy_raw(&entry)?;
+        }
+        writer.finish()
+    }
+}
+
+/// A v2 or v3 signer over the content digest of an archive that has no signing block yet.
+fn scheme_signer(
+    archive: &ZipArchive,
+    config: &SignerConfig,
+    additional_attributes: Vec<AdditionalAttribute>,
+    sdk_versions: Option<(u32, u32)>,
+) -> io::Result<Signer> {
+    let algorithm = config.key.signature_algorithm();
+    let content_digest_algorithm = algorithm.content_digest_algorithm()
+        .ok_or(SignatureError::UnsupportedSignatureAlgorithm(algorithm.into()))?;
+    let digest = digest::content_digest(archive, archive.central_directory_offset(), content_digest_algorithm)?;
+    let signed_data = SignedData::new(
+        vec![ContentDigest { algorithm, digest }],
+        config.certificates.clone(),
+        additional_attributes,
+        sdk_versions,
+    );
+    let signature = config.key.sign(algorithm, &signed_data.raw)?;
+    Ok(Signer {
+        signed_data,
+        min_sdk_version: sdk_versions.map(|(min, _)| min),
+        max_sdk_version: sdk_versions.map(|(_, max)| max),
+        signatures: vec![Signature { algorithm, signature }],
+        public_key: config.key.public_key(),
+    })
+}
+
+#[test]
+fn test_sign() {
+    use crate::signature::verify;
+
+    let unsigned = ZipArchive::from_bytes(&include_bytes!("../../testdata/unsigned.apk")[..]).unwrap();
+    let ec_key = &include_bytes!("../../testdata/ec.pk8")[..];
+    let ec = include_bytes!("../../testdata/ec.der").to_vec();
+    // the JAR signature falls back to SHA-1 below the SDK version
+    let configs = [
+        (&include_bytes!("../../testdata/rsa.pk8")[..], include_bytes!("../../testdata/rsa.der").to_vec(), 18),
+        (ec_key, ec.clone(), 21),
+    ];
+    for (key, certificate, sha256_min_sdk_version) in configs {
+        for min_sdk_version in [18, 24] {
+            let mut signer = ApkSigner::new(key, vec![certificate.clone()]).unwrap();
+            signer.min_sdk_version(min_sdk_version);
+            let mut signed = Vec::new();
+            signer.sign(&unsigned, &mut signed).unwrap();
+
+            let archive = ZipArchive::from_bytes(signed).unwrap();
+            let report = verify::verify(&archive).unwrap();
+            assert!(report.is_verified(), "{:?}", report);
+            assert!(report.v1.signed && report.v2.signed && report.v3.signed && !report.v31.signed);
+            assert_eq!(report.v3.signers[0].certificates, vec![certificate.clone()]);
+            assert!(archive.check_alignment(PAGE_SIZE).unwrap().is_empty());
+            let manifest = archive.by_name("META-INF/MANIFEST.MF").unwrap().unwrap().bytes().unwrap().into_owned();
+            assert_eq!(String::from_utf8(manifest).unwrap().contains("SHA1-Digest"), min_sdk_version < sha256_min_sdk_version);
+        }
+    }
+
+    // signing a signed APK again replaces its signatures
+    let mut signer = ApkSigner::new(ec_key, vec![ec]).unwrap();
+    signer.v1(false);
+    let mut resigned = Vec::new();
+    signer.sign(&ZipArchive::from_bytes(&include_bytes!("../../testdata/v1-rsa.apk")[..]).unwrap(), &mut resigned).unwrap();
+    let archive = ZipArchive::from_bytes(resigned).unwrap();
+    let report = verify::verify(&archive).unwrap();
+    assert!(report.is_verified(), "{:?}", report);
+    assert!(!report.v1.signed);
+    assert!(archive.files().all(|entry| !jar::is_signature_entry(&entry.file_name())));
+}
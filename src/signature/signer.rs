@@ -0,0 +1,378 @@
+use nom::*;
+use crate::signature::digest::ContentDigestAlgorithm;
+use crate::signature::lineage::{SigningCertificateLineage, PROOF_OF_ROTATION_ATTRIBUTE_ID};
+
+/// Signature algorithms of the v2 and later signature schemes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureAlgorithm {
+    RsaPssSha256,
+    RsaPssSha512,
+    RsaPkcs1Sha256,
+    RsaPkcs1Sha512,
+    EcdsaSha256,
+    EcdsaSha512,
+    DsaSha256,
+    /// The verity variants sign the root of an fs-verity Merkle tree instead of chunk digests.
+    VerityRsaPkcs1Sha256,
+    VerityEcdsaSha256,
+    VerityDsaSha256,
+    Other(u32),
+}
+
+impl From<u32> for SignatureAlgorithm {
+    fn from(id: u32) -> SignatureAlgorithm {
+        match id {
+            0x0101 => SignatureAlgorithm::RsaPssSha256,
+            0x0102 => SignatureAlgorithm::RsaPssSha512,
+            0x0103 => SignatureAlgorithm::RsaPkcs1Sha256,
+            0x0104 => SignatureAlgorithm::RsaPkcs1Sha512,
+            0x0201 => SignatureAlgorithm::EcdsaSha256,
+            0x0202 => SignatureAlgorithm::EcdsaSha512,
+            0x0301 => SignatureAlgorithm::DsaSha256,
+            0x0421 => SignatureAlgorithm::VerityRsaPkcs1Sha256,
+            0x0423 => SignatureAlgorithm::VerityEcdsaSha256,
+            0x0425 => SignatureAlgorithm::VerityDsaSha256,
+            id => SignatureAlgorithm::Other(id),
+        }
+    }
+}
+
+impl From<SignatureAlgorithm> for u32 {
+    fn from(algorithm: SignatureAlgorithm) -> u32 {
+        match algorithm {
+            SignatureAlgorithm::RsaPssSha256 => 0x0101,
+            SignatureAlgorithm::RsaPssSha512 => 0x0102,
+            SignatureAlgorithm::RsaPkcs1Sha256 => 0x0103,
+            SignatureAlgorithm::RsaPkcs1Sha512 => 0x0104,
+            SignatureAlgorithm::EcdsaSha256 => 0x0201,
+            SignatureAlgorithm::EcdsaSha512 => 0x0202,
+            SignatureAlgorithm::DsaSha256 => 0x0301,
+            SignatureAlgorithm::VerityRsaPkcs1Sha256 => 0x0421,
+            SignatureAlgorithm::VerityEcdsaSha256 => 0x0423,
+            SignatureAlgorithm::VerityDsaSha256 => 0x0425,
+            SignatureAlgorithm::Other(id) => id,
+        }
+    }
+}
+
+impl SignatureAlgorithm {
+    /// How the APK contents are digested for this algorithm, `None` if the digest can not be
+    /// computed.
+    pub fn content_digest_algorithm(&self) -> Option<ContentDigestAlgorithm> {
+        match self {
+            SignatureAlgorithm::RsaPssSha256
+            | SignatureAlgorithm::RsaPkcs1Sha256
+            | SignatureAlgorithm::EcdsaSha256
+            | SignatureAlgorithm::DsaSha256 => Some(ContentDigestAlgorithm::ChunkedSha256),
+            SignatureAlgorithm::RsaPssSha512
+            | SignatureAlgorithm::RsaPkcs1Sha512
+            | SignatureAlgorithm::EcdsaSha512 => Some(ContentDigestAlgorithm::ChunkedSha512),
+            _ => None,
+        }
+    }
+}
+
+/// A signer of the v2 or v3 signature scheme block.
+#[derive(Debug, Clone)]
+pub struct Signer {
+    pub signed_data: SignedData,
+    /// Only present in v3, must match the values in the signed data.
+    pub min_sdk_version: Option<u32>,
+    pub max_sdk_version: Option<u32>,
+    pub signatures: Vec<Signature>,
+    /// DER encoded SubjectPublicKeyInfo.
+    pub public_key: Vec<u8>,
+}
+
+/// The part of a signer covered by its signatures.
+#[derive(Debug, Clone)]
+pub struct SignedData {
+    /// The encoded signed data the signatures are computed over.
+    pub raw: Vec<u8>,
+    pub digests: Vec<ContentDigest>,
+    /// DER encoded X.509 certificates, the first one belongs to the signing key.
+    pub certificates: Vec<Vec<u8>>,
+    pub additional_attributes: Vec<AdditionalAttribute>,
+    pub min_sdk_version: Option<u32>,
+    pub max_sdk_version: Option<u32>,
+}
+
+/// The digest of the APK contents for a signature algorithm.
+#[derive(Debug, Clone)]
+pub struct ContentDigest {
+    pub algorithm: SignatureAlgorithm,
+    pub digest: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub algorithm: SignatureAlgorithm,
+    pub signature: Vec<u8>,
+}
+
+impl Signer {
+    /// Whether the SDK version is within the range of a v3 signer, always true for v2.
+    pub fn supports_sdk(&self, sdk: u32) -> bool {
+        self.min_sdk_version.is_none_or(|min| min <= sdk) && self.max_sdk_version.is_none_or(|max| sdk <= max)
+    }
+
+    /// The proof-of-rotation of a v3 signer whose key was rotated, `None` if the signer has
+    /// none or it can not be parsed. Not verified, see `SigningCertificateLineage::verify`.
+    pub fn lineage(&self) -> Option<SigningCertificateLineage> {
+        self.signed_data.additional_attribute(PROOF_OF_ROTATION_ATTRIBUTE_ID)
+            .and_then(SigningCertificateLineage::parse)
+    }
+}
+
+/// Writes `bytes` prefixed with their length as a little endian u32.
+pub(crate) fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// `bytes` prefixed with their length, to build blocks in tests.
+#[cfg(test)]
+pub(crate) fn length_prefixed(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len() + 4);
+    write_bytes(&mut out, bytes);
+    out
+}
+
+/// Writes a length prefixed sequence of length prefixed items.
+fn write_sequence<I: IntoIterator<Item = Vec<u8>>>(out: &mut Vec<u8>, items: I) {
+    let mut sequence = Vec::new();
+    for item in items {
+        write_bytes(&mut sequence, &item);
+    }
+    write_bytes(out, &sequence);
+}
+
+impl SignedData {
+    /// Encodes the signed data of a signer. For v3 the SDK versions follow the certificates,
+    /// ahead of the additional attributes.
+    pub fn new(
+        digests: Vec<ContentDigest>,
+        certificates: Vec<Vec<u8>>,
+        additional_attributes: Vec<AdditionalAttribute>,
+        sdk_versions: Option<(u32, u32)>,
+    ) -> SignedData {
+        let mut raw = Vec::new();
+        write_sequence(&mut raw, digests.iter().map(|digest| {
+            let mut item = u32::from(digest.algorithm).to_le_bytes().to_vec();
+            write_bytes(&mut item, &digest.digest);
+            item
+        }));
+        write_sequence(&mut raw, certificates.iter().cloned());
+        if let Some((min, max)) = sdk_versions {
+            raw.extend_from_slice(&min.to_le_bytes());
+            raw.extend_from_slice(&max.to_le_bytes());
+        }
+        write_sequence(&mut raw, additional_attributes.iter().map(|attribute| {
+            let mut item = attribute.id.to_le_bytes().to_vec();
+            item.extend_from_slice(&attribute.value);
+            item
+        }));
+        SignedData {
+            raw,
+            digests,
+            certificates,
+            additional_attributes,
+            min_sdk_version: sdk_versions.map(|(min, _)| min),
+            max_sdk_version: sdk_versions.map(|(_, max)| max),
+        }
+    }
+
+    /// The value of the first additional attribute with the given ID.
+    pub fn additional_attribute(&self, id: u32) -> Option<&[u8]> {
+        self.additional_attributes.iter().find(|attribute| attribute.id == id).map(|attribute| &attribute.value[..])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AdditionalAttribute {
+    pub id: u32,
+    pub value: Vec<u8>,
+}
+
+named!(parse_content_digest<&[u8], ContentDigest>, do_parse!(
+    algorithm: le_u32 >>
+    digest: length_bytes!(le_u32) >>
+    (ContentDigest { algorithm: algorithm.into(), digest: digest.to_vec() })
+));
+
+named!(pub parse_signature<&[u8], Signature>, do_parse!(
+    algorithm: le_u32 >>
+    signature: length_bytes!(le_u32) >>
+    (Signature { algorithm: algorithm.into(), signature: signature.to_vec() })
+));
+
+named!(pub parse_additional_attribute<&[u8], AdditionalAttribute>, do_parse!(
+    id: le_u32 >>
+    value: rest >>
+    (AdditionalAttribute { id, value: value.to_vec() })
+));
+
+named!(parse_certificate<&[u8], Vec<u8>>, map!(rest, |c: &[u8]| c.to_vec()));
+
+named!(parse_sdk_versions<&[u8], (u32, u32)>, do_parse!(
+    min_sdk_version: le_u32 >>
+    max_sdk_version: le_u32 >>
+    ((min_sdk_version, max_sdk_version))
+));
+
+/// Parses a sequence of length prefixed items, every item has to be accepted by `parser`.
+pub(crate) fn parse_sequence<'a, T>(input: &'a [u8], parser: fn(&'a [u8]) -> IResult<&'a [u8], T>) -> IResult<&'a [u8], Vec<T>> {
+    let (rest, mut data) = try_parse!(input, length_bytes!(le_u32));
+    let mut items = Vec::new();
+    while !data.is_empty() {
+        let (remaining, item) = try_parse!(data, length_bytes!(le_u32));
+        let (_, item) = try_parse!(item, parser);
+        items.push(item);
+        data = remaining;
+    }
+    IResult::Done(rest, items)
+}
+
+fn parse_signed_data(input: &[u8], v3: bool) -> IResult<&[u8], SignedData> {
+    let (rest, raw) = try_parse!(input, length_bytes!(le_u32));
+    let (data, digests) = try_parse!(raw, apply!(parse_sequence, parse_content_digest));
+    let (data, certificates) = try_parse!(data, apply!(parse_sequence, parse_certificate));
+    let (data, (min_sdk_version, max_sdk_version)) = if v3 {
+        let (data, (min, max)) = try_parse!(data, parse_sdk_versions);
+        (data, (Some(min), Some(max)))
+    } else {
+        (data, (None, None))
+    };
+    let (_, additional_attributes) = try_parse!(data, apply!(parse_sequence, parse_additional_attribute));
+    IResult::Done(rest, SignedData {
+        raw: raw.to_vec(),
+        digests,
+        certificates,
+        additional_attributes,
+        min_sdk_version,
+        max_sdk_version,
+    })
+}
+
+fn parse_signer(input: &[u8], v3: bool) -> IResult<&[u8], Signer> {
+    let (data, signed_data) = try_parse!(input, apply!(parse_signed_data, v3));
+    let (data, (min_sdk_version, max_sdk_version)) = if v3 {
+        let (data, (min, max)) = try_parse!(data, parse_sdk_versions);
+        (data, (Some(min), Some(max)))
+    } else {
+        (data, (None, None))
+    };
+    let (data, signatures) = try_parse!(data, apply!(parse_sequence, parse_signature));
+    let (data, public_key) = try_parse!(data, length_bytes!(le_u32));
+    IResult::Done(data, Signer {
+        signed_data,
+        min_sdk_version,
+        max_sdk_version,
+        signatures,
+        public_key: public_key.to_vec(),
+    })
+}
+
+/// Encodes the value of a v2 or v3 signature scheme block, the inverse of `parse_signers`. The
+/// signed data is taken as encoded in `raw`, SDK versions are written when present.
+pub fn encode_signers(signers: &[Signer]) -> Vec<u8> {
+    let mut block = Vec::new();
+    write_sequence(&mut block, signers.iter().map(|signer| {
+        let mut encoded = Vec::new();
+        write_bytes(&mut encoded, &signer.signed_data.raw);
+        if let (Some(min), Some(max)) = (signer.min_sdk_version, signer.max_sdk_version) {
+            encoded.extend_from_slice(&min.to_le_bytes());
+            encoded.extend_from_slice(&max.to_le_bytes());
+        }
+        write_sequence(&mut encoded, signer.signatures.iter().map(|signature| {
+            let mut item = u32::from(signature.algorithm).to_le_bytes().to_vec();
+            write_bytes(&mut item, &signature.signature);
+            item
+        }));
+        write_bytes(&mut encoded, &signer.public_key);
+        encoded
+    }));
+    block
+}
+
+/// Parses the value of a v2 or v3 signature scheme block, a length prefixed sequence of
+/// length prefixed signers.
+pub fn parse_signers(block: &[u8], v3: bool) -> Option<Vec<Signer>> {
+    let (rest, mut data) = match length_bytes!(block, le_u32) {
+        IResult::Done(rest, data) => (rest, data),
+        _ => return None,
+    };
+    if !rest.is_empty() {
+        return None;
+    }
+    let mut signers = Vec::new();
+    while !data.is_empty() {
+        let (remaining, signer) = match length_bytes!(data, le_u32) {
+            IResult::Done(remaining, signer) => (remaining, signer),
+            _ => return None,
+        };
+        match parse_signer(signer, v3) {
+            IResult::Done(_, signer) => signers.push(signer),
+            _ => return None,
+        }
+        data = remaining;
+    }
+    Some(signers)
+}
+
+#[test]
+fn test_parse_v3_signer() {
+    // a v3 signer laid out by hand as the APK Signature Scheme v3 documentation specifies it
+    fn bytes(out: &mut Vec<u8>, data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+    }
+    let mut digest = 0x0103u32.to_le_bytes().to_vec();
+    bytes(&mut digest, &[0xaa; 32]);
+    let mut digests = Vec::new();
+    bytes(&mut digests, &digest);
+    let mut certificates = Vec::new();
+    bytes(&mut certificates, b"certificate");
+    let mut attributes = Vec::new();
+    bytes(&mut attributes, &[0x0d, 0x0c, 0x0b, 0x0a, 1, 2]);
+    let mut signed_data = Vec::new();
+    bytes(&mut signed_data, &digests);
+    bytes(&mut signed_data, &certificates);
+    signed_data.extend_from_slice(&28u32.to_le_bytes());
+    signed_data.extend_from_slice(&0x7fff_ffffu32.to_le_bytes());
+    bytes(&mut signed_data, &attributes);
+    let mut signature = 0x0103u32.to_le_bytes().to_vec();
+    bytes(&mut signature, b"signature");
+    let mut signatures = Vec::new();
+    bytes(&mut signatures, &signature);
+    let mut signer = Vec::new();
+    bytes(&mut signer, &signed_data);
+    signer.extend_from_slice(&28u32.to_le_bytes());
+    signer.extend_from_slice(&0x7fff_ffffu32.to_le_bytes());
+    bytes(&mut signer, &signatures);
+    bytes(&mut signer, b"public key");
+    let mut signers = Vec::new();
+    bytes(&mut signers, &signer);
+    let mut block = Vec::new();
+    bytes(&mut block, &signers);
+
+    let parsed = parse_signers(&block, true).unwrap();
+    assert_eq!(parsed.len(), 1);
+    let data = &parsed[0].signed_data;
+    assert_eq!(data.raw, signed_data);
+    assert_eq!(data.digests[0].algorithm, SignatureAlgorithm::RsaPkcs1Sha256);
+    assert_eq!(data.certificates, vec![b"certificate".to_vec()]);
+    assert_eq!((data.min_sdk_version, data.max_sdk_version), (Some(28), Some(0x7fff_ffff)));
+    assert_eq!(data.additional_attribute(0x0a0b0c0d), Some(&[1, 2][..]));
+    assert_eq!(parsed[0].public_key, b"public key");
+
+    // encoding produces the same layout
+    let encoded = SignedData::new(
+        data.digests.clone(),
+        data.certificates.clone(),
+        data.additional_attributes.clone(),
+        Some((28, 0x7fff_ffff)),
+    );
+    assert_eq!(encoded.raw, signed_data);
+    assert_eq!(encode_signers(&parsed), block);
+}
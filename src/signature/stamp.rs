@@ -0,0 +1,141 @@
+use std::convert::TryFrom;
+use nom::*;
+use crate::signature::lineage::SigningCertificateLineage;
+use crate::signature::signer::{self, AdditionalAttribute, Signature};
+
+/// Stamp attribute holding the time the stamp was created, seconds since the epoch.
+pub const STAMP_TIME_ATTRIBUTE_ID: u32 = 0xe43c_5946;
+/// Stamp attribute holding the proof-of-rotation of the stamp certificate.
+pub const STAMP_PROOF_OF_ROTATION_ATTRIBUTE_ID: u32 = 0x9d63_03f7;
+
+/// The source stamp an app store adds to the signing block of the APKs it distributes. It
+/// signs the content digests of the signature schemes with the key of the store, so it is lost
+/// when the APK is repackaged and re-signed.
+#[derive(Debug, Clone)]
+pub struct SourceStamp {
+    /// 1 or 2, the version of the source stamp block.
+    pub version: u32,
+    /// DER encoded X.509 certificate of the stamp signer.
+    pub certificate: Vec<u8>,
+    pub signed_digests: Vec<SignedDigests>,
+    /// Only in version 2, signed by `attribute_signatures`.
+    pub attributes: Vec<AdditionalAttribute>,
+    pub attribute_signatures: Vec<Signature>,
+}
+
+/// Signatures of the stamp over the content digests of a signature scheme.
+#[derive(Debug, Clone)]
+pub struct SignedDigests {
+    /// 1 for JAR signing, 2 and 3 for the v2 and v3 schemes. `None` in version 1 stamps which
+    /// only sign the digests of the v3 or else v2 signer.
+    pub scheme: Option<u32>,
+    pub signatures: Vec<Signature>,
+}
+
+fn parse_signed_digests(input: &[u8]) -> IResult<&[u8], SignedDigests> {
+    let (data, scheme) = try_parse!(input, le_u32);
+    let (data, signatures) = try_parse!(data, apply!(signer::parse_sequence, signer::parse_signature));
+    IResult::Done(data, SignedDigests { scheme: Some(scheme), signatures })
+}
+
+fn parse_stamp_v1(input: &[u8]) -> IResult<&[u8], SourceStamp> {
+    let (data, certificate) = try_parse!(input, length_bytes!(le_u32));
+    let (data, signatures) = try_parse!(data, apply!(signer::parse_sequence, signer::parse_signature));
+    IResult::Done(data, SourceStamp {
+        version: 1,
+        certificate: certificate.to_vec(),
+        signed_digests: vec![SignedDigests { scheme: None, signatures }],
+        attributes: Vec::new(),
+        attribute_signatures: Vec::new(),
+    })
+}
+
+fn parse_stamp_v2(input: &[u8]) -> IResult<&[u8], SourceStamp> {
+    let (data, certificate) = try_parse!(input, length_bytes!(le_u32));
+    let (mut data, signed_digests) = try_parse!(data, apply!(signer::parse_sequence, parse_signed_digests));
+    let mut attributes = Vec::new();
+    let mut attribute_signatures = Vec::new();
+    // the attributes were added later and may be missing
+    if !data.is_empty() {
+        let (remaining, encoded_attributes) = try_parse!(data, length_bytes!(le_u32));
+        let (_, parsed) = try_parse!(encoded_attributes, apply!(signer::parse_sequence, signer::parse_additional_attribute));
+        let (remaining, signatures) = try_parse!(remaining, apply!(signer::parse_sequence, signer::parse_signature));
+        attributes = parsed;
+        attribute_signatures = signatures;
+        data = remaining;
+    }
+    IResult::Done(data, SourceStamp {
+        version: 2,
+        certificate: certificate.to_vec(),
+        signed_digests,
+        attributes,
+        attribute_signatures,
+    })
+}
+
+impl SourceStamp {
+    /// Parses the value of a source stamp block of the given version.
+    pub fn parse(value: &[u8], version: u32) -> Option<SourceStamp> {
+        let data = match length_bytes!(value, le_u32) {
+            IResult::Done([], data) => data,
+            _ => return None,
+        };
+        let parsed = match version {
+            1 => parse_stamp_v1(data),
+            2 => parse_stamp_v2(data),
+            _ => return None,
+        };
+        match parsed {
+            IResult::Done([], stamp) => Some(stamp),
+            _ => None,
+        }
+    }
+
+    /// The value of the first stamp attribute with the given ID.
+    pub fn attribute(&self, id: u32) -> Option<&[u8]> {
+        self.attributes.iter().find(|attribute| attribute.id == id).map(|attribute| &attribute.value[..])
+    }
+
+    /// When the stamp was created, seconds since the epoch.
+    pub fn timestamp(&self) -> Option<u64> {
+        let value = self.attribute(STAMP_TIME_ATTRIBUTE_ID)?;
+        let bytes = <[u8; 8]>::try_from(value).ok()?;
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    /// The proof-of-rotation of a stamp whose key was rotated. Not verified.
+    pub fn lineage(&self) -> Option<SigningCertificateLineage> {
+        self.attribute(STAMP_PROOF_OF_ROTATION_ATTRIBUTE_ID).and_then(SigningCertificateLineage::parse)
+    }
+}
+
+#[test]
+fn test_parse_source_stamp() {
+    use crate::signature::signer::length_prefixed as lp;
+
+    let mut signature = 0x0201u32.to_le_bytes().to_vec();
+    signature.extend_from_slice(&lp(b"sig"));
+    let signatures = lp(&lp(&signature));
+    let mut scheme = 3u32.to_le_bytes().to_vec();
+    scheme.extend_from_slice(&signatures);
+    let mut time = STAMP_TIME_ATTRIBUTE_ID.to_le_bytes().to_vec();
+    time.extend_from_slice(&1_600_000_000u64.to_le_bytes());
+
+    let mut stamp = lp(b"cert");
+    stamp.extend_from_slice(&lp(&lp(&scheme)));
+    stamp.extend_from_slice(&lp(&lp(&lp(&time))));
+    stamp.extend_from_slice(&signatures);
+    let stamp = SourceStamp::parse(&lp(&stamp), 2).unwrap();
+    assert_eq!(stamp.certificate, b"cert");
+    assert_eq!(stamp.signed_digests.len(), 1);
+    assert_eq!(stamp.signed_digests[0].scheme, Some(3));
+    assert_eq!(stamp.signed_digests[0].signatures[0].signature, b"sig");
+    assert_eq!(stamp.timestamp(), Some(1_600_000_000));
+    assert_eq!(stamp.attribute_signatures.len(), 1);
+
+    let mut stamp = lp(b"cert");
+    stamp.extend_from_slice(&signatures);
+    let stamp = SourceStamp::parse(&lp(&stamp), 1).unwrap();
+    assert_eq!(stamp.signed_digests[0].scheme, None);
+    assert_eq!(stamp.timestamp(), None);
+}
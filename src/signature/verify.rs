@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use crate::zip::archive::ZipArchive;
+use crate::signature::certificate;
+use crate::signature::block::{SigningBlock, ROTATION_MIN_SDK_VERSION_ATTRIBUTE_ID, SIGNATURE_SCHEME_V2_BLOCK_ID,
+    SIGNATURE_SCHEME_V31_BLOCK_ID, SIGNATURE_SCHEME_V3_BLOCK_ID};
+use crate::signature::digest::{self, ContentDigestAlgorithm};
+use crate::signature::jar::{self, JarReport};
+use crate::signature::key;
+use crate::signature::lineage::{SigningCertificateLineage, PROOF_OF_ROTATION_ATTRIBUTE_ID};
+use crate::signature::signer::{self, SignatureAlgorithm, Signer};
+
+/// Additional attribute of v2 signers naming the newer scheme the APK is also signed with. It
+/// keeps the newer signature from being stripped to downgrade verification to v2.
+pub const STRIPPING_PROTECTION_ATTRIBUTE_ID: u32 = 0xbeef_f00d;
+
+/// Why a signer or signature scheme failed verification.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerificationError {
+    /// The signature scheme block can not be parsed.
+    InvalidBlock,
+    /// The ZIP sections covered by the digests are not contiguous.
+    UnexpectedZipLayout,
+    NoSigners,
+    NoSignatures,
+    /// None of the signatures uses an algorithm whose content digest can be verified.
+    NoSupportedSignatures,
+    UnsupportedAlgorithm(SignatureAlgorithm),
+    InvalidPublicKey,
+    InvalidSignature(SignatureAlgorithm),
+    NoCertificates,
+    /// The first certificate is not for the public key of the signer.
+    PublicKeyMismatch,
+    /// The signed digests are not for the same algorithms as the signatures.
+    AlgorithmMismatch,
+    DigestMismatch(SignatureAlgorithm),
+    /// The SDK versions of a v3 signer differ from those in its signed data.
+    SdkVersionMismatch,
+    /// A v1 or v2 signer claims the APK is also signed with this scheme, but its block is
+    /// missing.
+    SchemeStripped(u32),
+    /// A v3 signer declares a key rotation for newer SDKs, but the v3.1 block is missing.
+    RotationStripped,
+    /// The v3.1 signers start at another SDK version than the v3 signer declares for the
+    /// rotation.
+    RotationMinSdkMismatch,
+    /// The proof-of-rotation can not be parsed or a certificate is not signed by its
+    /// predecessor.
+    InvalidLineage,
+    /// The last certificate of the proof-of-rotation is not the one of the signer.
+    LineageMismatch,
+    /// The Merkle tree of a v4 signature uses another hash algorithm or block size than
+    /// SHA-256 over 4096 byte blocks.
+    UnsupportedHashingInfo,
+    /// The Merkle tree root of a v4 signature is not the one of the APK.
+    RootHashMismatch,
+    /// The Merkle tree in a v4 signature file is not the one of the APK.
+    MerkleTreeMismatch,
+    /// The certificate of a v4 signature is not the one of the v3 or v2 signer.
+    SignerMismatch,
+    /// The APK digest of a v4 signature is not the content digest of the v3 or v2 signer.
+    ApkDigestMismatch,
+    MissingManifest,
+    InvalidManifest,
+    DuplicateManifestSection(String),
+    /// The manifest lists an entry the APK does not contain.
+    MissingEntry(String),
+    EntryDigestMismatch(String),
+    /// A signature block file has no signature file.
+    MissingSignatureFile,
+    InvalidSignatureFile,
+    /// The PKCS#7 signature block can not be parsed or uses unsupported algorithms.
+    InvalidSignatureBlock,
+    /// The PKCS#7 signature does not verify over the signature file.
+    InvalidJarSignature,
+    /// The digest of the manifest main attributes in the signature file does not match.
+    ManifestDigestMismatch,
+    /// The signature file has a section for an entry the manifest does not list.
+    MissingManifestSection(String),
+    /// The digest of a manifest section in the signature file does not match.
+    SectionDigestMismatch(String),
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerificationError::InvalidBlock => write!(f, "invalid signature scheme block"),
+            VerificationError::UnexpectedZipLayout => write!(f, "central directory does not end at the end of central directory record"),
+            VerificationError::NoSigners => write!(f, "no signers"),
+            VerificationError::NoSignatures => write!(f, "no signatures"),
+            VerificationError::NoSupportedSignatures => write!(f, "no signatures with supported algorithms"),
+            VerificationError::UnsupportedAlgorithm(algorithm) => write!(f, "unsupported signature algorithm {:?}", algorithm),
+            VerificationError::InvalidPublicKey => write!(f, "invalid public key"),
+            VerificationError::InvalidSignature(algorithm) => write!(f, "{:?} signature does not verify", algorithm),
+            VerificationError::NoCertificates => write!(f, "no certificates"),
+            VerificationError::PublicKeyMismatch => write!(f, "public key does not match the certificate"),
+            VerificationError::AlgorithmMismatch => write!(f, "signature and digest algorithms differ"),
+            VerificationError::DigestMismatch(algorithm) => write!(f, "{:?} content digest does not match", algorithm),
+            VerificationError::SdkVersionMismatch => write!(f, "SDK versions differ from the signed data"),
+            VerificationError::SchemeStripped(scheme) => write!(f, "APK Signature Scheme v{} block was stripped", scheme),
+            VerificationError::RotationStripped => write!(f, "APK Signature Scheme v3.1 block was stripped"),
+            VerificationError::RotationMinSdkMismatch => write!(f, "v3.1 signers do not start at the rotation min SDK version"),
+            VerificationError::InvalidLineage => write!(f, "invalid signing certificate lineage"),
+            VerificationError::LineageMismatch => write!(f, "signing certificate lineage does not end with the signer certificate"),
+            VerificationError::UnsupportedHashingInfo => write!(f, "unsupported Merkle tree hash algorithm or block size"),
+            VerificationError::RootHashMismatch => write!(f, "Merkle tree root hash does not match"),
+            VerificationError::MerkleTreeMismatch => write!(f, "Merkle tree does not match"),
+            VerificationError::SignerMismatch => write!(f, "certificate differs from the v2/v3 signer"),
+            VerificationError::ApkDigestMismatch => write!(f, "APK digest differs from the v2/v3 signer"),
+            VerificationError::MissingManifest => write!(f, "{} is missing", jar::MANIFEST_NAME),
+            VerificationError::InvalidManifest => write!(f, "invalid manifest"),
+            VerificationError::DuplicateManifestSection(name) => write!(f, "duplicate manifest section for {}", name),
+            VerificationError::MissingEntry(name) => write!(f, "{} is listed in the manifest but missing", name),
+            VerificationError::EntryDigestMismatch(name) => write!(f, "digest of {} does not match the manifest", name),
+            VerificationError::MissingSignatureFile => write!(f, "signature block without signature file"),
+            VerificationError::InvalidSignatureFile => write!(f, "invalid signature file"),
+            VerificationError::InvalidSignatureBlock => write!(f, "invalid PKCS#7 signature block"),
+            VerificationError::InvalidJarSignature => write!(f, "PKCS#7 signature does not verify"),
+            VerificationError::ManifestDigestMismatch => write!(f, "digest of the manifest main attributes does not match"),
+            VerificationError::MissingManifestSection(name) => write!(f, "manifest has no section for {}", name),
+            VerificationError::SectionDigestMismatch(name) => write!(f, "digest of the manifest section for {} does not match", name),
+        }
+    }
+}
+
+/// Outcome of verifying the JAR, v2, v3 and v3.1 signatures of an APK.
+#[derive(Debug, Clone, Default)]
+pub struct SignatureReport {
+    pub v1: JarReport,
+    pub v2: SchemeReport,
+    pub v3: SchemeReport,
+    pub v31: SchemeReport,
+}
+
+impl SignatureReport {
+    /// Whether the APK is signed with at least one scheme and all its schemes verify.
+    pub fn is_verified(&self) -> bool {
+        (self.v1.signed || self.v2.signed || self.v3.signed || self.v31.signed)
+            && (!self.v1.signed || self.v1.is_verified())
+            && (!self.v2.signed || self.v2.is_verified())
+            && (!self.v3.signed || self.v3.is_verified())
+            && (!self.v31.signed || self.v31.is_verified())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SchemeReport {
+    /// Whether the APK has a block for this scheme.
+    pub signed: bool,
+    pub signers: Vec<SignerReport>,
+    /// Errors concerning the scheme as a whole rather than a single signer.
+    pub errors: Vec<VerificationError>,
+}
+
+impl SchemeReport {
+    pub fn is_verified(&self) -> bool {
+        self.signed && self.errors.is_empty() && self.signers.iter().all(SignerReport::is_verified)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SignerReport {
+    /// DER encoded X.509 certificates, the first one belongs to the signing key.
+    pub certificates: Vec<Vec<u8>>,
+    pub min_sdk_version: Option<u32>,
+    pub max_sdk_version: Option<u32>,
+    /// The proof-of-rotation of a v3 or v3.1 signer whose key was rotated.
+    pub lineage: Option<SigningCertificateLineage>,
+    pub errors: Vec<VerificationError>,
+}
+
+impl SignerReport {
+    pub fn is_verified(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Content digests of an APK, each computed only once for all signers.
+struct ContentDigests<'a> {
+    archive: &'a ZipArchive,
+    signing_block_offset: u64,
+    digests: HashMap<ContentDigestAlgorithm, Vec<u8>>,
+}
+
+impl<'a> ContentDigests<'a> {
+    fn get(&mut self, algorithm: ContentDigestAlgorithm) -> io::Result<&[u8]> {
+        if !self.digests.contains_key(&algorithm) {
+            let digest = digest::content_digest(self.archive, self.signing_block_offset, algorithm)?;
+            self.digests.insert(algorithm, digest);
+        }
+        Ok(&self.digests[&algorithm])
+    }
+}
+
+/// Verifies the JAR, v2, v3 and v3.1 signatures of an archive. Fails only for I/O errors and a
+/// malformed APK signing block, everything else is reported.
+pub fn verify(archive: &ZipArchive) -> io::Result<SignatureReport> {
+    let mut report = match SigningBlock::read(archive)? {
+        Some(block) => verify_signing_block(archive, &block)?,
+        None => SignatureReport::default(),
+    };
+    report.v1 = jar::verify(archive, report.v2.signed, report.v3.signed)?;
+    Ok(report)
+}
+
+fn verify_signing_block(archive: &ZipArchive, block: &SigningBlock) -> io::Result<SignatureReport> {
+    let mut digests = ContentDigests {
+        archive,
+        signing_block_offset: block.offset,
+        digests: HashMap::new(),
+    };
+    let layout_valid = archive.central_directory_offset().checked_add(archive.central_directory_size())
+        == Some(archive.end_of_central_directory_offset());
+
+    let mut v2 = verify_scheme(block, SIGNATURE_SCHEME_V2_BLOCK_ID, layout_valid, &mut digests)?;
+    let mut v3 = verify_scheme(block, SIGNATURE_SCHEME_V3_BLOCK_ID, layout_valid, &mut digests)?;
+    let v31 = verify_scheme(block, SIGNATURE_SCHEME_V31_BLOCK_ID, layout_valid, &mut digests)?;
+
+    if !v3.signed && v2_claims_scheme(block, 3) {
+        v2.errors.push(VerificationError::SchemeStripped(3));
+    }
+    if let Some(rotation_min_sdk_version) = v3_rotation_min_sdk_version(block) {
+        // the lowest SDK version of the v3.1 signers has to be the one the v3 signer declares,
+        // or else devices in between would accept the APK with neither signer
+        let v31_min_sdk_version = v31.signers.iter().filter_map(|signer| signer.min_sdk_version).min();
+        if !v31.signed {
+            v3.errors.push(VerificationError::RotationStripped);
+        } else if v31_min_sdk_version.map(|version| version.to_le_bytes().to_vec()) != Some(rotation_min_sdk_version) {
+            v3.errors.push(VerificationError::RotationMinSdkMismatch);
+        }
+    }
+    Ok(SignatureReport { v1: JarReport::default(), v2, v3, v31 })
+}
+
+/// Whether a v2 signer declares the APK to be signed with a newer scheme as well.
+fn v2_claims_scheme(block: &SigningBlock, scheme: u32) -> bool {
+    let signers = block.get(SIGNATURE_SCHEME_V2_BLOCK_ID)
+        .and_then(|value| signer::parse_signers(value, false))
+        .unwrap_or_default();
+    signers.iter()
+        .flat_map(|signer| &signer.signed_data.additional_attributes)
+        .any(|attribute| attribute.id == STRIPPING_PROTECTION_ATTRIBUTE_ID && attribute.value == scheme.to_le_bytes())
+}
+
+/// The encoded SDK version from which on a v3 signer declares a v3.1 block with the rotated
+/// key, `None` if it declares none.
+fn v3_rotation_min_sdk_version(block: &SigningBlock) -> Option<Vec<u8>> {
+    let signers = block.get(SIGNATURE_SCHEME_V3_BLOCK_ID)
+        .and_then(|value| signer::parse_signers(value, true))
+        .unwrap_or_default();
+    signers.iter().find_map(|signer| signer.signed_data.additional_attribute(ROTATION_MIN_SDK_VERSION_ATTRIBUTE_ID).map(<[u8]>::to_vec))
+}
+
+fn verify_scheme(block: &SigningBlock, id: u32, layout_valid: bool, digests: &mut ContentDigests) -> io::Result<SchemeReport> {
+    let mut report = SchemeReport::default();
+    let value = match block.get(id) {
+        Some(value) => value,
+        None => return Ok(report),
+    };
+    report.signed = true;
+
+    let v3 = id == SIGNATURE_SCHEME_V3_BLOCK_ID || id == SIGNATURE_SCHEME_V31_BLOCK_ID;
+    let signers = match signer::parse_signers(value, v3) {
+        Some(signers) => signers,
+        None => {
+            report.errors.push(VerificationError::InvalidBlock);
+            return Ok(report);
+        }
+    };
+    if signers.is_empty() {
+        report.errors.push(VerificationError::NoSigners);
+    }
+    if !layout_valid {
+        report.errors.push(VerificationError::UnexpectedZipLayout);
+    }
+    for signer in &signers {
+        let mut errors = verify_signer(signer, layout_valid, digests)?;
+        let lineage = if v3 { verify_lineage(signer, &mut errors) } else { None };
+        report.signers.push(SignerReport {
+            certificates: signer.signed_data.certificates.clone(),
+            min_sdk_version: signer.min_sdk_version,
+            max_sdk_version: signer.max_sdk_version,
+            lineage,
+            errors,
+        });
+    }
+    Ok(report)
+}
+
+fn verify_signer(signer: &Signer, layout_valid: bool, digests: &mut ContentDigests) -> io::Result<Vec<VerificationError>> {
+    let mut errors = Vec::new();
+    let signed_data = &signer.signed_data;
+
+    if signer.signatures.is_empty() {
+        errors.push(VerificationError::NoSignatures);
+    }
+    let mut supported = false;
+    for signature in &signer.signatures {
+        match key::verify_signature(&signer.public_key, signature.algorithm, &signed_data.raw, &signature.signature) {
+            Ok(()) => supported |= signature.algorithm.content_digest_algorithm().is_some(),
+            Err(VerificationError::UnsupportedAlgorithm(_)) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    if !signer.signatures.is_empty() && !supported && errors.is_empty() {
+        errors.push(VerificationError::NoSupportedSignatures);
+    }
+
+    match signed_data.certificates.first() {
+        Some(certificate) => {
+            let info = certificate::parse_certificate_info(certificate);
+            if info.is_none_or(|info| info.public_key != signer.public_key) {
+                errors.push(VerificationError::PublicKeyMismatch);
+            }
+        }
+        None => errors.push(VerificationError::NoCertificates),
+    }
+
+    let signature_algorithms: Vec<_> = signer.signatures.iter().map(|s| s.algorithm).collect();
+    let digest_algorithms: Vec<_> = signed_data.digests.iter().map(|d| d.algorithm).collect();
+    if signature_algorithms != digest_algorithms {
+        errors.push(VerificationError::AlgorithmMismatch);
+    }
+
+    if signer.min_sdk_version != signed_data.min_sdk_version || signer.max_sdk_version != signed_data.max_sdk_version {
+        errors.push(VerificationError::SdkVersionMismatch);
+    }
+
+    // the digests are only trusted once the signatures over them verified
+    if errors.is_empty() && layout_valid {
+        for content_digest in &signed_data.digests {
+            if let Some(algorithm) = content_digest.algorithm.content_digest_algorithm() {
+                if digests.get(algorithm)? != &content_digest.digest[..] {
+                    errors.push(VerificationError::DigestMismatch(content_digest.algorithm));
+                }
+            }
+        }
+    }
+    Ok(errors)
+}
+
+/// Verifies the proof-of-rotation of a v3 signer, if it has one.
+fn verify_lineage(signer: &Signer, errors: &mut Vec<VerificationError>) -> Option<SigningCertificateLineage> {
+    let value = signer.signed_data.additional_attribute(PROOF_OF_ROTATION_ATTRIBUTE_ID)?;
+    let lineage = match SigningCertificateLineage::parse(value) {
+        Some(lineage) => lineage,
+        None => {
+            errors.push(VerificationError::InvalidLineage);
+            return None;
+        }
+    };
+    if let Err(e) = lineage.verify() {
+        errors.push(e);
+    } else if lineage.current() != signer.signed_data.certificates.first().map(|certificate| &certificate[..]) {
+        errors.push(VerificationError::LineageMismatch);
+    }
+    Some(lineage)
+}
+
+#[test]
+fn test_verify() {
+    let verify_bytes = |data: Vec<u8>| verify(&ZipArchive::from_bytes(data).unwrap()).unwrap();
+
+    let fixtures: [(&[u8], &[u8]); 4] = [
+        (include_bytes!("../../testdata/v2v3-rsa.apk"), include_bytes!("../../testdata/rsa.der")),
+        (include_bytes!("../../testdata/v2v3-rsa-pss.apk"), include_bytes!("../../testdata/rsa.der")),
+        (include_bytes!("../../testdata/v2v3-ec.apk"), include_bytes!("../../testdata/ec.der")),
+        (include_bytes!("../../testdata/v2v3-dsa.apk"), include_bytes!("../../testdata/dsa.der")),
+    ];
+    for (apk, certificate) in fixtures {
+        let report = verify_bytes(apk.to_vec());
+        assert!(report.is_verified(), "{:?}", report);
+        assert!(!report.v1.signed && report.v2.signed && report.v3.signed && !report.v31.signed);
+        assert_eq!(report.v3.signers[0].certificates, vec![certificate.to_vec()]);
+        assert_eq!((report.v3.signers[0].min_sdk_version, report.v3.signers[0].max_sdk_version), (Some(28), Some(0x7fff_ffff)));
+    }
+
+    let report = verify_bytes(include_bytes!("../../testdata/v2v3-algorithm-mismatch.apk").to_vec());
+    assert_eq!(report.v2.signers[0].errors, vec![VerificationError::AlgorithmMismatch]);
+    assert_eq!(report.v3.signers[0].errors, vec![VerificationError::AlgorithmMismatch]);
+
+    // a tampered entry changes the content digest
+    let mut apk = include_bytes!("../../testdata/v2v3-rsa.apk").to_vec();
+    let position = apk.windows(13).position(|w| w == b"Hello, world!").unwrap();
+    apk[position] = b'J';
+    let report = verify_bytes(apk);
+    assert!(!report.is_verified());
+    assert_eq!(report.v2.signers[0].errors, vec![VerificationError::DigestMismatch(SignatureAlgorithm::RsaPkcs1Sha256)]);
+    assert_eq!(report.v3.signers[0].errors, vec![VerificationError::DigestMismatch(SignatureAlgorithm::RsaPkcs1Sha256)]);
+
+    // data between the central directory and its end record is not covered by the digests
+    let mut apk = include_bytes!("../../testdata/v2v3-ec.apk").to_vec();
+    let end_of_central_directory = apk.len() - 22;
+    apk.splice(end_of_central_directory..end_of_central_directory, [0; 4]);
+    let report = verify_bytes(apk);
+    assert_eq!(report.v2.errors, vec![VerificationError::UnexpectedZipLayout]);
+    assert_eq!(report.v3.errors, vec![VerificationError::UnexpectedZipLayout]);
+    assert!(!report.is_verified());
+}
+
+#[test]
+fn test_verify_rotation() {
+    let verify_bytes = |data: &[u8]| verify(&ZipArchive::from_bytes(data.to_vec()).unwrap()).unwrap();
+    let rsa = include_bytes!("../../testdata/rsa.der").to_vec();
+    let ec = include_bytes!("../../testdata/ec.der").to_vec();
+
+    let report = verify_bytes(include_bytes!("../../testdata/v31-rotated.apk"));
+    assert!(report.is_verified(), "{:?}", report);
+    assert_eq!(report.v3.signers[0].certificates, vec![rsa.clone()]);
+    assert_eq!(report.v3.signers[0].max_sdk_version, Some(32));
+    assert_eq!(report.v31.signers[0].certificates, vec![ec.clone()]);
+    assert_eq!(report.v31.signers[0].min_sdk_version, Some(33));
+    let lineage = report.v31.signers[0].lineage.as_ref().unwrap();
+    let certificates: Vec<_> = lineage.nodes.iter().map(|node| node.certificate.clone()).collect();
+    assert_eq!(certificates, vec![rsa, ec]);
+
+    let report = verify_bytes(include_bytes!("../../testdata/v31-stripped.apk"));
+    assert_eq!(report.v3.errors, vec![VerificationError::RotationStripped]);
+    assert!(!report.is_verified());
+
+    let report = verify_bytes(include_bytes!("../../testdata/v31-min-sdk-mismatch.apk"));
+    assert!(report.v31.is_verified());
+    assert_eq!(report.v3.errors, vec![VerificationError::RotationMinSdkMismatch]);
+    assert!(!report.is_verified());
+
+    // the v3.1 signature is intact, but the old key did not sign the new certificate
+    let report = verify_bytes(include_bytes!("../../testdata/v31-invalid-lineage.apk"));
+    assert_eq!(report.v31.signers[0].errors, vec![VerificationError::InvalidLineage]);
+    assert!(report.v3.is_verified());
+    assert!(!report.is_verified());
+}
@@ -0,0 +1,103 @@
+use std::io::{self, Read};
+use sha2::{Digest, Sha256};
+use crate::signature::digest::read_chunk;
+
+/// Size of the data blocks and of the tree blocks holding their hashes.
+pub const BLOCK_SIZE: usize = 4096;
+
+/// An fs-verity style SHA-256 Merkle tree over a file, as used by APK Signature Scheme v4.
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    pub root_hash: Vec<u8>,
+    /// The levels of the tree from the top down, each padded to whole blocks. The root hash is
+    /// not included.
+    pub tree: Vec<u8>,
+    /// Size of the data the tree was computed over.
+    pub data_size: u64,
+}
+
+/// Hashes a block, the salt if any is put in front of it.
+fn hash_block(salt: &[u8], block: &[u8]) -> [u8; 32] {
+    let mut digest = Sha256::new();
+    digest.update(salt);
+    digest.update(block);
+    digest.finalize().into()
+}
+
+/// Hashes every block of `data`, the last one padded with zeros.
+fn hash_level(salt: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut hashes = Vec::with_capacity(data.len() / BLOCK_SIZE * 32);
+    for block in data.chunks(BLOCK_SIZE) {
+        if block.len() < BLOCK_SIZE {
+            let mut padded = block.to_vec();
+            pad_to_block(&mut padded);
+            hashes.extend_from_slice(&hash_block(salt, &padded));
+        } else {
+            hashes.extend_from_slice(&hash_block(salt, block));
+        }
+    }
+    hashes
+}
+
+fn pad_to_block(data: &mut Vec<u8>) {
+    let len = data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    data.resize(len, 0);
+}
+
+/// Computes the Merkle tree over everything `r` returns. Each level hashes the blocks of the
+/// level below, the first one the data blocks, until a level fits in a single block whose hash
+/// is the root. The root hash of empty data is all zeros.
+pub fn merkle_tree<R: Read>(mut r: R, salt: &[u8]) -> io::Result<MerkleTree> {
+    let mut level = Vec::new();
+    let mut data_size = 0;
+    let mut buf = vec![0; BLOCK_SIZE];
+    loop {
+        let len = read_chunk(&mut r, &mut buf)?;
+        if len == 0 {
+            break;
+        }
+        buf[len..].iter_mut().for_each(|b| *b = 0);
+        level.extend_from_slice(&hash_block(salt, &buf));
+        data_size += len as u64;
+        if len < BLOCK_SIZE {
+            break;
+        }
+    }
+    if data_size == 0 {
+        return Ok(MerkleTree { root_hash: vec![0; 32], tree: Vec::new(), data_size });
+    }
+
+    let mut levels = Vec::new();
+    loop {
+        pad_to_block(&mut level);
+        if level.len() == BLOCK_SIZE {
+            let root_hash = hash_block(salt, &level).to_vec();
+            levels.push(level);
+            let tree = levels.into_iter().rev().flatten().collect();
+            return Ok(MerkleTree { root_hash, tree, data_size });
+        }
+        let next = hash_level(salt, &level);
+        levels.push(level);
+        level = next;
+    }
+}
+
+#[test]
+fn test_merkle_tree() {
+    let data = vec![0x42; BLOCK_SIZE * 200 + 1];
+    let tree = merkle_tree(&data[..], b"").unwrap();
+    assert_eq!(tree.data_size, data.len() as u64);
+    // 201 data block hashes take two blocks, their two hashes fit into the top block
+    assert_eq!(tree.tree.len(), 3 * BLOCK_SIZE);
+
+    let mut leaves = hash_level(b"", &data);
+    assert_eq!(leaves.len(), 201 * 32);
+    pad_to_block(&mut leaves);
+    let mut top = hash_level(b"", &leaves);
+    pad_to_block(&mut top);
+    assert_eq!(tree.tree[..BLOCK_SIZE], top[..]);
+    assert_eq!(tree.tree[BLOCK_SIZE..], leaves[..]);
+    assert_eq!(tree.root_hash, Sha256::digest(&top).to_vec());
+
+    assert_eq!(merkle_tree(&b""[..], b"").unwrap().root_hash, vec![0; 32]);
+}
@@ -0,0 +1,42 @@
+use std::io;
+use crate::zip::error::ZipError;
+
+/// Header id of the extra field record zipalign and apksigner pad local headers with. Its data
+/// is the alignment as `u16` followed by the zero padding.
+pub const ALIGNMENT_EXTRA_FIELD_ID: u16 = 0xD935;
+
+/// A stored entry whose data does not start at a multiple of the alignment Android requires.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MisalignedEntry {
+    pub file_name: String,
+    pub data_offset: u64,
+    pub alignment: u64,
+}
+
+/// Alignment of the data of stored entries as produced by `zipalign -p`: shared libraries are
+/// page aligned so they can be mapped directly from the APK, everything else is 4 byte aligned.
+pub fn required_alignment(file_name: &str, page_size: u64) -> u64 {
+    if file_name.ends_with(".so") {
+        page_size
+    } else {
+        4
+    }
+}
+
+/// Number of bytes needed to move `offset` to the next multiple of `alignment`, none for an
+/// alignment of 0.
+pub fn padding_for(offset: u64, alignment: u64) -> u64 {
+    if alignment == 0 {
+        return 0;
+    }
+    (alignment - offset % alignment) % alignment
+}
+
+/// Page sizes have to be powers of two that fit the alignment record, like the 4096 and 16384
+/// Android uses.
+pub fn check_page_size(page_size: u64) -> io::Result<()> {
+    if !page_size.is_power_of_two() || !(4..=0x8000).contains(&page_size) {
+        return Err(ZipError::InvalidPageSize(page_size).into());
+    }
+    Ok(())
+}
@@ -1,20 +1,35 @@
 use std::io::{self, Read, Seek};
-use std::fs::File;
 use std::io::SeekFrom;
-use std::rc::Rc;
-use crate::zip::io::{Open, FileReader};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use crate::zip::io::{FileReader, MemoryReader, MmapReader, SharedReader};
 use crate::zip::parser;
 use nom::*;
-use crate::zip::parser::CentralDirectoryFileHeader;
+use crate::zip::parser::{CentralDirectoryFileHeader, EndOfCentralDirectory};
 use std::borrow::Cow;
 use flate2::read::DeflateDecoder;
 use crate::zip::io::ReaderWrapper;
+use crate::zip::error::ZipError;
+use crate::zip::crc32::Crc32Reader;
+use crate::zip::audit::{self, AuditFinding};
+use crate::zip::align::{self, MisalignedEntry};
+use crate::zip::extra::ExtraFields;
+use crate::zip::time::{self, DateTime};
+use crate::zip::glob::glob_match;
+use crate::zip::limits::{Limit, LimitState, LimitedReader, Limits, MAX_PREALLOCATION};
+use crate::signature::block::SigningBlock;
+use crate::signature::error::SignatureError;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Compression {
     Store,
     Deflate,
     Bzip2,
     LZMA,
+    Zstd,
+    Other(u16),
 }
 
 impl From<u16> for Compression {
@@ -24,52 +39,273 @@ impl From<u16> for Compression {
             8 => Compression::Deflate,
             12 => Compression::Bzip2,
             14 => Compression::LZMA,
-            _ => unimplemented!(),
+            93 => Compression::Zstd,
+            c => Compression::Other(c),
         }
     }
 }
 
-fn get_range_of_central_directory<R: Read + Seek>(data: &mut R) -> io::Result<(usize, usize)> {
-    let seek_offset = data.seek(SeekFrom::End(-1024))? as usize;
-    let mut buf = vec![0; 1024];
+/// The end of central directory record is 22 bytes long and followed by a comment of up to
+/// 65535 bytes, so it has to start within the last 65557 bytes of the archive.
+const MAX_END_OF_CENTRAL_DIRECTORY_LEN: u64 = 22 + 0xFFFF;
+
+/// Finds the end of central directory record, returns it along with the offset of the classic
+/// record (a ZIP64 record is located in front of it).
+fn get_range_of_central_directory<R: Read + Seek>(data: &mut R) -> io::Result<(EndOfCentralDirectory, u64)> {
+    let archive_len = data.seek(SeekFrom::End(0))?;
+    if archive_len < 22 {
+        return Err(ZipError::EndOfCentralDirectoryNotFound.into());
+    }
+    let window_len = archive_len.min(MAX_END_OF_CENTRAL_DIRECTORY_LEN);
+    let window_offset = data.seek(SeekFrom::End(-(window_len as i64)))?;
+    let mut buf = vec![0; window_len as usize];
     data.read_exact(&mut buf)?;
-    for (offset, _) in buf.windows(4).enumerate().filter(|(_, w)| w == &[0x50, 0x4b, 0x05, 0x06]).rev() {
-        let comment_len_pos = offset + 20;
-        if comment_len_pos > buf.len() {
+
+    // Signatures can also appear inside the comment, so the last candidate whose comment
+    // ends exactly at the end of the archive and which points to a central directory wins.
+    let mut error = ZipError::EndOfCentralDirectoryNotFound;
+    for offset in (0..=buf.len() - 22).rev() {
+        if buf[offset..offset + 4] != [0x50, 0x4b, 0x05, 0x06] {
             continue;
         }
-        let comment_len = buf[comment_len_pos] as usize + (buf[comment_len_pos + 1] as usize) << 8;
-        if offset + 22 + comment_len != 1024 {
-            continue;
+        let eocd = match parser::parse_end_of_central_directory(&buf[offset..]) {
+            IResult::Done([], eocd) => eocd,
+            _ => continue,
+        };
+
+        let eocd_offset = window_offset + offset as u64;
+        let (eocd, directory_end) = match read_zip64_end_of_central_directory(data, eocd_offset) {
+            // only the classic record carries the archive comment
+            Ok(Some((zip64_offset, zip64))) => (EndOfCentralDirectory { comment: eocd.comment, ..zip64 }, zip64_offset),
+            Ok(None) => (eocd, eocd_offset),
+            Err(e) => {
+                error = e;
+                continue;
+            }
+        };
+
+        match validate_central_directory(data, &eocd, directory_end) {
+            Ok(()) => return Ok((eocd, eocd_offset)),
+            Err(e) => error = e,
         }
+    }
+
+    Err(error.into())
+}
+
+fn validate_central_directory<R: Read + Seek>(data: &mut R, eocd: &EndOfCentralDirectory, directory_end: u64) -> Result<(), ZipError> {
+    let offset = eocd.central_directory_offset;
+    let size = eocd.central_directory_size;
+    if offset.checked_add(size).is_none_or(|end| end > directory_end) {
+        return Err(ZipError::CentralDirectoryOutOfBounds { offset, size });
+    }
+    if size == 0 {
+        return Ok(());
+    }
 
-        let offset_offset = offset + 16;
-        let mut cd_offset = buf[offset_offset] as usize;
-        cd_offset += (buf[offset_offset + 1] as usize) << 8;
-        cd_offset += (buf[offset_offset + 2] as usize) << 16;
-        cd_offset += (buf[offset_offset + 3] as usize) << 24;
+    let mut signature = [0; 4];
+    let read = data.seek(SeekFrom::Start(offset)).and_then(|_| data.read_exact(&mut signature));
+    if read.is_err() || signature != [0x50, 0x4b, 0x01, 0x02] {
+        return Err(ZipError::CentralDirectorySignatureMismatch { offset });
+    }
+    Ok(())
+}
 
-        let size_offset = offset + 12;
-        let mut cd_size = buf[size_offset] as usize;
-        cd_size += (buf[size_offset + 1] as usize) << 8;
-        cd_size += (buf[size_offset + 2] as usize) << 16;
-        cd_size += (buf[size_offset + 3] as usize) << 24;
+/// Archives with more than 65535 entries or a central directory beyond 4 GiB carry a ZIP64 end
+/// of central directory record. It is found through the locator which directly precedes the
+/// classic end of central directory record.
+fn read_zip64_end_of_central_directory<R: Read + Seek>(data: &mut R, eocd_offset: u64) -> Result<Option<(u64, EndOfCentralDirectory)>, ZipError> {
+    if eocd_offset < 20 + 56 {
+        return Ok(None);
+    }
+    let mut buf = vec![0; 20];
+    data.seek(SeekFrom::Start(eocd_offset - 20))
+        .and_then(|_| data.read_exact(&mut buf))
+        .map_err(|_| ZipError::InvalidZip64EndOfCentralDirectory)?;
+    let record_offset = match parser::parse_zip64_end_of_central_directory_locator(&buf) {
+        IResult::Done(_, record_offset) => record_offset,
+        _ => return Ok(None),
+    };
 
-        return Ok((cd_offset, cd_size));
+    if record_offset > eocd_offset - 20 - 56 {
+        return Err(ZipError::InvalidZip64EndOfCentralDirectory);
+    }
+    let mut buf = vec![0; 56];
+    data.seek(SeekFrom::Start(record_offset))
+        .and_then(|_| data.read_exact(&mut buf))
+        .map_err(|_| ZipError::InvalidZip64EndOfCentralDirectory)?;
+    if let IResult::Done(_, record) = parser::parse_zip64_end_of_central_directory(&buf) {
+        return Ok(Some((record_offset, record)));
     }
 
-    Err(io::Error::new(io::ErrorKind::Other, "end of central directory signature not found"))
+    Err(ZipError::InvalidZip64EndOfCentralDirectory)
+}
+
+/// Compressed bytes handed to the LZMA decoder at a time. The output of each chunk is passed on
+/// before more is decoded, so the limits are checked after every output buffer. A few bytes of
+/// a crafted stream can still expand to a large buffer, at most the declared uncompressed size.
+#[cfg(feature = "lzma")]
+const LZMA_INPUT_CHUNK: usize = 64;
+
+/// LZMA entries start with a 2 byte version and the length of the LZMA properties, followed by
+/// the properties and the raw LZMA stream without the uncompressed size the `.lzma` format has.
+/// The stream is decoded incrementally as it is read.
+#[cfg(feature = "lzma")]
+struct LzmaReader<R> {
+    raw: R,
+    stream: Option<lzma_rs::decompress::Stream<Vec<u8>>>,
+    output: Vec<u8>,
+    position: usize,
+}
+
+#[cfg(feature = "lzma")]
+impl<R: Read> LzmaReader<R> {
+    fn new(mut raw: R, uncompressed_size: u64) -> io::Result<LzmaReader<R>> {
+        let mut header = [0; 4];
+        raw.read_exact(&mut header)?;
+        let options = lzma_rs::decompress::Options {
+            unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(uncompressed_size)),
+            ..Default::default()
+        };
+        Ok(LzmaReader {
+            raw,
+            stream: Some(lzma_rs::decompress::Stream::new_with_options(&options, Vec::new())),
+            output: Vec::new(),
+            position: 0,
+        })
+    }
+}
+
+#[cfg(feature = "lzma")]
+impl<R: Read> Read for LzmaReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::io::Write;
+
+        while self.position == self.output.len() {
+            let stream = match self.stream.as_mut() {
+                Some(stream) => stream,
+                None => return Ok(0),
+            };
+            let mut input = [0; LZMA_INPUT_CHUNK];
+            let n = self.raw.read(&mut input)?;
+            self.output.clear();
+            self.position = 0;
+            if n == 0 {
+                let stream = self.stream.take().unwrap();
+                self.output = stream.finish().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            } else {
+                let mut input = &input[..n];
+                // nothing is taken once the declared size is reached, an end marker may follow
+                while !input.is_empty() {
+                    match stream.write(input)? {
+                        0 => break,
+                        written => input = &input[written..],
+                    }
+                }
+                if let Some(output) = stream.get_output_mut() {
+                    std::mem::swap(&mut self.output, output);
+                }
+            }
+        }
+        let n = buf.len().min(self.output.len() - self.position);
+        buf[..n].copy_from_slice(&self.output[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
 }
 
 pub struct ZipArchive {
     reader: ReaderWrapper,
-    entries: Rc<Vec<CentralDirectoryFileHeader>>,
+    entries: Arc<Vec<CentralDirectoryFileHeader>>,
+    central_directory_offset: u64,
+    central_directory_size: u64,
+    end_of_central_directory_offset: u64,
+    comment: Vec<u8>,
+    limits: Arc<LimitState>,
+    crc_checked: Arc<[AtomicBool]>,
+    /// Index of the first entry with each name.
+    names: HashMap<Vec<u8>, usize>,
+    duplicate_names: Vec<String>,
 }
 
 #[derive(Debug)]
 pub struct ZipEntry {
     reader: ReaderWrapper,
     pub header: CentralDirectoryFileHeader,
+    limits: Arc<LimitState>,
+    index: usize,
+    /// Per entry of the archive, whether the CRC-32 of borrowed data was checked already.
+    crc_checked: Arc<[AtomicBool]>,
+}
+
+/// Options for opening an archive, mainly limits protecting against decompression bombs and
+/// archives crafted to exhaust memory. All limits are unset by default. Independent of them,
+/// no entry ever yields more data than its header declares.
+///
+/// ```no_run
+/// use apk_rs::zip::archive::OpenOptions;
+///
+/// let archive = OpenOptions::new()
+///     .max_entry_size(64 * 1024 * 1024)
+///     .max_compression_ratio(100)
+///     .open("app.apk");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    limits: Limits,
+}
+
+impl OpenOptions {
+    pub fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    /// Maximum number of bytes a single entry may extract to.
+    pub fn max_entry_size(&mut self, max: u64) -> &mut OpenOptions {
+        self.limits.max_entry_size = Some(max);
+        self
+    }
+
+    /// Maximum number of bytes extracted from all entries together, over the lifetime of the
+    /// archive. Entries that are extracted more than once count each time. A long-lived archive
+    /// can start a fresh budget for each operation with `ZipArchive::reset_total_uncompressed`.
+    pub fn max_total_uncompressed(&mut self, max: u64) -> &mut OpenOptions {
+        self.limits.max_total_uncompressed = Some(max);
+        self
+    }
+
+    /// Maximum ratio of extracted to compressed size of an entry.
+    pub fn max_compression_ratio(&mut self, max: u64) -> &mut OpenOptions {
+        self.limits.max_compression_ratio = Some(max);
+        self
+    }
+
+    pub fn max_entry_count(&mut self, max: usize) -> &mut OpenOptions {
+        self.limits.max_entry_count = Some(max);
+        self
+    }
+
+    /// Maximum size of the central directory in bytes, it is read into memory when opening.
+    pub fn max_central_directory_size(&mut self, max: u64) -> &mut OpenOptions {
+        self.limits.max_central_directory_size = Some(max);
+        self
+    }
+
+    pub fn open(&self, path: &str) -> io::Result<ZipArchive> {
+        ZipArchive::from_reader_wrapper(ReaderWrapper::File(FileReader::open(path)?), self.limits.clone())
+    }
+
+    pub fn open_mmap(&self, path: &str) -> io::Result<ZipArchive> {
+        ZipArchive::from_reader_wrapper(ReaderWrapper::Mmap(MmapReader::open(path)?), self.limits.clone())
+    }
+
+    pub fn from_bytes<D: Into<Arc<[u8]>>>(&self, data: D) -> io::Result<ZipArchive> {
+        ZipArchive::from_reader_wrapper(ReaderWrapper::Memory(MemoryReader::new(data.into())), self.limits.clone())
+    }
+
+    pub fn from_reader<R: Read + Seek + Send + 'static>(&self, reader: R) -> io::Result<ZipArchive> {
+        ZipArchive::from_reader_wrapper(ReaderWrapper::Shared(SharedReader::new(reader)), self.limits.clone())
+    }
 }
 
 impl ZipEntry {
@@ -85,59 +321,322 @@ impl ZipEntry {
         self.header.uncompressed_size as usize
     }
 
-    pub fn content(&self) -> io::Result<Box<Read>> {
+    /// The modification time from the extended timestamp extra field (0x5455) if present,
+    /// otherwise the DOS timestamp of the central directory.
+    pub fn last_modified(&self) -> DateTime {
+        time::modification_time(self.header.extra_field(), self.header.last_mod_date, self.header.last_mod_time)
+    }
+
+    /// Unix permission and file type bits, only available for entries created on Unix.
+    pub fn unix_mode(&self) -> Option<u32> {
+        if self.header.version_producer >> 8 == 3 {
+            Some(self.header.external_file_attributes >> 16)
+        } else {
+            None
+        }
+    }
+
+    pub fn comment(&self) -> String {
+        String::from_utf8_lossy(self.header.file_comment()).to_string()
+    }
+
+    pub fn extra_fields(&self) -> ExtraFields<'_> {
+        ExtraFields::new(self.header.extra_field())
+    }
+
+    /// Offset of the entry's data, right after its local file header.
+    pub fn data_offset(&self) -> io::Result<u64> {
         let mut r = self.reader.clone();
-        r.seek(SeekFrom::Start(self.header.relative_offset_of_local_header as u64))?;
+        let offset = self.header.relative_offset_of_local_header;
+        r.seek(SeekFrom::Start(offset))?;
         let mut header_buf = vec![0; 30];
         r.read_exact(&mut header_buf)?;
-        if let IResult::Done(_, (file_name_len, extra_field_len)) = parser::parse_local_file_header(&header_buf) {
-            r.seek(SeekFrom::Current(file_name_len + extra_field_len))?;
+        if let IResult::Done(_, local) = parser::parse_local_file_header(&header_buf) {
+            return Ok(offset + 30 + u64::from(local.file_name_length) + u64::from(local.extra_field_length));
         }
-        if self.header.compression_method == 8 {
-            return Ok(Box::new(DeflateDecoder::new(r.take(self.header.compressed_size as u64))));
+        Err(ZipError::InvalidLocalFileHeader { file_name: self.file_name(), offset }.into())
+    }
+
+    /// The uncompressed data of the entry. Reading fails if the CRC-32 or size do not match the
+    /// central directory or the data exceeds the limits of the archive.
+    pub fn content(&self) -> io::Result<Box<dyn Read + Send>> {
+        if self.limits.limits.max_entry_size.is_some_and(|max| self.header.uncompressed_size > max) {
+            return Err(ZipError::LimitExceeded { limit: Limit::EntrySize, file_name: Some(self.file_name()) }.into());
+        }
+        let raw = self.raw_content()?;
+        let decoder: Box<dyn Read + Send> = match self.compression() {
+            Compression::Store => Box::new(raw),
+            Compression::Deflate => Box::new(DeflateDecoder::new(raw)),
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => Box::new(bzip2::read::BzDecoder::new(raw)),
+            #[cfg(feature = "lzma")]
+            Compression::LZMA => Box::new(LzmaReader::new(raw, self.header.uncompressed_size)?),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(raw)?),
+            _ => return Err(ZipError::UnsupportedCompression(self.header.compression_method).into()),
+        };
+        let limited = LimitedReader::new(
+            decoder,
+            self.limits.clone(),
+            self.file_name(),
+            self.header.compressed_size,
+            self.header.uncompressed_size,
+        );
+        Ok(Box::new(Crc32Reader::new(limited, self.header.crc32(), self.file_name())))
+    }
+
+    /// The data of the entry as stored in the archive, without decompressing it. Fails if the
+    /// data extends beyond the end of the archive.
+    pub fn raw_content(&self) -> io::Result<impl Read + Send> {
+        let mut r = self.reader.clone();
+        let data_offset = self.data_offset()?;
+        let archive_len = r.seek(SeekFrom::End(0))?;
+        if data_offset.checked_add(self.header.compressed_size).is_none_or(|end| end > archive_len) {
+            return Err(ZipError::EntryOutOfBounds { file_name: self.file_name() }.into());
+        }
+        r.seek(SeekFrom::Start(data_offset))?;
+        Ok(r.take(self.header.compressed_size))
+    }
+
+    /// Borrows the data of a stored entry directly from an archive held in memory or memory
+    /// mapped. Returns `None` for compressed entries and other archive sources. The CRC-32 is
+    /// checked the first time an entry of the archive is borrowed.
+    pub fn stored_data(&self) -> io::Result<Option<&[u8]>> {
+        if self.header.compression_method != 0 {
+            return Ok(None);
+        }
+        let archive = match self.reader.as_slice() {
+            Some(archive) => archive,
+            None => return Ok(None),
+        };
+        let start = self.data_offset()?;
+        let end = start.checked_add(self.header.compressed_size);
+        let data = match end.and_then(|end| archive.get(start as usize..end as usize)) {
+            Some(data) => data,
+            None => return Err(ZipError::EntryOutOfBounds { file_name: self.file_name() }.into()),
+        };
+        let checked = &self.crc_checked[self.index];
+        if !checked.load(Ordering::Relaxed) {
+            let actual = crc32fast::hash(data);
+            if actual != self.header.crc32() {
+                return Err(ZipError::CrcMismatch { file_name: self.file_name(), expected: self.header.crc32(), actual }.into());
+            }
+            checked.store(true, Ordering::Relaxed);
         }
-        Ok(Box::new(r.take(self.header.compressed_size as u64)))
+        Ok(Some(data))
+    }
+
+    /// The uncompressed data of the entry, borrowed if possible, see `stored_data`.
+    pub fn bytes(&self) -> io::Result<Cow<'_, [u8]>> {
+        if let Some(data) = self.stored_data()? {
+            return Ok(Cow::Borrowed(data));
+        }
+        let mut buf = Vec::with_capacity(self.len().min(MAX_PREALLOCATION));
+        self.content()?.read_to_end(&mut buf)?;
+        Ok(Cow::Owned(buf))
+    }
+
+    /// Extracts the entry without keeping the data, the CRC-32 check fails for corrupted entries.
+    pub fn verify(&self) -> io::Result<()> {
+        io::copy(&mut self.content()?, &mut io::sink())?;
+        Ok(())
     }
 }
 
 impl ZipArchive {
     pub fn open(path: &str) -> io::Result<ZipArchive> {
-        let mut reader = ReaderWrapper::FileReader(FileReader::open(path)?);
-        let (offset, size) = get_range_of_central_directory(&mut reader)?;
+        OpenOptions::new().open(path)
+    }
+
+    /// Memory maps the archive, stored entries can then be borrowed without copying.
+    pub fn open_mmap(path: &str) -> io::Result<ZipArchive> {
+        OpenOptions::new().open_mmap(path)
+    }
+
+    pub fn from_bytes<D: Into<Arc<[u8]>>>(data: D) -> io::Result<ZipArchive> {
+        OpenOptions::new().from_bytes(data)
+    }
 
-        reader.seek(SeekFrom::Start(offset as u64))?;
+    pub fn from_reader<R: Read + Seek + Send + 'static>(reader: R) -> io::Result<ZipArchive> {
+        OpenOptions::new().from_reader(reader)
+    }
+
+    fn from_reader_wrapper(mut reader: ReaderWrapper, limits: Limits) -> io::Result<ZipArchive> {
+        let (eocd, eocd_offset) = get_range_of_central_directory(&mut reader)?;
+        let offset = eocd.central_directory_offset;
+        let size = eocd.central_directory_size;
+        if limits.max_central_directory_size.is_some_and(|max| size > max) {
+            return Err(ZipError::LimitExceeded { limit: Limit::CentralDirectorySize, file_name: None }.into());
+        }
 
-        let mut buf = vec![0; size];
+        reader.seek(SeekFrom::Start(offset))?;
+
+        let mut buf = vec![0; size as usize];
         reader.read_exact(&mut buf)?;
-        let entries: Vec<CentralDirectoryFileHeader>;
-        if let IResult::Done(foo, res) = parser::parse_central_directory(&buf) {
-            entries = res;
-        } else {
-            return Err(io::Error::new(io::ErrorKind::Other, "error parsing central directory"));
+        // a record that can not be parsed ends the entries early, leaving the rest behind
+        let entries = match parser::parse_central_directory(&buf) {
+            IResult::Done([], entries) => entries,
+            _ => return Err(ZipError::InvalidCentralDirectory.into()),
+        };
+        if limits.max_entry_count.is_some_and(|max| entries.len() > max) {
+            return Err(ZipError::LimitExceeded { limit: Limit::EntryCount, file_name: None }.into());
         }
 
+        let mut names = HashMap::with_capacity(entries.len());
+        let mut duplicates = HashSet::new();
+        let mut duplicate_names = Vec::new();
+        for (index, entry) in entries.iter().enumerate() {
+            match names.entry(entry.file_name_bytes().to_vec()) {
+                Entry::Occupied(name) => {
+                    if duplicates.insert(name.key().clone()) {
+                        duplicate_names.push(entry.file_name());
+                    }
+                }
+                Entry::Vacant(name) => {
+                    name.insert(index);
+                }
+            }
+        }
+
+        let crc_checked = entries.iter().map(|_| AtomicBool::new(false)).collect();
         Ok(ZipArchive {
             reader,
-            entries: Rc::new(entries),
+            crc_checked,
+            entries: Arc::new(entries),
+            central_directory_offset: offset,
+            central_directory_size: size,
+            end_of_central_directory_offset: eocd_offset,
+            comment: eocd.comment,
+            limits: Arc::new(LimitState::new(limits)),
+            names,
+            duplicate_names,
         })
     }
 
-    pub fn by_name(&self, name: &str) -> io::Result<Option<ZipEntry>> {
-        for entry in self.entries.iter() {
-            if entry.file_name() == name {
-                return Ok(Some(ZipEntry {
-                    reader: self.reader.clone(),
-                    header: entry.clone(),
-                }));
+    /// Reports every stored entry whose data is not aligned the way `zipalign -p` aligns it for
+    /// the given page size (4096, or 16384 for devices with 16 KiB pages).
+    pub fn check_alignment(&self, page_size: u64) -> io::Result<Vec<MisalignedEntry>> {
+        align::check_page_size(page_size)?;
+        let mut misaligned = Vec::new();
+        for entry in self.files() {
+            if entry.header.compression_method != 0 {
+                continue;
+            }
+            let file_name = entry.file_name();
+            let alignment = align::required_alignment(&file_name, page_size);
+            let data_offset = entry.data_offset()?;
+            if data_offset % alignment != 0 {
+                misaligned.push(MisalignedEntry { file_name, data_offset, alignment });
             }
         }
-        Ok(None)
+        Ok(misaligned)
+    }
+
+    /// Number of bytes extracted from all entries so far, counted against
+    /// `OpenOptions::max_total_uncompressed`.
+    pub fn total_uncompressed(&self) -> u64 {
+        self.limits.total_uncompressed()
+    }
+
+    /// Starts the `max_total_uncompressed` budget over, for example before each request a
+    /// server handles with the same archive. Applies to all clones of the archive.
+    pub fn reset_total_uncompressed(&self) {
+        self.limits.reset_total_uncompressed();
+    }
+
+    pub fn comment(&self) -> String {
+        String::from_utf8_lossy(&self.comment).to_string()
+    }
+
+    /// Offset of the central directory. Data between the last entry and the central directory,
+    /// like the APK signing block, does not belong to any entry.
+    pub fn central_directory_offset(&self) -> u64 {
+        self.central_directory_offset
+    }
+
+    pub fn central_directory_size(&self) -> u64 {
+        self.central_directory_size
+    }
+
+    /// Offset of the classic end of central directory record, it extends to the end of the
+    /// archive.
+    pub fn end_of_central_directory_offset(&self) -> u64 {
+        self.end_of_central_directory_offset
+    }
+
+    /// Reads `len` bytes of the archive starting at `offset`, regardless of entries.
+    pub fn raw_range(&self, offset: u64, len: u64) -> io::Result<impl Read + Send> {
+        let mut r = self.reader.clone();
+        r.seek(SeekFrom::Start(offset))?;
+        Ok(r.take(len))
+    }
+
+    /// Checks the local headers against the central directory and looks for overlapping
+    /// entries, duplicate names and data outside of any entry.
+    pub fn audit(&self) -> io::Result<Vec<AuditFinding>> {
+        // the APK Signing Block of v2 and later signatures sits right before the central
+        // directory, a block that can not be parsed counts as data outside of any entry
+        let data_end = match SigningBlock::read(self) {
+            Ok(Some(block)) => block.offset,
+            Ok(None) => self.central_directory_offset,
+            Err(e) if matches!(e.get_ref().and_then(|e| e.downcast_ref()), Some(SignatureError::InvalidSigningBlock)) => self.central_directory_offset,
+            Err(e) => return Err(e),
+        };
+        audit::audit(&self.reader, &self.entries, self.central_directory_offset, data_end)
+    }
+
+    /// Looks up an entry by name. If several entries share the name the first one in the
+    /// central directory is returned, see `duplicate_names`.
+    pub fn by_name(&self, name: &str) -> io::Result<Option<ZipEntry>> {
+        self.by_name_bytes(name.as_bytes())
+    }
+
+    /// Looks up an entry by the raw bytes of its name, for names that are not valid UTF-8.
+    pub fn by_name_bytes(&self, name: &[u8]) -> io::Result<Option<ZipEntry>> {
+        Ok(self.names.get(name).map(|&index| self.entry(index)))
+    }
+
+    /// Names that occur more than once in the central directory. Android refuses to install
+    /// such APKs, while most zip tools silently use either the first or the last entry, so the
+    /// content seen by a tool may differ from what was signed.
+    pub fn duplicate_names(&self) -> &[String] {
+        &self.duplicate_names
+    }
+
+    /// Entries whose name starts with `prefix`, e.g. `files_under("lib/arm64-v8a/")`, in
+    /// central directory order.
+    pub fn files_under<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = ZipEntry> + 'a {
+        self.entries.iter()
+            .enumerate()
+            .filter(move |(_, entry)| entry.file_name_bytes().starts_with(prefix.as_bytes()))
+            .map(move |(index, _)| self.entry(index))
+    }
+
+    /// Entries whose name matches a glob pattern like `lib/*/libfoo.so` or `res/**/*.xml`, in
+    /// central directory order. `*` and `?` do not match `/`, `**` does.
+    pub fn files_matching<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = ZipEntry> + 'a {
+        self.entries.iter()
+            .enumerate()
+            .filter(move |(_, entry)| glob_match(pattern, &entry.file_name()))
+            .map(move |(index, _)| self.entry(index))
+    }
+
+    fn entry(&self, index: usize) -> ZipEntry {
+        ZipEntry {
+            reader: self.reader.clone(),
+            header: self.entries[index].clone(),
+            limits: self.limits.clone(),
+            index,
+            crc_checked: self.crc_checked.clone(),
+        }
     }
 
     pub fn files(&self) -> ZipIter {
         ZipIter {
             reader: self.reader.clone(),
             entries: self.entries.clone(),
+            limits: self.limits.clone(),
+            crc_checked: self.crc_checked.clone(),
             index: 0,
         }
     }
@@ -145,7 +644,9 @@ impl ZipArchive {
 
 pub struct ZipIter {
     reader: ReaderWrapper,
-    entries: Rc<Vec<CentralDirectoryFileHeader>>,
+    entries: Arc<Vec<CentralDirectoryFileHeader>>,
+    limits: Arc<LimitState>,
+    crc_checked: Arc<[AtomicBool]>,
     index: usize,
 }
 
@@ -157,10 +658,279 @@ impl Iterator for ZipIter {
             let res = Some(ZipEntry {
                 reader: self.reader.clone(),
                 header: self.entries[self.index].clone(),
+                limits: self.limits.clone(),
+                index: self.index,
+                crc_checked: self.crc_checked.clone(),
             });
             self.index += 1;
             return res;
         }
         None
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+fn build_test_archive(comment: &[u8]) -> Vec<u8> {
+    let mut d = vec![0x50, 0x4b, 0x03, 0x04, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+    d.extend_from_slice(&[0xac, 0x2a, 0x93, 0xd8, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    d.extend_from_slice(b"ahi");
+    let cd_offset = d.len() as u8;
+    d.extend_from_slice(&[0x50, 0x4b, 0x01, 0x02, 0x0a, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    d.extend_from_slice(&[0xac, 0x2a, 0x93, 0xd8, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    d.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, b'a']);
+    let cd_size = d.len() as u8 - cd_offset;
+    d.extend_from_slice(&[0x50, 0x4b, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00]);
+    d.extend_from_slice(&[cd_size, 0x00, 0x00, 0x00, cd_offset, 0x00, 0x00, 0x00]);
+    d.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+    d.extend_from_slice(comment);
+    d
+}
+
+#[test]
+fn test_get_range_of_central_directory() {
+    let d = build_test_archive(b"");
+    let (r, eocd_offset) = get_range_of_central_directory(&mut io::Cursor::new(&d)).unwrap();
+    assert_eq!((r.central_directory_offset, r.central_directory_size), (33, 47));
+    assert_eq!(eocd_offset, 80);
+
+    // a comment longer than 1 KiB that contains something looking like an end of central directory
+    let mut comment = vec![0x20; 5000];
+    comment[100..104].copy_from_slice(&[0x50, 0x4b, 0x05, 0x06]);
+    let d = build_test_archive(&comment);
+    let (r, _) = get_range_of_central_directory(&mut io::Cursor::new(&d)).unwrap();
+    assert_eq!((r.central_directory_offset, r.central_directory_size), (33, 47));
+    assert_eq!(r.comment, comment);
+
+    let r = get_range_of_central_directory(&mut io::Cursor::new(&d[..d.len() - 1]));
+    let e = r.unwrap_err();
+    assert_eq!(e.get_ref().unwrap().downcast_ref::<ZipError>(), Some(&ZipError::EndOfCentralDirectoryNotFound));
+}
+
+#[test]
+fn test_from_bytes() {
+    let archive = ZipArchive::from_bytes(build_test_archive(b"")).unwrap();
+    let entry = archive.by_name("a").unwrap().unwrap();
+    let mut content = String::new();
+    entry.content().unwrap().read_to_string(&mut content).unwrap();
+    assert_eq!(content, "hi");
+    assert_eq!(entry.stored_data().unwrap(), Some(&b"hi"[..]));
+    assert_eq!(archive.audit().unwrap(), vec![]);
+
+    let archive = ZipArchive::from_reader(io::Cursor::new(build_test_archive(b""))).unwrap();
+    assert_eq!(archive.files().count(), 1);
+
+    // bytes after the last central directory record
+    let mut d = build_test_archive(b"");
+    d.splice(80..80, [0x50, 0x4b, 0x01, 0x02]);
+    d[84 + 12] += 4;
+    let e = ZipArchive::from_bytes(d).err().unwrap();
+    assert_eq!(e.get_ref().unwrap().downcast_ref::<ZipError>(), Some(&ZipError::InvalidCentralDirectory));
+}
+
+#[test]
+fn test_stored_data_crc() {
+    let archive = ZipArchive::from_bytes(build_test_archive(b"")).unwrap();
+    let entry = archive.by_name("a").unwrap().unwrap();
+    assert_eq!(entry.stored_data().unwrap(), Some(&b"hi"[..]));
+    assert_eq!(archive.by_name("a").unwrap().unwrap().bytes().unwrap(), &b"hi"[..]);
+
+    let mut d = build_test_archive(b"");
+    d[31] = b'o';
+    let archive = ZipArchive::from_bytes(d).unwrap();
+    let e = archive.by_name("a").unwrap().unwrap().bytes().unwrap_err();
+    assert_eq!(e.get_ref().unwrap().downcast_ref::<ZipError>(), Some(&ZipError::CrcMismatch { file_name: "a".into(), expected: 0xd893_2aac, actual: crc32fast::hash(b"oi") }));
+}
+
+#[test]
+fn test_audit() {
+    let mut d = build_test_archive(b"");
+    // local file name differs from the central directory, compression method too
+    d[30] = b'b';
+    d[8] = 0x08;
+    let archive = ZipArchive::from_bytes(d).unwrap();
+    assert_eq!(archive.audit().unwrap(), vec![
+        AuditFinding::NameMismatch { central: "a".into(), local: "b".into() },
+        AuditFinding::CompressionMethodMismatch { file_name: "a".into(), central: 0, local: 8 },
+    ]);
+
+    // an APK Signing Block between the entries and the central directory is expected, other
+    // bytes there are not
+    let insert_before_central_directory = |gap: &[u8]| {
+        let mut d = build_test_archive(b"");
+        d.splice(33..33, gap.iter().cloned());
+        let eocd = d.len() - 22;
+        d[eocd + 16] += gap.len() as u8;
+        ZipArchive::from_bytes(d).unwrap()
+    };
+    let mut block = 40u64.to_le_bytes().to_vec();
+    block.extend_from_slice(&[0; 16]);
+    block.extend_from_slice(&40u64.to_le_bytes());
+    block.extend_from_slice(b"APK Sig Block 42");
+    assert_eq!(insert_before_central_directory(&block).audit().unwrap(), vec![]);
+    block[0] = 41;
+    assert_eq!(insert_before_central_directory(&block).audit().unwrap(), vec![
+        AuditFinding::UnaccountedData { offset: 33, len: 48 },
+    ]);
+    let signed = ZipArchive::from_bytes(&include_bytes!("../../testdata/v2v3-rsa.apk")[..]).unwrap();
+    assert_eq!(signed.audit().unwrap(), vec![]);
+
+    // a ZIP64 compressed size that overflows the end offset of the entry
+    let mut d = build_test_archive(b"");
+    d[33 + 20..33 + 24].copy_from_slice(&[0xff; 4]);
+    d[33 + 30] = 12;
+    let mut zip64_extra = vec![0x01, 0x00, 0x08, 0x00];
+    zip64_extra.extend_from_slice(&0xFFFF_FFFF_FFFF_FFF0u64.to_le_bytes());
+    d.splice(80..80, zip64_extra);
+    d[92 + 12] += 12;
+    let archive = ZipArchive::from_bytes(d).unwrap();
+    assert_eq!(archive.audit().unwrap(), vec![
+        AuditFinding::EntryOutOfBounds { file_name: "a".into() },
+        AuditFinding::UnaccountedData { offset: 0, len: 33 },
+    ]);
+}
+
+#[test]
+fn test_unsupported_compression() {
+    let mut d = build_test_archive(b"");
+    d[8] = 99;
+    d[33 + 10] = 99;
+    let archive = ZipArchive::from_bytes(d).unwrap();
+    let e = archive.by_name("a").unwrap().unwrap().content().err().unwrap();
+    assert_eq!(e.get_ref().unwrap().downcast_ref::<ZipError>(), Some(&ZipError::UnsupportedCompression(99)));
+}
+
+/// An archive with the single entry `a`, compressed with `method`.
+#[cfg(all(test, any(feature = "bzip2", feature = "lzma", feature = "zstd")))]
+fn build_compressed_archive(method: u16, compressed: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut sizes = crc32fast::hash(data).to_le_bytes().to_vec();
+    sizes.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+    sizes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    sizes.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+
+    let mut d = vec![0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00];
+    d.extend_from_slice(&method.to_le_bytes());
+    d.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    d.extend_from_slice(&sizes);
+    d.push(b'a');
+    d.extend_from_slice(compressed);
+    let cd_offset = d.len() as u32;
+    d.extend_from_slice(&[0x50, 0x4b, 0x01, 0x02, 0x14, 0x00, 0x14, 0x00, 0x00, 0x00]);
+    d.extend_from_slice(&method.to_le_bytes());
+    d.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    d.extend_from_slice(&sizes);
+    d.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, b'a']);
+    let cd_size = d.len() as u32 - cd_offset;
+    d.extend_from_slice(&[0x50, 0x4b, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00]);
+    d.extend_from_slice(&cd_size.to_le_bytes());
+    d.extend_from_slice(&cd_offset.to_le_bytes());
+    d.extend_from_slice(&[0x00, 0x00]);
+    d
+}
+
+#[cfg(all(test, any(feature = "bzip2", feature = "lzma", feature = "zstd")))]
+fn read_entry(archive: Vec<u8>, options: &OpenOptions) -> io::Result<Vec<u8>> {
+    let archive = options.from_bytes(archive)?;
+    let mut content = Vec::new();
+    archive.by_name("a")?.unwrap().content()?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+#[cfg(feature = "bzip2")]
+#[test]
+fn test_bzip2() {
+    use std::io::Write;
+
+    let data = b"bzip2 compressed entry ".repeat(100);
+    let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    encoder.write_all(&data).unwrap();
+    let archive = build_compressed_archive(12, &encoder.finish().unwrap(), &data);
+    assert_eq!(read_entry(archive, &OpenOptions::new()).unwrap(), data);
+}
+
+#[cfg(feature = "lzma")]
+#[test]
+fn test_lzma() {
+    let data = b"lzma compressed entry ".repeat(100);
+    let mut lzma = Vec::new();
+    lzma_rs::lzma_compress(&mut &data[..], &mut lzma).unwrap();
+    // the .lzma header has the properties followed by the uncompressed size, zip entries have
+    // a version and the length of the properties instead
+    let mut compressed = vec![0x10, 0x02, 0x05, 0x00];
+    compressed.extend_from_slice(&lzma[..5]);
+    compressed.extend_from_slice(&lzma[13..]);
+    let archive = build_compressed_archive(14, &compressed, &data);
+    assert_eq!(read_entry(archive, &OpenOptions::new()).unwrap(), data);
+
+    // the limits apply while the entry is decoded
+    let data = vec![0; 1 << 20];
+    let mut lzma = Vec::new();
+    lzma_rs::lzma_compress(&mut &data[..], &mut lzma).unwrap();
+    let mut compressed = vec![0x10, 0x02, 0x05, 0x00];
+    compressed.extend_from_slice(&lzma[..5]);
+    compressed.extend_from_slice(&lzma[13..]);
+    let archive = build_compressed_archive(14, &compressed, &data);
+    let e = read_entry(archive, OpenOptions::new().max_total_uncompressed(4096)).unwrap_err();
+    assert_eq!(
+        e.get_ref().unwrap().downcast_ref::<ZipError>(),
+        Some(&ZipError::LimitExceeded { limit: Limit::TotalUncompressed, file_name: Some("a".into()) })
+    );
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd() {
+    let data = b"zstd compressed entry ".repeat(100);
+    let archive = build_compressed_archive(93, &zstd::encode_all(&data[..], 3).unwrap(), &data);
+    assert_eq!(read_entry(archive, &OpenOptions::new()).unwrap(), data);
+
+    let mut archive = build_compressed_archive(93, &zstd::encode_all(&data[..], 3).unwrap(), &data);
+    archive[40] ^= 0xff;
+    assert!(read_entry(archive, &OpenOptions::new()).is_err());
+}
+
+#[test]
+fn test_open_options() {
+    let e = OpenOptions::new().max_entry_count(0).from_bytes(build_test_archive(b"")).err().unwrap();
+    assert_eq!(
+        e.get_ref().unwrap().downcast_ref::<ZipError>(),
+        Some(&ZipError::LimitExceeded { limit: Limit::EntryCount, file_name: None })
+    );
+
+    let archive = OpenOptions::new().max_total_uncompressed(3).from_bytes(build_test_archive(b"")).unwrap();
+    let entry = archive.by_name("a").unwrap().unwrap();
+    assert!(entry.verify().is_ok());
+    assert!(entry.verify().is_err());
+    assert_eq!(archive.total_uncompressed(), 4);
+    archive.reset_total_uncompressed();
+    assert!(entry.verify().is_ok());
+    assert_eq!(archive.total_uncompressed(), 2);
+}
+
+#[test]
+fn test_names() {
+    let mut w = crate::zip::writer::ZipWriter::new(io::Cursor::new(Vec::new()));
+    for name in ["lib/arm64-v8a/liba.so", "lib/x86/liba.so", "classes.dex", "lib/arm64-v8a/libb.so", "classes.dex"] {
+        w.add_stored(name, name.as_bytes()).unwrap();
+    }
+    let archive = ZipArchive::from_bytes(w.finish().unwrap().into_inner()).unwrap();
+
+    let names = |entries: Vec<ZipEntry>| entries.iter().map(ZipEntry::file_name).collect::<Vec<_>>();
+    assert_eq!(names(archive.files_under("lib/arm64-v8a/").collect()), ["lib/arm64-v8a/liba.so", "lib/arm64-v8a/libb.so"]);
+    assert_eq!(names(archive.files_matching("lib/*/liba.so").collect()), ["lib/arm64-v8a/liba.so", "lib/x86/liba.so"]);
+    assert_eq!(archive.duplicate_names(), ["classes.dex"]);
+    assert_eq!(archive.by_name("classes.dex").unwrap().unwrap().header.relative_offset_of_local_header, 132);
+    assert!(archive.by_name("lib").unwrap().is_none());
+
+    // names that are not UTF-8 are told apart by their bytes, not their lossy decoding
+    let mut w = crate::zip::writer::ZipWriter::new(Vec::new());
+    w.add_stored("xa", b"").unwrap();
+    w.add_stored("xb", b"").unwrap();
+    let mut data = w.finish().unwrap();
+    for i in 0..data.len() - 1 {
+        if data[i] == b'x' && (data[i + 1] == b'a' || data[i + 1] == b'b') {
+            data[i + 1] |= 0x80;
+        }
+    }
+    let archive = ZipArchive::from_bytes(data).unwrap();
+    assert!(archive.duplicate_names().is_empty());
+    assert_eq!(archive.by_name_bytes(b"x\xe2").unwrap().unwrap().header.relative_offset_of_local_header, 32);
+}
@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Seek, SeekFrom};
+use nom::IResult;
+use crate::zip::io::ReaderWrapper;
+use crate::zip::parser::{self, CentralDirectoryFileHeader};
+use crate::zip::extra::find_extra_field;
+
+/// A discrepancy found by `ZipArchive::audit`. Archives containing any of these can show
+/// different content to parsers that trust the local headers and parsers that trust the central
+/// directory.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditFinding {
+    /// The local header referenced by the central directory is missing or can not be parsed.
+    InvalidLocalHeader { file_name: String, offset: u64 },
+    /// The data descriptor following the entry can not be parsed.
+    InvalidDataDescriptor { file_name: String },
+    NameMismatch { central: String, local: String },
+    CompressionMethodMismatch { file_name: String, central: u16, local: u16 },
+    DataDescriptorFlagMismatch { file_name: String, central: bool, local: bool },
+    CrcMismatch { file_name: String, central: u32, local: u32 },
+    SizeMismatch {
+        file_name: String,
+        central_compressed: u64,
+        local_compressed: u64,
+        central_uncompressed: u64,
+        local_uncompressed: u64,
+    },
+    /// More than one entry in the central directory has this name.
+    DuplicateName { file_name: String, count: usize },
+    /// The second entry starts before the first one ends.
+    OverlappingEntries { first: String, second: String },
+    EntryOverlapsCentralDirectory { file_name: String },
+    /// The data of the entry would end beyond the largest possible offset.
+    EntryOutOfBounds { file_name: String },
+    /// Bytes before the central directory which do not belong to any entry.
+    UnaccountedData { offset: u64, len: u64 },
+}
+
+/// Checks the entries of an archive. The bytes from `data_end` up to the central directory are
+/// expected to hold the APK Signing Block, those before have to belong to entries.
+pub fn audit(reader: &ReaderWrapper, entries: &[CentralDirectoryFileHeader], central_directory_offset: u64, data_end: u64) -> io::Result<Vec<AuditFinding>> {
+    let mut findings = Vec::new();
+
+    let mut name_counts: HashMap<&[u8], usize> = HashMap::new();
+    for entry in entries {
+        *name_counts.entry(entry.file_name_bytes()).or_insert(0) += 1;
+    }
+    let mut reported = HashSet::new();
+    for entry in entries {
+        let count = name_counts[entry.file_name_bytes()];
+        if count > 1 && reported.insert(entry.file_name_bytes()) {
+            findings.push(AuditFinding::DuplicateName { file_name: entry.file_name(), count });
+        }
+    }
+
+    let mut spans = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if let Some(end) = audit_entry(reader.clone(), entry, &mut findings)? {
+            spans.push((entry.relative_offset_of_local_header, end, entry));
+        }
+    }
+
+    spans.sort_by_key(|(start, _, _)| *start);
+    let mut covered = 0;
+    let mut previous: Option<&CentralDirectoryFileHeader> = None;
+    for (start, end, entry) in spans {
+        if start < covered {
+            if let Some(previous) = previous {
+                findings.push(AuditFinding::OverlappingEntries {
+                    first: previous.file_name(),
+                    second: entry.file_name(),
+                });
+            }
+        } else if start > covered {
+            findings.push(AuditFinding::UnaccountedData { offset: covered, len: start - covered });
+        }
+        if end > central_directory_offset {
+            findings.push(AuditFinding::EntryOverlapsCentralDirectory { file_name: entry.file_name() });
+        }
+        if end > covered {
+            covered = end;
+            previous = Some(entry);
+        }
+    }
+    if covered < data_end {
+        findings.push(AuditFinding::UnaccountedData { offset: covered, len: data_end - covered });
+    }
+
+    Ok(findings)
+}
+
+/// Compares the local header of an entry with its central directory header and returns the
+/// offset right after the entry's data and data descriptor.
+fn audit_entry(mut r: ReaderWrapper, entry: &CentralDirectoryFileHeader, findings: &mut Vec<AuditFinding>) -> io::Result<Option<u64>> {
+    let file_name = entry.file_name();
+    let offset = entry.relative_offset_of_local_header;
+    let mut header_buf = [0; 30];
+    if r.seek(SeekFrom::Start(offset)).and_then(|_| r.read_exact(&mut header_buf)).is_err() {
+        findings.push(AuditFinding::InvalidLocalHeader { file_name, offset });
+        return Ok(None);
+    }
+    let local = match parser::parse_local_file_header(&header_buf) {
+        IResult::Done(_, local) => local,
+        _ => {
+            findings.push(AuditFinding::InvalidLocalHeader { file_name, offset });
+            return Ok(None);
+        }
+    };
+    let name_len = usize::from(local.file_name_length);
+    let mut variable = vec![0; name_len + usize::from(local.extra_field_length)];
+    if r.read_exact(&mut variable).is_err() {
+        findings.push(AuditFinding::InvalidLocalHeader { file_name, offset });
+        return Ok(None);
+    }
+    let (local_name, local_extra) = variable.split_at(name_len);
+
+    if local_name != entry.file_name_bytes() {
+        findings.push(AuditFinding::NameMismatch {
+            central: file_name.clone(),
+            local: String::from_utf8_lossy(local_name).to_string(),
+        });
+    }
+    if local.compression_method != entry.compression_method {
+        findings.push(AuditFinding::CompressionMethodMismatch {
+            file_name: file_name.clone(),
+            central: entry.compression_method,
+            local: local.compression_method,
+        });
+    }
+    let central_data_descriptor = entry.general_purpose_flags & 0x08 == 0x08;
+    if central_data_descriptor != local.has_data_descriptor() {
+        findings.push(AuditFinding::DataDescriptorFlagMismatch {
+            file_name: file_name.clone(),
+            central: central_data_descriptor,
+            local: local.has_data_descriptor(),
+        });
+    }
+
+    let zip64_extra = find_extra_field(local_extra, 0x0001);
+    let data_end = match (offset + 30 + variable.len() as u64).checked_add(entry.compressed_size) {
+        Some(data_end) => data_end,
+        None => {
+            findings.push(AuditFinding::EntryOutOfBounds { file_name });
+            return Ok(None);
+        }
+    };
+    let mut end = data_end;
+    let (crc32, compressed_size, uncompressed_size) = if local.has_data_descriptor() {
+        let mut buf = Vec::with_capacity(24);
+        r.seek(SeekFrom::Start(data_end))?;
+        (&mut r).take(24).read_to_end(&mut buf)?;
+        match parser::parse_data_descriptor(&buf, zip64_extra.is_some()) {
+            IResult::Done(rest, descriptor) => {
+                end += (buf.len() - rest.len()) as u64;
+                (descriptor.crc32, descriptor.compressed_size, descriptor.uncompressed_size)
+            }
+            _ => {
+                findings.push(AuditFinding::InvalidDataDescriptor { file_name });
+                return Ok(Some(end));
+            }
+        }
+    } else {
+        let mut uncompressed_size = u64::from(local.uncompressed_size);
+        let mut compressed_size = u64::from(local.compressed_size);
+        if let Some(IResult::Done(_, (uncompressed, compressed))) = zip64_extra.map(parser::parse_local_zip64_sizes) {
+            if uncompressed_size == 0xFFFF_FFFF {
+                uncompressed_size = uncompressed;
+            }
+            if compressed_size == 0xFFFF_FFFF {
+                compressed_size = compressed;
+            }
+        }
+        (local.crc32, compressed_size, uncompressed_size)
+    };
+
+    if crc32 != entry.crc32() {
+        findings.push(AuditFinding::CrcMismatch { file_name: file_name.clone(), central: entry.crc32(), local: crc32 });
+    }
+    if compressed_size != entry.compressed_size || uncompressed_size != entry.uncompressed_size {
+        findings.push(AuditFinding::SizeMismatch {
+            file_name,
+            central_compressed: entry.compressed_size,
+            local_compressed: compressed_size,
+            central_uncompressed: entry.uncompressed_size,
+            local_uncompressed: uncompressed_size,
+        });
+    }
+
+    Ok(Some(end))
+}
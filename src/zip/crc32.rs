@@ -0,0 +1,55 @@
+use std::io::{self, Read};
+use crc32fast::Hasher;
+use crate::zip::error::ZipError;
+
+/// Computes the CRC-32 of everything read through it and fails with `InvalidData` at the end of
+/// the stream if it does not match the checksum from the central directory.
+pub struct Crc32Reader<R> {
+    inner: R,
+    hasher: Hasher,
+    expected: u32,
+    file_name: String,
+    checked: bool,
+}
+
+impl<R: Read> Crc32Reader<R> {
+    pub fn new(inner: R, expected: u32, file_name: String) -> Crc32Reader<R> {
+        Crc32Reader {
+            inner,
+            hasher: Hasher::new(),
+            expected,
+            file_name,
+            checked: false,
+        }
+    }
+}
+
+impl<R: Read> Read for Crc32Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.hasher.update(&buf[..n]);
+        } else if !buf.is_empty() && !self.checked {
+            self.checked = true;
+            let actual = self.hasher.clone().finalize();
+            if actual != self.expected {
+                return Err(ZipError::CrcMismatch {
+                    file_name: self.file_name.clone(),
+                    expected: self.expected,
+                    actual,
+                }.into());
+            }
+        }
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_crc32_reader() {
+    let mut r = Crc32Reader::new(&b"123456789"[..], 0xcbf4_3926, "a".into());
+    assert_eq!(io::copy(&mut r, &mut io::sink()).unwrap(), 9);
+
+    let mut r = Crc32Reader::new(&b"123456780"[..], 0xcbf4_3926, "a".into());
+    let e = io::copy(&mut r, &mut io::sink()).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,87 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use crate::zip::limits::Limit;
+
+/// Reasons for rejecting an archive. Functions of the zip module return `io::Result`, a
+/// `ZipError` can be recovered from the returned error with `io::Error::get_ref` and
+/// `downcast_ref`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZipError {
+    /// No end of central directory record was found in the last 65557 bytes of the archive.
+    EndOfCentralDirectoryNotFound,
+    /// A ZIP64 end of central directory locator points to an invalid record.
+    InvalidZip64EndOfCentralDirectory,
+    /// The central directory does not lie between the start of the archive and the end of
+    /// central directory record.
+    CentralDirectoryOutOfBounds { offset: u64, size: u64 },
+    /// The central directory does not start with a central directory file header signature.
+    CentralDirectorySignatureMismatch { offset: u64 },
+    /// The central directory could not be parsed.
+    InvalidCentralDirectory,
+    /// The local file header referenced by the central directory can not be parsed.
+    InvalidLocalFileHeader { file_name: String, offset: u64 },
+    /// The data of the entry extends beyond the end of the archive.
+    EntryOutOfBounds { file_name: String },
+    /// The entry uses a compression method that is unknown or whose decoder is not enabled.
+    UnsupportedCompression(u16),
+    /// The CRC-32 of the extracted data does not match the one from the central directory.
+    CrcMismatch { file_name: String, expected: u32, actual: u32 },
+    /// The size of the extracted data does not match the declared size.
+    SizeMismatch { file_name: String, expected: u64, actual: u64 },
+    /// The data descriptor following the data of the entry can not be parsed.
+    InvalidDataDescriptor { file_name: String },
+    /// The entry defers its sizes to a data descriptor but is not deflated, so its end can not
+    /// be found without the central directory.
+    UnknownEntryLength { file_name: String },
+    /// The archive or the entry exceeds one of the limits set when opening the archive.
+    LimitExceeded { limit: Limit, file_name: Option<String> },
+    /// The file name or extra field of an entry to write is longer than the 65535 bytes the
+    /// headers can express.
+    FieldTooLong { file_name: String },
+    /// The page size to align shared libraries to is not a power of two up to 32768.
+    InvalidPageSize(u64),
+}
+
+impl fmt::Display for ZipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZipError::EndOfCentralDirectoryNotFound => write!(f, "end of central directory signature not found"),
+            ZipError::InvalidZip64EndOfCentralDirectory => write!(f, "invalid zip64 end of central directory record"),
+            ZipError::CentralDirectoryOutOfBounds { offset, size } => {
+                write!(f, "central directory at offset {} with size {} is out of bounds", offset, size)
+            }
+            ZipError::CentralDirectorySignatureMismatch { offset } => {
+                write!(f, "no central directory file header signature at offset {}", offset)
+            }
+            ZipError::InvalidCentralDirectory => write!(f, "error parsing central directory"),
+            ZipError::InvalidLocalFileHeader { file_name, offset } => {
+                write!(f, "{}: invalid local file header at offset {}", file_name, offset)
+            }
+            ZipError::EntryOutOfBounds { file_name } => write!(f, "{}: entry data is out of bounds", file_name),
+            ZipError::UnsupportedCompression(method) => write!(f, "unsupported compression method {}", method),
+            ZipError::CrcMismatch { file_name, expected, actual } => {
+                write!(f, "{}: crc32 mismatch, expected {:08x} but got {:08x}", file_name, expected, actual)
+            }
+            ZipError::SizeMismatch { file_name, expected, actual } => {
+                write!(f, "{}: size mismatch, expected {} bytes but got {}", file_name, expected, actual)
+            }
+            ZipError::InvalidDataDescriptor { file_name } => write!(f, "{}: invalid data descriptor", file_name),
+            ZipError::UnknownEntryLength { file_name } => {
+                write!(f, "{}: entry with data descriptor is not deflated, its length is unknown", file_name)
+            }
+            ZipError::LimitExceeded { limit, file_name: Some(file_name) } => write!(f, "{}: {} exceeded", file_name, limit),
+            ZipError::LimitExceeded { limit, file_name: None } => write!(f, "{} exceeded", limit),
+            ZipError::FieldTooLong { file_name } => write!(f, "{}: file name or extra field longer than 65535 bytes", file_name),
+            ZipError::InvalidPageSize(page_size) => write!(f, "invalid page size {}", page_size),
+        }
+    }
+}
+
+impl Error for ZipError {}
+
+impl From<ZipError> for io::Error {
+    fn from(e: ZipError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
@@ -0,0 +1,51 @@
+/// A record of the extra field of a local or central file header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExtraField<'a> {
+    pub header_id: u16,
+    pub data: &'a [u8],
+}
+
+/// Iterates over the records of an extra field. Trailing bytes too short for a record header,
+/// like the zero padding `zipalign` adds, are skipped.
+#[derive(Debug, Clone)]
+pub struct ExtraFields<'a>(&'a [u8]);
+
+impl<'a> ExtraFields<'a> {
+    pub fn new(extra_field: &'a [u8]) -> ExtraFields<'a> {
+        ExtraFields(extra_field)
+    }
+}
+
+impl<'a> Iterator for ExtraFields<'a> {
+    type Item = ExtraField<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let extra = self.0;
+        if extra.len() < 4 {
+            return None;
+        }
+        let header_id = u16::from(extra[0]) | u16::from(extra[1]) << 8;
+        let size = (usize::from(extra[2]) | usize::from(extra[3]) << 8).min(extra.len() - 4);
+        self.0 = &extra[4 + size..];
+        Some(ExtraField {
+            header_id,
+            data: &extra[4..4 + size],
+        })
+    }
+}
+
+/// Returns the data of the first record with the given header id.
+pub fn find_extra_field(extra_field: &[u8], header_id: u16) -> Option<&[u8]> {
+    ExtraFields::new(extra_field).find(|f| f.header_id == header_id).map(|f| f.data)
+}
+
+#[test]
+fn test_extra_fields() {
+    let d = [0x01, 0x00, 0x02, 0x00, 0xaa, 0xbb, 0x55, 0x54, 0x01, 0x00, 0x01, 0x00, 0x00];
+    let fields: Vec<ExtraField> = ExtraFields::new(&d).collect();
+    assert_eq!(fields, vec![
+        ExtraField { header_id: 0x0001, data: &[0xaa, 0xbb] },
+        ExtraField { header_id: 0x5455, data: &[0x01] },
+    ]);
+    assert_eq!(find_extra_field(&d, 0x5455), Some(&[0x01][..]));
+}
@@ -0,0 +1,43 @@
+/// Matches an entry name against a glob pattern. `?` matches a single character and `*` any
+/// number of characters, both except `/`. `**` matches across directories, so
+/// `lib/**/*.so` matches every shared library below `lib/`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    matches(pattern.as_bytes(), name.as_bytes())
+}
+
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern {
+        [] => name.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            // `**/` also matches no directory at all
+            matches(rest, name) || (0..name.len()).any(|i| name[i] == b'/' && matches(rest, &name[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=name.len()).any(|i| matches(rest, &name[i..])),
+        [b'*', rest @ ..] => {
+            let segment_len = name.iter().position(|&c| c == b'/').unwrap_or(name.len());
+            (0..=segment_len).any(|i| matches(rest, &name[i..]))
+        }
+        [b'?', rest @ ..] => match name {
+            [c, name_rest @ ..] if *c != b'/' => {
+                // skip the continuation bytes of a multi byte character
+                let len = name_rest.iter().take_while(|&&c| c & 0xc0 == 0x80).count();
+                matches(rest, &name_rest[len..])
+            }
+            _ => false,
+        },
+        [p, rest @ ..] => name.first() == Some(p) && matches(rest, &name[1..]),
+    }
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("lib/*/libfoo.so", "lib/arm64-v8a/libfoo.so"));
+    assert!(!glob_match("lib/*.so", "lib/arm64-v8a/libfoo.so"));
+    assert!(glob_match("lib/**/*.so", "lib/arm64-v8a/libfoo.so"));
+    assert!(glob_match("**/*.xml", "AndroidManifest.xml"));
+    assert!(!glob_match("res/drawable-??dpi/*", "res/drawable-xxxdpi/icon.png"));
+    assert!(glob_match("res/drawable-?dpi/*", "res/drawable-hdpi/icon.png"));
+    assert!(glob_match("classes?.dex", "classes2.dex"));
+    assert!(!glob_match("classes?.dex", "classes.dex"));
+    assert!(glob_match("META-INF/*", "META-INF/CERT.RSA"));
+}
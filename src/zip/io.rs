@@ -1,17 +1,28 @@
 use std::io::{self, Read, Seek};
 use std::fs::File;
-use std::io::SeekFrom;
-use std::rc::Rc;
+use std::io::{Cursor, SeekFrom};
+use std::sync::{Arc, Mutex};
+use std::fmt;
+use memmap2::Mmap;
 
+/// Source of an archive. Clones share the underlying file, buffer or reader but keep their own
+/// position, so entries can be read concurrently from different threads.
 #[derive(Debug,Clone)]
 pub enum ReaderWrapper {
-    FileReader(FileReader),
+    File(FileReader),
+    Memory(MemoryReader),
+    Mmap(MmapReader),
+    Shared(SharedReader),
 }
 
-impl Open for ReaderWrapper {
-    fn do_open(&mut self) -> io::Result<()> {
+impl ReaderWrapper {
+    /// The whole archive if it is accessible as a slice, which allows borrowing entry data
+    /// without copying it.
+    pub fn as_slice(&self) -> Option<&[u8]> {
         match self {
-            ReaderWrapper::FileReader(r) => r.do_open(),
+            ReaderWrapper::Memory(r) => Some(r.0.get_ref()),
+            ReaderWrapper::Mmap(r) => Some(r.0.get_ref().as_ref()),
+            _ => None,
         }
     }
 }
@@ -19,7 +30,10 @@ impl Open for ReaderWrapper {
 impl Read for ReaderWrapper {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
-            ReaderWrapper::FileReader(r) => r.read(buf),
+            ReaderWrapper::File(r) => r.read(buf),
+            ReaderWrapper::Memory(r) => r.read(buf),
+            ReaderWrapper::Mmap(r) => r.read(buf),
+            ReaderWrapper::Shared(r) => r.read(buf),
         }
     }
 }
@@ -27,66 +41,198 @@ impl Read for ReaderWrapper {
 impl Seek for ReaderWrapper {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         match self {
-            ReaderWrapper::FileReader(r) => r.seek(pos),
+            ReaderWrapper::File(r) => r.seek(pos),
+            ReaderWrapper::Memory(r) => r.seek(pos),
+            ReaderWrapper::Mmap(r) => r.seek(pos),
+            ReaderWrapper::Shared(r) => r.seek(pos),
         }
     }
 }
 
-
-pub trait Open {
-    fn do_open(&mut self) -> io::Result<()>;
+fn offset_position(position: u64, offset: i64) -> io::Result<u64> {
+    position.checked_add_signed(offset).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")
+    })
 }
 
-#[derive(Debug)]
+/// Reads a file with positional reads (pread), so all clones can share one file handle.
+#[derive(Debug, Clone)]
 pub struct FileReader {
-    path: Rc<String>,
-    file: Option<File>,
+    file: Arc<File>,
+    len: u64,
+    position: u64,
 }
 
 impl FileReader {
     pub fn open(path: &str) -> io::Result<FileReader> {
         let file = File::open(path)?;
+        let len = file.metadata()?.len();
 
         Ok(FileReader {
-            path: Rc::new(path.into()),
-            file: Some(file),
+            file: Arc::new(file),
+            len,
+            position: 0,
         })
     }
 }
 
-impl Open for FileReader {
-    fn do_open(&mut self) -> io::Result<()> {
-        match self.file {
-            None => self.file = Some(File::open(self.path.as_ref())?),
-            _ => {}
-        }
-        Ok(())
-    }
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::os::windows::fs::FileExt;
+    file.seek_read(buf, offset)
 }
 
 impl Read for FileReader {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.file.is_none() {
-            self.do_open()?;
-        }
-        self.file.as_ref().unwrap().read(buf)
+        let n = read_at(&self.file, buf, self.position)?;
+        self.position += n as u64;
+        Ok(n)
     }
 }
 
 impl Seek for FileReader {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        if self.file.is_none() {
-            self.do_open()?;
-        }
-        self.file.as_ref().unwrap().seek(pos)
+        self.position = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(offset) => offset_position(self.len, offset)?,
+            SeekFrom::Current(offset) => offset_position(self.position, offset)?,
+        };
+        Ok(self.position)
+    }
+}
+
+/// Reads from an archive held in memory. Clones share the buffer but keep their own position.
+#[derive(Clone)]
+pub struct MemoryReader(Cursor<Arc<[u8]>>);
+
+impl MemoryReader {
+    pub fn new(data: Arc<[u8]>) -> MemoryReader {
+        MemoryReader(Cursor::new(data))
+    }
+}
+
+impl fmt::Debug for MemoryReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryReader")
+            .field("len", &self.0.get_ref().len())
+            .field("position", &self.0.position())
+            .finish()
+    }
+}
+
+impl Read for MemoryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Seek for MemoryReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
     }
 }
 
-impl Clone for FileReader {
-    fn clone(&self) -> FileReader {
-        FileReader {
-            path: self.path.clone(),
-            file: None,
+#[derive(Clone)]
+struct SharedMmap(Arc<Mmap>);
+
+impl AsRef<[u8]> for SharedMmap {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Reads from a memory mapped archive. Clones share the mapping but keep their own position.
+#[derive(Clone)]
+pub struct MmapReader(Cursor<SharedMmap>);
+
+impl MmapReader {
+    pub fn open(path: &str) -> io::Result<MmapReader> {
+        let file = File::open(path)?;
+        // the mapping is only valid as long as nobody truncates or modifies the file
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(MmapReader(Cursor::new(SharedMmap(Arc::new(map)))))
+    }
+}
+
+impl fmt::Debug for MmapReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MmapReader")
+            .field("len", &self.0.get_ref().as_ref().len())
+            .field("position", &self.0.position())
+            .finish()
+    }
+}
+
+impl Read for MmapReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Seek for MmapReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Reads from an arbitrary `Read + Seek` source. Clones share the source behind a mutex, every
+/// clone keeps its own position and seeks the source to it before reading.
+#[derive(Clone)]
+pub struct SharedReader {
+    inner: Arc<Mutex<Box<dyn ReadSeek>>>,
+    position: u64,
+}
+
+impl SharedReader {
+    pub fn new<R: Read + Seek + Send + 'static>(inner: R) -> SharedReader {
+        SharedReader {
+            inner: Arc::new(Mutex::new(Box::new(inner))),
+            position: 0,
         }
     }
+
+    fn lock(&self) -> io::Result<std::sync::MutexGuard<'_, Box<dyn ReadSeek>>> {
+        self.inner.lock().map_err(|_| io::Error::other("shared reader poisoned"))
+    }
+}
+
+impl fmt::Debug for SharedReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedReader")
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl Read for SharedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let mut inner = self.lock()?;
+            inner.seek(SeekFrom::Start(self.position))?;
+            inner.read(buf)?
+        };
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for SharedReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(_) => self.lock()?.seek(pos)?,
+            SeekFrom::Current(offset) => offset_position(self.position, offset)?,
+        };
+        Ok(self.position)
+    }
 }
@@ -0,0 +1,162 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::zip::error::ZipError;
+
+/// Buffers for extracted entries are preallocated up to this size, beyond it they grow as data
+/// arrives. The declared size of an entry can not be trusted before it has been extracted.
+pub(crate) const MAX_PREALLOCATION: usize = 16 * 1024 * 1024;
+
+/// The limit that was exceeded, see `ZipError::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    EntrySize,
+    TotalUncompressed,
+    CompressionRatio,
+    EntryCount,
+    CentralDirectorySize,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::EntrySize => write!(f, "maximum entry size"),
+            Limit::TotalUncompressed => write!(f, "maximum total uncompressed size"),
+            Limit::CompressionRatio => write!(f, "maximum compression ratio"),
+            Limit::EntryCount => write!(f, "maximum number of entries"),
+            Limit::CentralDirectorySize => write!(f, "maximum central directory size"),
+        }
+    }
+}
+
+/// Limits on the resources an archive may use, `None` means unlimited.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Limits {
+    pub max_entry_size: Option<u64>,
+    pub max_total_uncompressed: Option<u64>,
+    pub max_compression_ratio: Option<u64>,
+    pub max_entry_count: Option<usize>,
+    pub max_central_directory_size: Option<u64>,
+}
+
+/// The limits of an archive together with the number of bytes extracted from all of its
+/// entries so far. That total is a budget for the lifetime of the archive, shared by all of its
+/// clones, until it is reset.
+#[derive(Debug, Default)]
+pub(crate) struct LimitState {
+    pub limits: Limits,
+    total_uncompressed: AtomicU64,
+}
+
+impl LimitState {
+    pub fn new(limits: Limits) -> LimitState {
+        LimitState {
+            limits,
+            total_uncompressed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn total_uncompressed(&self) -> u64 {
+        self.total_uncompressed.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_total_uncompressed(&self) {
+        self.total_uncompressed.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Wraps the decoder of an entry. Fails as soon as the entry produces more data than it
+/// declares or than the limits allow, so a decompression bomb is never fully extracted. Once
+/// it failed, every further read returns the same error.
+pub(crate) struct LimitedReader<R> {
+    inner: R,
+    state: Arc<LimitState>,
+    file_name: String,
+    compressed_size: u64,
+    declared_size: u64,
+    read: u64,
+    error: Option<ZipError>,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(inner: R, state: Arc<LimitState>, file_name: String, compressed_size: u64, declared_size: u64) -> LimitedReader<R> {
+        LimitedReader {
+            inner,
+            state,
+            file_name,
+            compressed_size,
+            declared_size,
+            read: 0,
+            error: None,
+        }
+    }
+
+    fn exceeded(&self, limit: Limit) -> ZipError {
+        ZipError::LimitExceeded { limit, file_name: Some(self.file_name.clone()) }
+    }
+
+    /// Checks the counters after `n` more bytes were read.
+    fn check(&self, n: usize, len: usize) -> Result<(), ZipError> {
+        if self.read > self.declared_size || (n == 0 && len > 0 && self.read != self.declared_size) {
+            return Err(ZipError::SizeMismatch {
+                file_name: self.file_name.clone(),
+                expected: self.declared_size,
+                actual: self.read,
+            });
+        }
+        let limits = &self.state.limits;
+        if limits.max_entry_size.is_some_and(|max| self.read > max) {
+            return Err(self.exceeded(Limit::EntrySize));
+        }
+        if limits.max_compression_ratio.is_some_and(|max| self.read > self.compressed_size.max(1).saturating_mul(max)) {
+            return Err(self.exceeded(Limit::CompressionRatio));
+        }
+        let total = self.state.total_uncompressed.fetch_add(n as u64, Ordering::Relaxed) + n as u64;
+        if limits.max_total_uncompressed.is_some_and(|max| total > max) {
+            return Err(self.exceeded(Limit::TotalUncompressed));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(error) = &self.error {
+            return Err(error.clone().into());
+        }
+        // ask for at most one byte more than declared, enough to detect the overrun
+        let len = buf.len().min((self.declared_size - self.read).saturating_add(1).min(usize::MAX as u64) as usize);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.read += n as u64;
+        match self.check(n, len) {
+            Ok(()) => Ok(n),
+            Err(error) => {
+                self.error = Some(error.clone());
+                Err(error.into())
+            }
+        }
+    }
+}
+
+#[test]
+fn test_limited_reader() {
+    let state = Arc::new(LimitState::default());
+    let mut r = LimitedReader::new(&[0u8; 100][..], state.clone(), "a".into(), 100, 100);
+    assert_eq!(io::copy(&mut r, &mut io::sink()).unwrap(), 100);
+
+    // more data than declared
+    let mut r = LimitedReader::new(&[0u8; 100][..], state.clone(), "a".into(), 10, 50);
+    let e = io::copy(&mut r, &mut io::sink()).unwrap_err();
+    assert!(matches!(e.get_ref().unwrap().downcast_ref::<ZipError>(), Some(ZipError::SizeMismatch { actual: 51, .. })));
+    let e = r.read(&mut [0; 10]).unwrap_err();
+    assert!(matches!(e.get_ref().unwrap().downcast_ref::<ZipError>(), Some(ZipError::SizeMismatch { actual: 51, .. })));
+
+    let limits = Limits { max_compression_ratio: Some(5), ..Limits::default() };
+    let mut r = LimitedReader::new(&[0u8; 100][..], Arc::new(LimitState::new(limits)), "a".into(), 10, 100);
+    let e = io::copy(&mut r, &mut io::sink()).unwrap_err();
+    assert_eq!(
+        e.get_ref().unwrap().downcast_ref::<ZipError>(),
+        Some(&ZipError::LimitExceeded { limit: Limit::CompressionRatio, file_name: Some("a".into()) })
+    );
+}
@@ -1,3 +1,13 @@
 mod io;
+pub mod align;
 pub mod archive;
+pub mod audit;
+mod crc32;
+pub mod error;
+pub mod extra;
+mod glob;
+pub mod limits;
 mod parser;
+pub mod stream;
+pub mod time;
+pub mod writer;
@@ -1,40 +1,108 @@
 use nom::*;
-use std::borrow::Cow;
+use crate::zip::extra::find_extra_field;
 
-named!(pub parse_local_file_header<&[u8], (i64, i64)>, do_parse!(
+#[derive(Debug, Clone)]
+pub struct LocalFileHeader {
+    pub general_purpose_flags: u16,
+    pub compression_method: u16,
+    pub last_mod_time: u16,
+    pub last_mod_date: u16,
+    pub crc32: u32,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+    pub file_name_length: u16,
+    pub extra_field_length: u16,
+}
+
+impl LocalFileHeader {
+    pub fn has_data_descriptor(&self) -> bool {
+        self.general_purpose_flags & 0x08 == 0x08
+    }
+}
+
+named!(pub parse_local_file_header<&[u8], LocalFileHeader>, do_parse!(
     tag!([0x50, 0x4b, 0x03, 0x04]) >>
     version: le_u16 >>
-    flags: le_u16 >>
-    compression: le_u16 >>
-    mod_time: le_u16 >>
-    mod_date: le_u16 >>
+    general_purpose_flags: le_u16 >>
+    compression_method: le_u16 >>
+    last_mod_time: le_u16 >>
+    last_mod_date: le_u16 >>
     crc32: le_u32 >>
     compressed_size: le_u32 >>
     uncompressed_size: le_u32 >>
-    file_name_len: le_u16 >>
-    extra_field_len: le_u16 >>
-    ((file_name_len as i64, extra_field_len as i64))
+    file_name_length: le_u16 >>
+    extra_field_length: le_u16 >>
+    (LocalFileHeader {
+        general_purpose_flags,
+        compression_method,
+        last_mod_time,
+        last_mod_date,
+        crc32,
+        compressed_size,
+        uncompressed_size,
+        file_name_length,
+        extra_field_length,
+    })
+));
+
+// the ZIP64 extra field of local headers always contains both sizes
+named!(pub parse_local_zip64_sizes<&[u8], (u64, u64)>, do_parse!(
+    uncompressed_size: le_u64 >>
+    compressed_size: le_u64 >>
+    ((uncompressed_size, compressed_size))
 ));
 
+#[derive(Debug)]
+pub struct DataDescriptor {
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+/// Data descriptors follow the compressed data of entries with general purpose bit 3 set. The
+/// signature is optional, sizes are 64 bit wide if the entry uses ZIP64.
+pub fn parse_data_descriptor(input: &[u8], zip64: bool) -> IResult<&[u8], DataDescriptor> {
+    let input = if input.starts_with(&[0x50, 0x4b, 0x07, 0x08]) { &input[4..] } else { input };
+    if zip64 {
+        do_parse!(input,
+            crc32: le_u32 >>
+            compressed_size: le_u64 >>
+            uncompressed_size: le_u64 >>
+            (DataDescriptor { crc32, compressed_size, uncompressed_size })
+        )
+    } else {
+        do_parse!(input,
+            crc32: le_u32 >>
+            compressed_size: le_u32 >>
+            uncompressed_size: le_u32 >>
+            (DataDescriptor {
+                crc32,
+                compressed_size: u64::from(compressed_size),
+                uncompressed_size: u64::from(uncompressed_size),
+            })
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CentralDirectoryFileHeader {
-    //version_producer: u16,
-    //min_version_extractor: u16,
+    pub version_producer: u16,
+    pub min_version_extractor: u16,
     pub general_purpose_flags: u16,
     pub compression_method: u16,
-    //last_mod_time: u16,
-    //last_mod_date: u16,
+    pub last_mod_time: u16,
+    pub last_mod_date: u16,
     crc32: u32,
-    pub compressed_size: u32,
-    pub uncompressed_size: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
 
-    //disk_number_start: u16,
-    //internal_file_attributes: u16,
-    //external_file_attributes: u32,
-    pub relative_offset_of_local_header: u32,
+    pub disk_number_start: u16,
+    pub internal_file_attributes: u16,
+    pub external_file_attributes: u32,
+    pub relative_offset_of_local_header: u64,
     file_name: Vec<u8>,
     extra_field: Vec<u8>,
-    //file_comment: Vec<u8>,
+    file_comment: Vec<u8>,
 }
 
 impl CentralDirectoryFileHeader {
@@ -42,9 +110,48 @@ impl CentralDirectoryFileHeader {
         String::from_utf8_lossy(&self.file_name).to_string()
     }
 
+    pub fn file_name_bytes(&self) -> &[u8] {
+        &self.file_name
+    }
+
+    pub fn extra_field(&self) -> &[u8] {
+        &self.extra_field
+    }
+
+    pub fn file_comment(&self) -> &[u8] {
+        &self.file_comment
+    }
+
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
     pub fn is_utf8(&self) -> bool {
         self.general_purpose_flags & (0x800) == 0x800
     }
+
+    /// Replaces the 32 bit sizes and offset that are saturated to 0xFFFFFFFF with the values
+    /// from the ZIP64 extended information extra field (0x0001). The extra field only contains
+    /// the values whose regular field is saturated, always in this order.
+    fn apply_zip64_extra_field(&mut self) {
+        let mut data = match find_extra_field(&self.extra_field, 0x0001) {
+            Some(data) => data,
+            None => return,
+        };
+        for field in [
+            &mut self.uncompressed_size,
+            &mut self.compressed_size,
+            &mut self.relative_offset_of_local_header,
+        ] {
+            if *field != 0xFFFF_FFFF {
+                continue;
+            }
+            if let IResult::Done(rest, value) = le_u64(data) {
+                *field = value;
+                data = rest;
+            }
+        }
+    }
 }
 
 named!(pub parse_central_directory<&[u8], Vec<CentralDirectoryFileHeader>>, do_parse!(
@@ -73,23 +180,95 @@ named!(pub parse_central_file_header<&[u8], CentralDirectoryFileHeader>, do_pars
     file_name: take!(filename_length) >>
     extra_field: take!(extra_field_length) >>
     file_comment: take!(file_comment_length) >>
-    (CentralDirectoryFileHeader {
-        //version_producer,
-        //min_version_extractor,
-        general_purpose_flags,
-        compression_method,
-        //last_mod_time,
-        //last_mod_date,
-        crc32,
-        compressed_size,
-        uncompressed_size,
+    ({
+        let mut header = CentralDirectoryFileHeader {
+            version_producer,
+            min_version_extractor,
+            general_purpose_flags,
+            compression_method,
+            last_mod_time,
+            last_mod_date,
+            crc32,
+            compressed_size: u64::from(compressed_size),
+            uncompressed_size: u64::from(uncompressed_size),
 
-        //disk_number_start,
-        //internal_file_attributes,
-        //external_file_attributes,
-        relative_offset_of_local_header,
-        file_name: file_name.into(),
-        extra_field: extra_field.into(),
-        //file_comment: file_comment.into(),
+            disk_number_start,
+            internal_file_attributes,
+            external_file_attributes,
+            relative_offset_of_local_header: u64::from(relative_offset_of_local_header),
+            file_name: file_name.into(),
+            extra_field: extra_field.into(),
+            file_comment: file_comment.into(),
+        };
+        header.apply_zip64_extra_field();
+        header
     })
 ));
+
+#[derive(Debug)]
+pub struct EndOfCentralDirectory {
+    pub central_directory_size: u64,
+    pub central_directory_offset: u64,
+    pub comment: Vec<u8>,
+}
+
+named!(pub parse_end_of_central_directory<&[u8], EndOfCentralDirectory>, do_parse!(
+    tag!([0x50, 0x4b, 0x05, 0x06]) >>
+    disk_number: le_u16 >>
+    central_directory_disk_number: le_u16 >>
+    entries_on_disk: le_u16 >>
+    total_entries: le_u16 >>
+    central_directory_size: le_u32 >>
+    central_directory_offset: le_u32 >>
+    comment: length_bytes!(le_u16) >>
+    (EndOfCentralDirectory {
+        central_directory_size: u64::from(central_directory_size),
+        central_directory_offset: u64::from(central_directory_offset),
+        comment: comment.to_vec(),
+    })
+));
+
+named!(pub parse_zip64_end_of_central_directory_locator<&[u8], u64>, do_parse!(
+    tag!([0x50, 0x4b, 0x06, 0x07]) >>
+    disk_number: le_u32 >>
+    zip64_end_of_central_directory_offset: le_u64 >>
+    total_disks: le_u32 >>
+    (zip64_end_of_central_directory_offset)
+));
+
+named!(pub parse_zip64_end_of_central_directory<&[u8], EndOfCentralDirectory>, do_parse!(
+    tag!([0x50, 0x4b, 0x06, 0x06]) >>
+    record_size: le_u64 >>
+    version_producer: le_u16 >>
+    min_version_extractor: le_u16 >>
+    disk_number: le_u32 >>
+    central_directory_disk_number: le_u32 >>
+    entries_on_disk: le_u64 >>
+    total_entries: le_u64 >>
+    central_directory_size: le_u64 >>
+    central_directory_offset: le_u64 >>
+    (EndOfCentralDirectory {
+        central_directory_size,
+        central_directory_offset,
+        comment: Vec::new(),
+    })
+));
+
+#[test]
+fn test_zip64_extra_field() {
+    let mut d = vec![0x50, 0x4b, 0x01, 0x02, 0x2d, 0x00, 0x2d, 0x00, 0x00, 0x00, 0x00, 0x00];
+    d.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x78, 0x56, 0x34, 0x12]);
+    // compressed size fits into 32 bits, uncompressed size and offset do not
+    d.extend_from_slice(&[0x10, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff]);
+    d.extend_from_slice(&[0x01, 0x00, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    d.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, b'a']);
+    d.extend_from_slice(&[0x01, 0x00, 0x10, 0x00]);
+    d.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    d.extend_from_slice(&[0x00, 0x10, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00]);
+    let (rest, header) = parse_central_file_header(&d).unwrap();
+    assert_eq!(rest.len(), 0);
+    assert_eq!(header.file_name(), "a");
+    assert_eq!(header.compressed_size, 0x10);
+    assert_eq!(header.uncompressed_size, 0x1_0000_0000);
+    assert_eq!(header.relative_offset_of_local_header, 0x2_0000_1000);
+}
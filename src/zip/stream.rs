@@ -0,0 +1,341 @@
+use std::io::{self, BufRead, BufReader, Read};
+use flate2::{Decompress, FlushDecompress, Status};
+use crc32fast::Hasher;
+use nom::IResult;
+use crate::zip::archive::Compression;
+use crate::zip::error::ZipError;
+use crate::zip::extra::{find_extra_field, ExtraFields};
+use crate::zip::parser::{self, LocalFileHeader};
+use crate::zip::time::{self, DateTime};
+
+/// Reads an archive front to back from a source that can not seek, like a network stream,
+/// by walking the local file headers. Entries with a data descriptor (general purpose bit 3)
+/// are only supported if they are deflated, as the end of their data is found by the end of
+/// the deflate stream. Reading stops at the first record that is not a local file header,
+/// usually the APK signing block or the central directory.
+///
+/// The central directory is never read, so entries hidden from it or duplicated in it are not
+/// detected. Use `ZipArchive` when the whole archive is available.
+pub struct ZipStreamReader<R: Read> {
+    reader: BufReader<R>,
+    current: Option<EntryState>,
+    finished: bool,
+}
+
+/// The decoding state of the entry returned last by `next_entry`. It is kept in the reader so
+/// the rest of the entry can be skipped when the next one is requested.
+struct EntryState {
+    file_name: String,
+    compression_method: u16,
+    data_descriptor: bool,
+    zip64: bool,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    compressed_read: u64,
+    uncompressed_read: u64,
+    decompress: Option<Decompress>,
+    hasher: Hasher,
+    done: bool,
+}
+
+/// An entry of a `ZipStreamReader`. Reading it yields the uncompressed data, the CRC-32 and
+/// sizes are checked at the end.
+pub struct ZipStreamEntry<'a, R: Read> {
+    stream: &'a mut ZipStreamReader<R>,
+    header: LocalFileHeader,
+    file_name: Vec<u8>,
+    extra_field: Vec<u8>,
+}
+
+impl<R: Read> ZipStreamReader<R> {
+    pub fn new(reader: R) -> ZipStreamReader<R> {
+        ZipStreamReader {
+            reader: BufReader::new(reader),
+            current: None,
+            finished: false,
+        }
+    }
+
+    /// Skips the rest of the previous entry and returns the next one, or `None` after the
+    /// last local file header.
+    pub fn next_entry(&mut self) -> io::Result<Option<ZipStreamEntry<'_, R>>> {
+        self.skip_current()?;
+        if self.finished {
+            return Ok(None);
+        }
+
+        let mut signature = [0; 4];
+        if !read_or_eof(&mut self.reader, &mut signature)? || signature != [0x50, 0x4b, 0x03, 0x04] {
+            self.finished = true;
+            return Ok(None);
+        }
+        let mut buf = vec![0; 30];
+        buf[..4].copy_from_slice(&signature);
+        self.reader.read_exact(&mut buf[4..])?;
+        let header = match parser::parse_local_file_header(&buf) {
+            IResult::Done(_, header) => header,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid local file header")),
+        };
+        let mut file_name = vec![0; usize::from(header.file_name_length)];
+        self.reader.read_exact(&mut file_name)?;
+        let mut extra_field = vec![0; usize::from(header.extra_field_length)];
+        self.reader.read_exact(&mut extra_field)?;
+
+        let name = String::from_utf8_lossy(&file_name).to_string();
+        let mut compressed_size = u64::from(header.compressed_size);
+        let mut uncompressed_size = u64::from(header.uncompressed_size);
+        let zip64_extra = find_extra_field(&extra_field, 0x0001);
+        if let Some(IResult::Done(_, sizes)) = zip64_extra.map(parser::parse_local_zip64_sizes) {
+            if header.compressed_size == 0xFFFF_FFFF || header.uncompressed_size == 0xFFFF_FFFF {
+                uncompressed_size = sizes.0;
+                compressed_size = sizes.1;
+            }
+        }
+
+        let compression = Compression::from(header.compression_method);
+        if header.has_data_descriptor() && compression != Compression::Deflate {
+            return Err(ZipError::UnknownEntryLength { file_name: name }.into());
+        }
+        self.current = Some(EntryState {
+            file_name: name,
+            compression_method: header.compression_method,
+            data_descriptor: header.has_data_descriptor(),
+            zip64: zip64_extra.is_some(),
+            crc32: header.crc32,
+            compressed_size,
+            uncompressed_size,
+            compressed_read: 0,
+            uncompressed_read: 0,
+            decompress: if compression == Compression::Deflate { Some(Decompress::new(false)) } else { None },
+            hasher: Hasher::new(),
+            done: false,
+        });
+
+        Ok(Some(ZipStreamEntry {
+            stream: self,
+            header,
+            file_name,
+            extra_field,
+        }))
+    }
+
+    /// Moves the reader past the data (and data descriptor) of the current entry. Entries with
+    /// known sizes are skipped without decompressing them.
+    fn skip_current(&mut self) -> io::Result<()> {
+        let (data_descriptor, done, remaining) = match self.current.as_ref() {
+            Some(entry) => (entry.data_descriptor, entry.done, entry.compressed_size.saturating_sub(entry.compressed_read)),
+            None => return Ok(()),
+        };
+        if data_descriptor {
+            if !done {
+                io::copy(&mut CurrentEntry(self), &mut io::sink())?;
+            }
+        } else {
+            let skipped = io::copy(&mut (&mut self.reader).take(remaining), &mut io::sink())?;
+            if skipped != remaining {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        self.current = None;
+        Ok(())
+    }
+
+    fn read_current(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let entry = match self.current.as_mut() {
+            Some(entry) if !entry.done => entry,
+            _ => return Ok(0),
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let n = match entry.decompress.as_mut() {
+            Some(decompress) => inflate(&mut self.reader, decompress, entry.data_descriptor, entry.compressed_size, buf)?,
+            None if entry.compression_method == 0 => {
+                let remaining = entry.compressed_size - entry.compressed_read;
+                let len = buf.len().min(remaining.min(usize::MAX as u64) as usize);
+                let n = self.reader.read(&mut buf[..len])?;
+                if n == 0 && len > 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                entry.compressed_read += n as u64;
+                n
+            }
+            None => return Err(ZipError::UnsupportedCompression(entry.compression_method).into()),
+        };
+        if let Some(decompress) = entry.decompress.as_ref() {
+            entry.compressed_read = decompress.total_in();
+        }
+        entry.hasher.update(&buf[..n]);
+        entry.uncompressed_read += n as u64;
+
+        if n == 0 {
+            entry.done = true;
+            if entry.data_descriptor {
+                read_data_descriptor(&mut self.reader, entry)?;
+            }
+            check_entry(entry)?;
+        }
+        Ok(n)
+    }
+}
+
+/// Feeds the decompressor from the buffer of the reader, consuming only the bytes that belong
+/// to the deflate stream so the data descriptor or next header can be read afterwards.
+fn inflate<R: Read>(reader: &mut BufReader<R>, decompress: &mut Decompress, data_descriptor: bool, compressed_size: u64, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let input = reader.fill_buf()?;
+        let input = if data_descriptor {
+            input
+        } else {
+            let remaining = compressed_size.saturating_sub(decompress.total_in());
+            &input[..input.len().min(remaining.min(usize::MAX as u64) as usize)]
+        };
+        let eof = input.is_empty();
+        let before_in = decompress.total_in();
+        let before_out = decompress.total_out();
+        let status = decompress.decompress(input, buf, FlushDecompress::None)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let consumed = (decompress.total_in() - before_in) as usize;
+        let produced = (decompress.total_out() - before_out) as usize;
+        reader.consume(consumed);
+
+        if produced > 0 || status == Status::StreamEnd {
+            return Ok(produced);
+        }
+        if eof {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+}
+
+fn read_data_descriptor<R: Read>(reader: &mut BufReader<R>, entry: &mut EntryState) -> io::Result<()> {
+    let mut buf = vec![0; if entry.zip64 { 24 } else { 16 }];
+    reader.read_exact(&mut buf[..4])?;
+    let len = if buf.starts_with(&[0x50, 0x4b, 0x07, 0x08]) { buf.len() } else { buf.len() - 4 };
+    reader.read_exact(&mut buf[4..len])?;
+    match parser::parse_data_descriptor(&buf[..len], entry.zip64) {
+        IResult::Done(_, descriptor) => {
+            entry.crc32 = descriptor.crc32;
+            entry.compressed_size = descriptor.compressed_size;
+            entry.uncompressed_size = descriptor.uncompressed_size;
+            Ok(())
+        }
+        _ => Err(ZipError::InvalidDataDescriptor { file_name: entry.file_name.clone() }.into()),
+    }
+}
+
+fn check_entry(entry: &EntryState) -> Result<(), ZipError> {
+    let actual = entry.hasher.clone().finalize();
+    if actual != entry.crc32 {
+        return Err(ZipError::CrcMismatch { file_name: entry.file_name.clone(), expected: entry.crc32, actual });
+    }
+    if entry.uncompressed_read != entry.uncompressed_size {
+        return Err(ZipError::SizeMismatch {
+            file_name: entry.file_name.clone(),
+            expected: entry.uncompressed_size,
+            actual: entry.uncompressed_read,
+        });
+    }
+    if entry.data_descriptor && entry.compressed_read != entry.compressed_size {
+        return Err(ZipError::InvalidDataDescriptor { file_name: entry.file_name.clone() });
+    }
+    Ok(())
+}
+
+/// Fills `buf` completely, returns `false` if the reader is already at its end.
+fn read_or_eof<R: BufRead>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(false);
+    }
+    reader.read_exact(buf)?;
+    Ok(true)
+}
+
+struct CurrentEntry<'a, R: Read>(&'a mut ZipStreamReader<R>);
+
+impl<'a, R: Read> Read for CurrentEntry<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read_current(buf)
+    }
+}
+
+impl<'a, R: Read> ZipStreamEntry<'a, R> {
+    pub fn file_name(&self) -> String {
+        String::from_utf8_lossy(&self.file_name).to_string()
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.header.compression_method.into()
+    }
+
+    /// The uncompressed size, `None` if it is only known after reading the data descriptor.
+    pub fn uncompressed_size(&self) -> Option<u64> {
+        if self.header.has_data_descriptor() {
+            return None;
+        }
+        self.stream.current.as_ref().map(|entry| entry.uncompressed_size)
+    }
+
+    pub fn has_data_descriptor(&self) -> bool {
+        self.header.has_data_descriptor()
+    }
+
+    pub fn last_modified(&self) -> DateTime {
+        time::modification_time(&self.extra_field, self.header.last_mod_date, self.header.last_mod_time)
+    }
+
+    pub fn extra_fields(&self) -> ExtraFields<'_> {
+        ExtraFields::new(&self.extra_field)
+    }
+}
+
+impl<'a, R: Read> Read for ZipStreamEntry<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read_current(buf)
+    }
+}
+
+#[test]
+fn test_zip_stream_reader() {
+    use flate2::write::DeflateEncoder;
+    use std::io::Write;
+
+    // a deflated entry with a data descriptor followed by a stored entry
+    let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"hello hello hello").unwrap();
+    let deflated = encoder.finish().unwrap();
+    let mut d = vec![0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x08, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x00];
+    d.extend_from_slice(&[0; 12]);
+    d.extend_from_slice(&[0x01, 0x00, 0x00, 0x00, b'a']);
+    d.extend_from_slice(&deflated);
+    d.extend_from_slice(&[0x50, 0x4b, 0x07, 0x08]);
+    d.extend_from_slice(&crc32fast::hash(b"hello hello hello").to_le_bytes());
+    d.extend_from_slice(&(deflated.len() as u32).to_le_bytes());
+    d.extend_from_slice(&17u32.to_le_bytes());
+    d.extend_from_slice(&[0x50, 0x4b, 0x03, 0x04, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00]);
+    d.extend_from_slice(&[0xac, 0x2a, 0x93, 0xd8, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    d.extend_from_slice(b"bhi");
+    d.extend_from_slice(&[0x50, 0x4b, 0x01, 0x02]);
+
+    let mut stream = ZipStreamReader::new(&d[..]);
+    let mut entry = stream.next_entry().unwrap().unwrap();
+    assert_eq!(entry.file_name(), "a");
+    assert_eq!(entry.uncompressed_size(), None);
+    let mut content = String::new();
+    entry.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "hello hello hello");
+
+    // the second entry is skipped without being read
+    assert_eq!(stream.next_entry().unwrap().unwrap().uncompressed_size(), Some(2));
+    assert!(stream.next_entry().unwrap().is_none());
+
+    // skipping the deflated entry has to find the data descriptor as well
+    let mut stream = ZipStreamReader::new(&d[..]);
+    stream.next_entry().unwrap();
+    let mut entry = stream.next_entry().unwrap().unwrap();
+    let mut content = String::new();
+    entry.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "hi");
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::zip::extra::find_extra_field;
+
+/// Date and time of an entry. DOS timestamps carry no time zone, they are treated as UTC when
+/// converting to Unix time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Decodes the date and time fields of ZIP headers, seconds have a resolution of 2.
+    pub fn from_dos(date: u16, time: u16) -> DateTime {
+        DateTime {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0x0f) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3f) as u8,
+            second: ((time & 0x1f) * 2) as u8,
+        }
+    }
+
+    /// Encodes as `(date, time)` for ZIP headers, dates before 1980 are clamped to 1980-01-01.
+    pub fn to_dos(&self) -> (u16, u16) {
+        if self.year < 1980 {
+            return (0x21, 0);
+        }
+        let date = ((self.year.min(2107) - 1980) << 9) | u16::from(self.month) << 5 | u16::from(self.day);
+        let time = (u16::from(self.hour) << 11) | (u16::from(self.minute) << 5) | (u16::from(self.second) / 2);
+        (date, time)
+    }
+
+    pub fn from_unix_timestamp(timestamp: i64) -> DateTime {
+        let days = timestamp.div_euclid(86400);
+        let seconds = timestamp.rem_euclid(86400);
+
+        // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds % 3600 / 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    pub fn to_unix_timestamp(&self) -> i64 {
+        let month = i64::from(self.month);
+        let year = i64::from(self.year) - if month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        days * 86400 + i64::from(self.hour) * 3600 + i64::from(self.minute) * 60 + i64::from(self.second)
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        let timestamp = self.to_unix_timestamp();
+        if timestamp >= 0 {
+            UNIX_EPOCH + Duration::from_secs(timestamp as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(timestamp.unsigned_abs())
+        }
+    }
+}
+
+/// The modification time from the extended timestamp extra field (0x5455) if present, otherwise
+/// the DOS timestamp of the header.
+pub(crate) fn modification_time(extra_field: &[u8], dos_date: u16, dos_time: u16) -> DateTime {
+    let mtime = find_extra_field(extra_field, 0x5455)
+        .filter(|data| data.len() >= 5 && data[0] & 0x01 == 0x01)
+        .map(|data| i32::from_le_bytes([data[1], data[2], data[3], data[4]]));
+    match mtime {
+        Some(mtime) => DateTime::from_unix_timestamp(i64::from(mtime)),
+        None => DateTime::from_dos(dos_date, dos_time),
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+#[test]
+fn test_date_time() {
+    let dt = DateTime::from_dos(0x4f21, 0x6a5d);
+    assert_eq!(dt.to_string(), "2019-09-01 13:18:58");
+    assert_eq!(dt.to_dos(), (0x4f21, 0x6a5d));
+    assert_eq!(dt.to_unix_timestamp(), 1_567_343_938);
+    assert_eq!(DateTime::from_unix_timestamp(1_567_343_938), dt);
+    assert_eq!(DateTime::from_unix_timestamp(0).to_string(), "1970-01-01 00:00:00");
+}
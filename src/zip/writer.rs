@@ -0,0 +1,400 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use flate2::write::DeflateEncoder;
+use crate::zip::archive::{Compression, ZipEntry};
+use crate::zip::error::ZipError;
+use crate::zip::align;
+use crate::zip::extra::ExtraFields;
+
+/// 1980-01-01 00:00:00, the earliest date DOS timestamps can express. Used for all entries
+/// written without an explicit timestamp so builds are reproducible.
+const DEFAULT_DOS_DATE: u16 = 0x21;
+
+struct WrittenEntry {
+    version_producer: u16,
+    version_needed: u16,
+    general_purpose_flags: u16,
+    compression_method: u16,
+    last_mod_time: u16,
+    last_mod_date: u16,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    external_file_attributes: u32,
+    local_header_offset: u64,
+    file_name: Vec<u8>,
+    extra_field: Vec<u8>,
+}
+
+/// Writes a ZIP archive entry by entry. Entries end up in the central directory in the order
+/// they were added, ZIP64 records are only written when sizes, offsets or the number of entries
+/// require them.
+pub struct ZipWriter<W: Write> {
+    inner: W,
+    offset: u64,
+    entries: Vec<WrittenEntry>,
+    comment: Vec<u8>,
+    page_size: Option<u64>,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(inner: W) -> ZipWriter<W> {
+        ZipWriter {
+            inner,
+            offset: 0,
+            entries: Vec::new(),
+            comment: Vec::new(),
+            page_size: None,
+        }
+    }
+
+    /// Aligns the data of stored entries like `zipalign -p` does, by adding an alignment record
+    /// (0xD935) padded with zeros to the extra field of the local header. Shared libraries are
+    /// aligned to `page_size`, everything else to 4 bytes.
+    pub fn align_stored_entries(&mut self, page_size: u64) -> io::Result<()> {
+        align::check_page_size(page_size)?;
+        self.page_size = Some(page_size);
+        Ok(())
+    }
+
+    /// Number of bytes written so far, which is the offset of the next local header.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn set_comment(&mut self, comment: &[u8]) {
+        self.comment = comment[..comment.len().min(0xFFFF)].to_vec();
+    }
+
+    pub fn add_stored(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.add_file(name, data, Compression::Store)
+    }
+
+    pub fn add_deflated(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.add_file(name, data, Compression::Deflate)
+    }
+
+    pub fn add_file(&mut self, name: &str, data: &[u8], compression: Compression) -> io::Result<()> {
+        let (compression_method, compressed) = match compression {
+            Compression::Store => (0, None),
+            Compression::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                (8, Some(encoder.finish()?))
+            }
+            Compression::Bzip2 => return Err(ZipError::UnsupportedCompression(12).into()),
+            Compression::LZMA => return Err(ZipError::UnsupportedCompression(14).into()),
+            Compression::Zstd => return Err(ZipError::UnsupportedCompression(93).into()),
+            Compression::Other(method) => return Err(ZipError::UnsupportedCompression(method).into()),
+        };
+        let compressed_data = compressed.as_deref().unwrap_or(data);
+
+        let entry = WrittenEntry {
+            version_producer: 20,
+            version_needed: if compression_method == 0 { 10 } else { 20 },
+            general_purpose_flags: if name.is_ascii() { 0 } else { 0x800 },
+            compression_method,
+            last_mod_time: 0,
+            last_mod_date: DEFAULT_DOS_DATE,
+            crc32: crc32fast::hash(data),
+            compressed_size: compressed_data.len() as u64,
+            uncompressed_size: data.len() as u64,
+            external_file_attributes: 0,
+            local_header_offset: self.offset,
+            file_name: name.as_bytes().to_vec(),
+            extra_field: Vec::new(),
+        };
+        self.write_local_file_header(&entry)?;
+        self.write_all(compressed_data)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Copies an entry from another archive without decompressing and recompressing it. Name,
+    /// timestamps, attributes and extra fields are preserved, a data descriptor is not.
+    pub fn copy_raw(&mut self, entry: &ZipEntry) -> io::Result<()> {
+        let header = &entry.header;
+        let entry_to_write = WrittenEntry {
+            version_producer: header.version_producer,
+            // methods like bzip2, LZMA and zstd need later versions than stored and deflated
+            version_needed: header.min_version_extractor.max(if header.compression_method == 0 { 10 } else { 20 }),
+            general_purpose_flags: header.general_purpose_flags & !0x08,
+            compression_method: header.compression_method,
+            last_mod_time: header.last_mod_time,
+            last_mod_date: header.last_mod_date,
+            crc32: header.crc32(),
+            compressed_size: header.compressed_size,
+            uncompressed_size: header.uncompressed_size,
+            external_file_attributes: header.external_file_attributes,
+            local_header_offset: self.offset,
+            file_name: header.file_name_bytes().to_vec(),
+            // zip64 sizes and alignment are written anew
+            extra_field: without_extra_fields(header.extra_field(), &[0x0001, align::ALIGNMENT_EXTRA_FIELD_ID]),
+        };
+        // fails for data beyond the end of the source before anything is written
+        let mut raw_content = entry.raw_content()?;
+        self.write_local_file_header(&entry_to_write)?;
+        let copied = io::copy(&mut raw_content, &mut self.inner)?;
+        self.offset += copied;
+        if copied != header.compressed_size {
+            return Err(ZipError::EntryOutOfBounds { file_name: entry.file_name() }.into());
+        }
+        self.entries.push(entry_to_write);
+        Ok(())
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    fn write_local_file_header(&mut self, entry: &WrittenEntry) -> io::Result<()> {
+        let zip64 = entry.compressed_size >= 0xFFFF_FFFF || entry.uncompressed_size >= 0xFFFF_FFFF;
+        let mut extra_field = entry.extra_field.clone();
+        if zip64 {
+            extra_field.extend_from_slice(&0x0001u16.to_le_bytes());
+            extra_field.extend_from_slice(&16u16.to_le_bytes());
+            extra_field.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+            extra_field.extend_from_slice(&entry.compressed_size.to_le_bytes());
+        }
+        if let Some(page_size) = self.page_size.filter(|_| entry.compression_method == 0) {
+            let file_name = String::from_utf8_lossy(&entry.file_name);
+            let alignment = align::required_alignment(&file_name, page_size);
+            // the record takes at least 6 bytes: header id, size and the alignment itself
+            let data_offset = self.offset + 30 + (entry.file_name.len() + extra_field.len()) as u64 + 6;
+            let padding = align::padding_for(data_offset, alignment);
+            extra_field.extend_from_slice(&align::ALIGNMENT_EXTRA_FIELD_ID.to_le_bytes());
+            extra_field.extend_from_slice(&(2 + padding as u16).to_le_bytes());
+            extra_field.extend_from_slice(&(alignment as u16).to_le_bytes());
+            extra_field.resize(extra_field.len() + padding as usize, 0);
+        }
+
+        let mut buf = Vec::with_capacity(30 + entry.file_name.len() + extra_field.len());
+        buf.extend_from_slice(&[0x50, 0x4b, 0x03, 0x04]);
+        buf.extend_from_slice(&(if zip64 { 45 } else { entry.version_needed }).to_le_bytes());
+        buf.extend_from_slice(&entry.general_purpose_flags.to_le_bytes());
+        buf.extend_from_slice(&entry.compression_method.to_le_bytes());
+        buf.extend_from_slice(&entry.last_mod_time.to_le_bytes());
+        buf.extend_from_slice(&entry.last_mod_date.to_le_bytes());
+        buf.extend_from_slice(&entry.crc32.to_le_bytes());
+        buf.extend_from_slice(&saturate_u32(entry.compressed_size, zip64).to_le_bytes());
+        buf.extend_from_slice(&saturate_u32(entry.uncompressed_size, zip64).to_le_bytes());
+        buf.extend_from_slice(&field_length(&entry.file_name, entry)?.to_le_bytes());
+        buf.extend_from_slice(&field_length(&extra_field, entry)?.to_le_bytes());
+        buf.extend_from_slice(&entry.file_name);
+        buf.extend_from_slice(&extra_field);
+        self.write_all(&buf)
+    }
+
+    /// Writes the central directory and the end of central directory record and returns the
+    /// underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let central_directory_offset = self.offset;
+        let mut buf = Vec::new();
+        for entry in &self.entries {
+            write_central_file_header(&mut buf, entry)?;
+        }
+        self.write_all(&buf)?;
+        let central_directory_size = buf.len() as u64;
+
+        let mut buf = Vec::new();
+        let entry_count = self.entries.len() as u64;
+        let zip64 = entry_count >= 0xFFFF
+            || central_directory_offset >= 0xFFFF_FFFF
+            || central_directory_size >= 0xFFFF_FFFF;
+        if zip64 {
+            let record_offset = self.offset;
+            buf.extend_from_slice(&[0x50, 0x4b, 0x06, 0x06]);
+            buf.extend_from_slice(&44u64.to_le_bytes());
+            buf.extend_from_slice(&45u16.to_le_bytes());
+            buf.extend_from_slice(&45u16.to_le_bytes());
+            buf.extend_from_slice(&0u32.to_le_bytes());
+            buf.extend_from_slice(&0u32.to_le_bytes());
+            buf.extend_from_slice(&entry_count.to_le_bytes());
+            buf.extend_from_slice(&entry_count.to_le_bytes());
+            buf.extend_from_slice(&central_directory_size.to_le_bytes());
+            buf.extend_from_slice(&central_directory_offset.to_le_bytes());
+
+            buf.extend_from_slice(&[0x50, 0x4b, 0x06, 0x07]);
+            buf.extend_from_slice(&0u32.to_le_bytes());
+            buf.extend_from_slice(&record_offset.to_le_bytes());
+            buf.extend_from_slice(&1u32.to_le_bytes());
+        }
+
+        let entry_count = if zip64 { 0xFFFF } else { entry_count as u16 };
+        buf.extend_from_slice(&[0x50, 0x4b, 0x05, 0x06]);
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&entry_count.to_le_bytes());
+        buf.extend_from_slice(&entry_count.to_le_bytes());
+        buf.extend_from_slice(&saturate_u32(central_directory_size, zip64).to_le_bytes());
+        buf.extend_from_slice(&saturate_u32(central_directory_offset, zip64).to_le_bytes());
+        buf.extend_from_slice(&(self.comment.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&self.comment);
+        self.write_all(&buf)?;
+
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+fn saturate_u32(value: u64, saturate: bool) -> u32 {
+    if saturate || value >= 0xFFFF_FFFF {
+        0xFFFF_FFFF
+    } else {
+        value as u32
+    }
+}
+
+/// Length of a file name or extra field, which headers store in 16 bits.
+fn field_length(field: &[u8], entry: &WrittenEntry) -> io::Result<u16> {
+    u16::try_from(field.len())
+        .map_err(|_| ZipError::FieldTooLong { file_name: String::from_utf8_lossy(&entry.file_name).to_string() }.into())
+}
+
+fn write_central_file_header(buf: &mut Vec<u8>, entry: &WrittenEntry) -> io::Result<()> {
+    // the ZIP64 extra field of central headers only holds the values that do not fit
+    let mut zip64_extra = Vec::new();
+    for value in [entry.uncompressed_size, entry.compressed_size, entry.local_header_offset] {
+        if value >= 0xFFFF_FFFF {
+            zip64_extra.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    let mut extra_field = entry.extra_field.clone();
+    let mut version_needed = entry.version_needed;
+    if !zip64_extra.is_empty() {
+        extra_field.extend_from_slice(&0x0001u16.to_le_bytes());
+        extra_field.extend_from_slice(&(zip64_extra.len() as u16).to_le_bytes());
+        extra_field.extend_from_slice(&zip64_extra);
+        version_needed = 45;
+    }
+
+    buf.extend_from_slice(&[0x50, 0x4b, 0x01, 0x02]);
+    buf.extend_from_slice(&entry.version_producer.max(version_needed).to_le_bytes());
+    buf.extend_from_slice(&version_needed.to_le_bytes());
+    buf.extend_from_slice(&entry.general_purpose_flags.to_le_bytes());
+    buf.extend_from_slice(&entry.compression_method.to_le_bytes());
+    buf.extend_from_slice(&entry.last_mod_time.to_le_bytes());
+    buf.extend_from_slice(&entry.last_mod_date.to_le_bytes());
+    buf.extend_from_slice(&entry.crc32.to_le_bytes());
+    buf.extend_from_slice(&saturate_u32(entry.compressed_size, false).to_le_bytes());
+    buf.extend_from_slice(&saturate_u32(entry.uncompressed_size, false).to_le_bytes());
+    buf.extend_from_slice(&field_length(&entry.file_name, entry)?.to_le_bytes());
+    buf.extend_from_slice(&field_length(&extra_field, entry)?.to_le_bytes());
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    buf.extend_from_slice(&entry.external_file_attributes.to_le_bytes());
+    buf.extend_from_slice(&saturate_u32(entry.local_header_offset, false).to_le_bytes());
+    buf.extend_from_slice(&entry.file_name);
+    buf.extend_from_slice(&extra_field);
+    Ok(())
+}
+
+/// Removes all records with one of the given header ids from an extra field.
+fn without_extra_fields(extra_field: &[u8], header_ids: &[u16]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(extra_field.len());
+    for field in ExtraFields::new(extra_field).filter(|f| !header_ids.contains(&f.header_id)) {
+        result.extend_from_slice(&field.header_id.to_le_bytes());
+        result.extend_from_slice(&(field.data.len() as u16).to_le_bytes());
+        result.extend_from_slice(field.data);
+    }
+    result
+}
+
+#[test]
+fn test_zip_writer() {
+    use std::io::Read;
+    use crate::zip::archive::ZipArchive;
+
+    let mut writer = ZipWriter::new(Vec::new());
+    writer.add_stored("stored.txt", b"stored content").unwrap();
+    writer.add_deflated("deflated.txt", &[b'a'; 1000]).unwrap();
+    writer.set_comment(b"comment");
+    let mut first = writer.finish().unwrap();
+    // the version needed to extract of the first entry as if it was compressed with LZMA
+    let central_directory_offset = ZipArchive::from_bytes(first.clone()).unwrap().central_directory_offset() as usize;
+    first[central_directory_offset + 6..central_directory_offset + 8].copy_from_slice(&63u16.to_le_bytes());
+
+    let archive = ZipArchive::from_bytes(first).unwrap();
+    let mut writer = ZipWriter::new(Vec::new());
+    for entry in archive.files().collect::<Vec<_>>().iter().rev() {
+        writer.copy_raw(entry).unwrap();
+    }
+    let second = writer.finish().unwrap();
+
+    let archive = ZipArchive::from_bytes(second).unwrap();
+    assert_eq!(archive.audit().unwrap(), vec![]);
+    let names: Vec<String> = archive.files().map(|e| e.file_name()).collect();
+    assert_eq!(names, vec!["deflated.txt", "stored.txt"]);
+    assert_eq!(archive.by_name("stored.txt").unwrap().unwrap().header.min_version_extractor, 63);
+    assert_eq!(archive.by_name("deflated.txt").unwrap().unwrap().header.min_version_extractor, 20);
+    let entry = archive.by_name("deflated.txt").unwrap().unwrap();
+    assert_eq!(entry.header.compression_method, 8);
+    let mut content = Vec::new();
+    entry.content().unwrap().read_to_end(&mut content).unwrap();
+    assert_eq!(content, vec![b'a'; 1000]);
+}
+
+#[test]
+fn test_align_stored_entries() {
+    use crate::zip::archive::ZipArchive;
+
+    let mut writer = ZipWriter::new(Vec::new());
+    writer.add_stored("a", b"a").unwrap();
+    writer.add_stored("lib/arm64-v8a/libfoo.so", b"ELF").unwrap();
+    let archive = ZipArchive::from_bytes(writer.finish().unwrap()).unwrap();
+    assert_eq!(archive.check_alignment(4096).unwrap().len(), 2);
+
+    let mut writer = ZipWriter::new(Vec::new());
+    writer.align_stored_entries(16384).unwrap();
+    writer.add_stored("a", b"a").unwrap();
+    writer.add_deflated("b", b"b").unwrap();
+    writer.add_stored("lib/arm64-v8a/libfoo.so", b"ELF").unwrap();
+    let aligned = writer.finish().unwrap();
+    // the local header is padded with an alignment record holding the alignment
+    let name = b"lib/arm64-v8a/libfoo.so";
+    let record = aligned.windows(name.len()).position(|w| w == name).unwrap() + name.len();
+    assert_eq!(&aligned[record..record + 2], &[0x35, 0xD9]);
+    assert_eq!(&aligned[record + 4..record + 6], &16384u16.to_le_bytes());
+    let archive = ZipArchive::from_bytes(aligned).unwrap();
+    assert_eq!(archive.check_alignment(16384).unwrap(), vec![]);
+    assert_eq!(archive.by_name("lib/arm64-v8a/libfoo.so").unwrap().unwrap().data_offset().unwrap(), 16384);
+    assert_eq!(archive.audit().unwrap(), vec![]);
+
+    // copying replaces the old alignment record instead of adding another one
+    let mut writer = ZipWriter::new(Vec::new());
+    writer.align_stored_entries(4096).unwrap();
+    for entry in archive.files() {
+        writer.copy_raw(&entry).unwrap();
+    }
+    let archive = ZipArchive::from_bytes(writer.finish().unwrap()).unwrap();
+    assert_eq!(archive.check_alignment(4096).unwrap(), vec![]);
+    assert_eq!(archive.by_name("lib/arm64-v8a/libfoo.so").unwrap().unwrap().data_offset().unwrap(), 4096);
+
+    assert!(ZipWriter::new(Vec::new()).align_stored_entries(0).is_err());
+    assert!(archive.check_alignment(0).is_err());
+    assert!(archive.check_alignment(4000).is_err());
+}
+
+#[test]
+fn test_field_too_long() {
+    use crate::zip::archive::ZipArchive;
+
+    let mut writer = ZipWriter::new(Vec::new());
+    let error = writer.add_stored(&"a".repeat(0x10000), b"a").unwrap_err();
+    assert!(error.to_string().contains("longer than 65535 bytes"));
+    assert!(writer.inner.is_empty());
+
+    let mut writer = ZipWriter::new(Vec::new());
+    writer.add_stored("a", b"hi").unwrap();
+    let mut data = writer.finish().unwrap();
+    // the compressed size in the central directory now exceeds the archive
+    let central_directory_offset = data.len() - 22 - 47;
+    data[central_directory_offset + 20..central_directory_offset + 24].copy_from_slice(&1000u32.to_le_bytes());
+    let archive = ZipArchive::from_bytes(data).unwrap();
+    let mut writer = ZipWriter::new(Vec::new());
+    assert!(writer.copy_raw(&archive.by_name("a").unwrap().unwrap()).is_err());
+    assert!(writer.inner.is_empty());
+}
@@ -0,0 +1,236 @@
+#!/usr/bin/env python3
+"""Generates the signing test fixtures, needs the `cryptography` package and `jarsigner`.
+
+Keys and certificates are created once and reused on later runs. The APK Signing Blocks are
+laid out by hand following the APK Signature Scheme v2 and v3 documentation, independent of
+the crate's own signer. JAR signatures (v1) are made with jarsigner.
+"""
+import datetime
+import hashlib
+import os
+import struct
+import subprocess
+import tempfile
+import zipfile
+
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import dsa, ec, padding, rsa
+from cryptography.hazmat.primitives.serialization import pkcs12
+from cryptography.x509.oid import NameOID
+
+DIR = os.path.dirname(os.path.abspath(__file__))
+
+V2_BLOCK_ID = 0x7109871A
+V3_BLOCK_ID = 0xF05368C0
+V31_BLOCK_ID = 0x1B93AD61
+STRIPPING_PROTECTION_ATTRIBUTE_ID = 0xBEEFF00D
+PROOF_OF_ROTATION_ATTRIBUTE_ID = 0x3BA06F8C
+ROTATION_MIN_SDK_VERSION_ATTRIBUTE_ID = 0x559F8B02
+
+RSA_PSS_SHA256 = 0x0101
+RSA_PKCS1_SHA256 = 0x0103
+RSA_PKCS1_SHA512 = 0x0104
+ECDSA_SHA256 = 0x0201
+DSA_SHA256 = 0x0301
+
+
+def u32(value):
+    return struct.pack('<I', value)
+
+
+def lp(data):
+    return u32(len(data)) + data
+
+
+def seq(items):
+    return lp(b''.join(lp(item) for item in items))
+
+
+def path(name):
+    return os.path.join(DIR, name)
+
+
+def write(name, data):
+    with open(path(name), 'wb') as f:
+        f.write(data)
+
+
+def name(*attributes):
+    return x509.Name([x509.NameAttribute(oid, value) for oid, value in attributes])
+
+
+def key_and_certificate(key_name, generate, subject):
+    """Loads `<key_name>.pk8` and `<key_name>.der`, creating them first if necessary."""
+    if not os.path.exists(path(key_name + '.pk8')):
+        key = generate()
+        start = datetime.datetime(2020, 1, 1)
+        certificate = (x509.CertificateBuilder()
+                       .subject_name(subject)
+                       .issuer_name(subject)
+                       .public_key(key.public_key())
+                       .serial_number(0x1234567)
+                       .not_valid_before(start)
+                       .not_valid_after(start + datetime.timedelta(days=30 * 365))
+                       .add_extension(x509.BasicConstraints(ca=False, path_length=None), critical=True)
+                       .add_extension(x509.SubjectKeyIdentifier.from_public_key(key.public_key()), critical=False)
+                       .sign(key, hashes.SHA256()))
+        write(key_name + '.pk8', key.private_bytes(
+            serialization.Encoding.DER, serialization.PrivateFormat.PKCS8, serialization.NoEncryption()))
+        write(key_name + '.der', certificate.public_bytes(serialization.Encoding.DER))
+    with open(path(key_name + '.pk8'), 'rb') as f:
+        key = serialization.load_der_private_key(f.read(), None)
+    with open(path(key_name + '.der'), 'rb') as f:
+        certificate = f.read()
+    return key, certificate
+
+
+def sign(key, algorithm, data):
+    if algorithm == RSA_PSS_SHA256:
+        return key.sign(data, padding.PSS(padding.MGF1(hashes.SHA256()), 32), hashes.SHA256())
+    if algorithm in (RSA_PKCS1_SHA256, RSA_PKCS1_SHA512):
+        return key.sign(data, padding.PKCS1v15(), hashes.SHA256() if algorithm == RSA_PKCS1_SHA256 else hashes.SHA512())
+    if algorithm == ECDSA_SHA256:
+        return key.sign(data, ec.ECDSA(hashes.SHA256()))
+    if algorithm == DSA_SHA256:
+        return key.sign(data, hashes.SHA256())
+    raise ValueError(algorithm)
+
+
+def spki(key):
+    return key.public_key().public_bytes(serialization.Encoding.DER, serialization.PublicFormat.SubjectPublicKeyInfo)
+
+
+def sections(apk):
+    """The contents of the entries, the central directory and its end record."""
+    eocd = apk.rindex(b'PK\x05\x06')
+    cd_offset = struct.unpack('<I', apk[eocd + 16:eocd + 20])[0]
+    return apk[:cd_offset], apk[cd_offset:eocd], apk[eocd:]
+
+
+def content_digest(apk):
+    """Chunked SHA-256 digest over the sections of an APK without signing block."""
+    chunks = []
+    for section in sections(apk):
+        for i in range(0, len(section), 1 << 20):
+            chunk = section[i:i + (1 << 20)]
+            chunks.append(hashlib.sha256(b'\xa5' + u32(len(chunk)) + chunk).digest())
+    return hashlib.sha256(b'\x5a' + u32(len(chunks)) + b''.join(chunks)).digest()
+
+
+def signer(key, certificate, algorithm, digest, attributes=(), sdk_versions=None, digest_algorithm=None):
+    """A v2 signer, or a v3 signer with `sdk_versions`. The SDK versions of v3 follow the
+    certificates in the signed data, ahead of the additional attributes."""
+    signed_data = seq([u32(digest_algorithm or algorithm) + lp(digest)]) + seq([certificate])
+    sdk = struct.pack('<II', *sdk_versions) if sdk_versions else b''
+    signed_data += sdk + seq([u32(attribute_id) + value for attribute_id, value in attributes])
+    signatures = seq([u32(algorithm) + lp(sign(key, algorithm, signed_data))])
+    return lp(signed_data) + sdk + signatures + lp(spki(key))
+
+
+def with_signing_block(apk, pairs):
+    """Inserts an APK Signing Block with the ID-value pairs in front of the central directory."""
+    entries, central_directory, eocd = sections(apk)
+    body = b''.join(struct.pack('<QI', len(value) + 4, pair_id) + value for pair_id, value in pairs)
+    size = struct.pack('<Q', len(body) + 24)
+    block = size + body + size + b'APK Sig Block 42'
+    eocd = eocd[:16] + u32(len(entries) + len(block)) + eocd[20:]
+    return entries + block + central_directory + eocd
+
+
+def sign_v2_v3(apk, key, certificate, algorithm, digest_algorithm=None):
+    digest = content_digest(apk)
+    v2 = signer(key, certificate, algorithm, digest, [(STRIPPING_PROTECTION_ATTRIBUTE_ID, u32(3))],
+                digest_algorithm=digest_algorithm)
+    v3 = signer(key, certificate, algorithm, digest, sdk_versions=(28, 0x7FFFFFFF), digest_algorithm=digest_algorithm)
+    return with_signing_block(apk, [(V2_BLOCK_ID, lp(lp(v2))), (V3_BLOCK_ID, lp(lp(v3)))])
+
+
+def lineage(nodes, tamper=False):
+    """The proof-of-rotation over `(key, certificate, algorithm)` nodes, oldest first. Every
+    certificate is signed by the key of the previous one, capabilities are apksigner's default."""
+    encoded = u32(1)
+    parent = None
+    for key, certificate, algorithm in nodes:
+        signed_data = lp(certificate) + u32(parent[2] if parent else 0)
+        signature = sign(parent[0], parent[2], signed_data) if parent else b''
+        if tamper and parent:
+            signature = signature[:-1] + bytes([signature[-1] ^ 1])
+        encoded += lp(lp(signed_data) + u32(0x17) + u32(algorithm) + lp(signature))
+        parent = (key, certificate, algorithm)
+    return encoded
+
+
+def sign_rotated(apk, old, new, v31=True, v31_min_sdk_version=33, tamper_lineage=False):
+    """Signs v2 and v3 with the old key and v3.1 with the new one from Android 13 on, like
+    apksigner does with `--rotation-min-sdk-version 33`."""
+    digest = content_digest(apk)
+    v2 = signer(old[0], old[1], old[2], digest, [(STRIPPING_PROTECTION_ATTRIBUTE_ID, u32(3))])
+    v3 = signer(old[0], old[1], old[2], digest, [(ROTATION_MIN_SDK_VERSION_ATTRIBUTE_ID, u32(33))], (28, 32))
+    pairs = [(V2_BLOCK_ID, lp(lp(v2))), (V3_BLOCK_ID, lp(lp(v3)))]
+    if v31:
+        proof = lineage([old, new], tamper_lineage)
+        v31_signer = signer(new[0], new[1], new[2], digest, [(PROOF_OF_ROTATION_ATTRIBUTE_ID, proof)],
+                            (v31_min_sdk_version, 0x7FFFFFFF))
+        pairs.append((V31_BLOCK_ID, lp(lp(v31_signer))))
+    return with_signing_block(apk, pairs)
+
+
+def unsigned_apk():
+    with tempfile.TemporaryFile() as f:
+        with zipfile.ZipFile(f, 'w') as z:
+            for entry_name, data, method in [
+                ('AndroidManifest.xml', b'\x03\x00\x08\x00' + bytes(64), zipfile.ZIP_DEFLATED),
+                ('classes.dex', b'dex\n035\x00' + bytes(range(256)) * 4, zipfile.ZIP_DEFLATED),
+                ('res/raw/hello.txt', b'Hello, world!\n', zipfile.ZIP_STORED),
+                ('lib/arm64-v8a/libhello.so', b'\x7fELF' + bytes(60), zipfile.ZIP_STORED),
+            ]:
+                z.writestr(zipfile.ZipInfo(entry_name, (2020, 1, 1, 0, 0, 0)), data, method)
+        f.seek(0)
+        return f.read()
+
+
+def jarsign(src, dst, key, certificate):
+    with tempfile.TemporaryDirectory() as tmp:
+        keystore = os.path.join(tmp, 'keystore.p12')
+        with open(keystore, 'wb') as f:
+            f.write(pkcs12.serialize_key_and_certificates(
+                b'key', key, x509.load_der_x509_certificate(certificate), None,
+                serialization.BestAvailableEncryption(b'password')))
+        subprocess.run(['jarsigner', '-keystore', keystore, '-storetype', 'pkcs12', '-storepass', 'password',
+                        '-digestalg', 'SHA-256', '-sigalg', 'SHA256withRSA', '-sigfile', 'CERT',
+                        '-signedjar', path(dst), path(src), 'key'], check=True, stdout=subprocess.DEVNULL)
+
+
+def main():
+    rsa_key, rsa_certificate = key_and_certificate(
+        'rsa', lambda: rsa.generate_private_key(65537, 2048),
+        name((NameOID.COUNTRY_NAME, 'US'), (NameOID.ORGANIZATION_NAME, 'Android'), (NameOID.COMMON_NAME, 'Android Debug')))
+    ec_key, ec_certificate = key_and_certificate(
+        'ec', lambda: ec.generate_private_key(ec.SECP256R1()),
+        name((NameOID.ORGANIZATION_NAME, 'Example'), (NameOID.COMMON_NAME, 'Example EC')))
+    dsa_key, dsa_certificate = key_and_certificate(
+        'dsa', lambda: dsa.generate_private_key(2048),
+        name((NameOID.ORGANIZATION_NAME, 'Example'), (NameOID.COMMON_NAME, 'Example DSA')))
+
+    apk = unsigned_apk()
+    write('unsigned.apk', apk)
+    write('v2v3-rsa.apk', sign_v2_v3(apk, rsa_key, rsa_certificate, RSA_PKCS1_SHA256))
+    write('v2v3-rsa-pss.apk', sign_v2_v3(apk, rsa_key, rsa_certificate, RSA_PSS_SHA256))
+    write('v2v3-ec.apk', sign_v2_v3(apk, ec_key, ec_certificate, ECDSA_SHA256))
+    write('v2v3-dsa.apk', sign_v2_v3(apk, dsa_key, dsa_certificate, DSA_SHA256))
+    # the digest is listed for another algorithm than the signature is made with
+    write('v2v3-algorithm-mismatch.apk', sign_v2_v3(apk, rsa_key, rsa_certificate, RSA_PKCS1_SHA256, RSA_PKCS1_SHA512))
+    jarsign('unsigned.apk', 'v1-rsa.apk', rsa_key, rsa_certificate)
+
+    # rotation from the RSA to the EC key
+    old = (rsa_key, rsa_certificate, RSA_PKCS1_SHA256)
+    new = (ec_key, ec_certificate, ECDSA_SHA256)
+    write('v31-rotated.apk', sign_rotated(apk, old, new))
+    write('v31-stripped.apk', sign_rotated(apk, old, new, v31=False))
+    write('v31-min-sdk-mismatch.apk', sign_rotated(apk, old, new, v31_min_sdk_version=34))
+    write('v31-invalid-lineage.apk', sign_rotated(apk, old, new, tamper_lineage=True))
+
+
+if __name__ == '__main__':
+    main()